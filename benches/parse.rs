@@ -0,0 +1,50 @@
+//! Benchmark of the parser itself
+
+#![feature(test)]
+
+extern crate trimmer;
+extern crate test;
+
+use test::Bencher;
+
+
+const CHUNK: &str = r###"
+<ul class="{{ cls }}">
+## for item in items
+    ## skip if item.hidden
+    <li id="item-{{ item.id }}">
+        ## if item.kind == "dir" and not item.empty
+        <a href="{{ path }}/{{ item.name }}/">{{ item.name }}/</a>
+        ## elif item.size > 1024 * 1024
+        <a href="{{ path }}/{{ item.name }}">{{ item.name }}</a> (large)
+        ## else
+        <a href="{{ path }}/{{ item.name }}">{{ item.name }}</a>
+        ## endif
+        {# item attributes #}
+        ## let attrs = {"size": item.size, "tags": [item.a, item.b]}
+        <span>{{ attrs["size"] }} {{ attrs.tags[0] }}</span>
+    </li>
+## endfor
+</ul>
+"###;
+
+fn large_template() -> String {
+    (0..200).map(|_| CHUNK).collect()
+}
+
+#[bench]
+fn new_parser(b: &mut Bencher) {
+    b.iter(|| {
+        test::black_box(trimmer::Parser::new());
+    });
+}
+
+#[bench]
+fn parse_large(b: &mut Bencher) {
+    let parser = trimmer::Parser::new();
+    let data = large_template();
+    b.bytes = data.len() as u64;
+    b.iter(|| {
+        parser.parse(&data).unwrap()
+    });
+}
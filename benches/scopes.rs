@@ -0,0 +1,80 @@
+//! Benchmark of block statements that create scopes
+//!
+//! Every iteration of a loop and every branch of a condition creates a new
+//! scope for local variables, so this is where compiling templates to
+//! bytecode matters most. Run with `cargo bench --bench scopes`, and
+//! `cargo test --benches` to check the number of allocations.
+//!
+//! When bytecode was introduced, a render took 4657 allocations and about
+//! 2.1 ms with the tree-walking renderer, versus 1341 allocations and
+//! about 0.8 ms with bytecode.
+
+#![feature(test)]
+
+extern crate trimmer;
+extern crate test;
+#[macro_use] extern crate serde_json;
+
+use trimmer::{Template, Context};
+use serde_json::Value;
+use test::Bencher;
+
+mod counter;
+
+#[global_allocator]
+static ALLOC: counter::Counter = counter::Counter;
+
+
+fn template() -> Template {
+    trimmer::Parser::new().parse(r###"## syntax: indent
+## for group in groups
+    ## let title = group.name
+    <h2>{{ title }}</h2>
+    ## for item in group.items
+        ## skip if item.hidden
+        ## if item.size > 1000
+            ## let size = item.size / 1000
+            <p>{{ title }}/{{ item.name }}: {{ size }}k</p>
+        ## else
+            <p>{{ title }}/{{ item.name }}: {{ item.size }}</p>
+        ## endif
+    ## endfor
+## endfor
+    "###).unwrap()
+}
+
+fn data() -> Value {
+    let items = (0..100).map(|i| json!({
+        "name": format!("item{}", i),
+        "size": i * 37,
+        "hidden": i % 10 == 0,
+    })).collect::<Vec<_>>();
+    Value::Array((0..20).map(|i| json!({
+        "name": format!("group{}", i),
+        "items": items.clone(),
+    })).collect())
+}
+
+#[bench]
+fn render(b: &mut Bencher) {
+    let tpl = template();
+    let groups = data();
+    let mut ctx = Context::new();
+    ctx.set("groups", &groups);
+    b.iter(|| {
+        tpl.render(&ctx).unwrap();
+    });
+}
+
+#[test]
+fn allocations() {
+    let tpl = template();
+    let groups = data();
+    let mut ctx = Context::new();
+    ctx.set("groups", &groups);
+    let num = counter::allocations(|| {
+        tpl.render(&ctx).unwrap();
+    });
+    println!("Allocations per render: {}", num);
+    assert!(num < 3000);
+}
@@ -10,6 +10,11 @@ use trimmer::{Template, Context};
 use serde_json::Value;
 use test::Bencher;
 
+mod counter;
+
+#[global_allocator]
+static ALLOC: counter::Counter = counter::Counter;
+
 
 fn template() -> Template {
     trimmer::Parser::new().parse(r###"## syntax: indent
@@ -47,3 +52,28 @@ fn render(b: &mut Bencher) {
         tpl.render(&ctx).unwrap();
     });
 }
+
+#[test]
+fn allocations() {
+    let tpl = template();
+    let row = json!({
+        "a": 1,
+        "b": 2,
+        "c": 3,
+        "d": 4,
+        "e": 5,
+        "f": 6,
+        "g": 7,
+        "h": 8,
+        "i": 9,
+        "j": 10,
+    });
+    let table = Value::Array((0..1000).map(|_| row.clone()).collect());
+    let mut ctx = Context::new();
+    ctx.set("table", &table);
+    let num = counter::allocations(|| {
+        tpl.render(&ctx).unwrap();
+    });
+    println!("Allocations per render: {}", num);
+    assert!(num < 2000);
+}
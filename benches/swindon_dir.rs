@@ -9,6 +9,11 @@ use trimmer::{Template, Context};
 use serde_json::Value;
 use test::Bencher;
 
+mod counter;
+
+#[global_allocator]
+static ALLOC: counter::Counter = counter::Counter;
+
 
 fn template() -> Template {
     trimmer::Parser::new().parse(r###"## syntax: indent
@@ -83,3 +88,18 @@ fn render(b: &mut Bencher) {
         tpl.render(&ctx).unwrap();
     });
 }
+
+#[test]
+fn allocations() {
+    let tpl = template();
+    let path = "/my/path";
+    let data = data();
+    let mut ctx = Context::new();
+    ctx.set("path", &path);
+    ctx.set("entries", &data);
+    let num = counter::allocations(|| {
+        tpl.render(&ctx).unwrap();
+    });
+    println!("Allocations per render: {}", num);
+    assert!(num < 20);
+}
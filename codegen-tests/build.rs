@@ -0,0 +1,222 @@
+//! Generates rust code for every template found in the render tests
+//!
+//! Tests are plain rust files, so every string literal in them is treated
+//! as a template. Literals that aren't valid templates are skipped.
+//!
+//! Generated functions are looked up by the binary artifact of the parsed
+//! template (see `Template::to_bytes`), so the same source parsed with
+//! different options gets its own function.
+extern crate trimmer;
+
+use std::ascii;
+use std::collections::HashSet;
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use trimmer::{Parser, Options};
+
+
+fn curly() -> Options {
+    Options::new().balance_curly().clone()
+}
+
+fn square() -> Options {
+    Options::new().balance_square().clone()
+}
+
+fn round() -> Options {
+    Options::new().balance_round().clone()
+}
+
+fn curly_round() -> Options {
+    Options::new().balance_curly().balance_round().clone()
+}
+
+fn all_brackets() -> Options {
+    Options::new().balance_curly().balance_square().balance_round().clone()
+}
+
+fn new_line_at_eof() -> Options {
+    Options::new().new_line_at_eof(true).clone()
+}
+
+fn custom_delimiters() -> Options {
+    Options::new().expression_delimiters("${", "}")
+        .statement_prefix("#%")
+        .comment_delimiters("/*", "*/")
+        .clone()
+}
+
+fn statement_prefix() -> Options {
+    Options::new().statement_prefix("%%").clone()
+}
+
+/// Test module of `trimmer` (see `src/lib.rs`), prefixes that the tests
+/// add to the templates and options they parse templates with, besides
+/// the default ones
+type Test = (&'static str, &'static [&'static str],
+             &'static [fn() -> Options]);
+
+const TESTS: &[Test] = &[
+    ("boolean", &[], &[]),
+    ("brackets", &[], &[curly, square, round, curly_round, all_brackets]),
+    ("delimiters", &[], &[custom_delimiters, statement_prefix]),
+    ("filter", &[], &[]),
+    ("html", &["## filter default: builtin.html_auto\n"], &[]),
+    ("indent", &["## syntax: indent\n"], &[]),
+    ("json", &[], &[]),
+    ("math", &[], &[]),
+    ("newline", &[], &[new_line_at_eof]),
+    ("oneline", &["## syntax: oneline\n"], &[]),
+    ("render", &[], &[]),
+    ("safe", &[], &[]),
+    ("validate", &[], &[]),
+    ("vars", &[], &[]),
+    ("whitespace", &[], &[]),
+];
+
+fn unescape(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut result = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('r') => result.push('\r'),
+                Some('t') => result.push('\t'),
+                Some('0') => result.push('\0'),
+                Some('x') => {
+                    let hex = chars.by_ref().take(2).collect::<String>();
+                    result.push(u8::from_str_radix(&hex, 16).unwrap() as char);
+                }
+                Some('u') => {
+                    let hex = chars.by_ref().skip(1).take_while(|&c| c != '}')
+                        .collect::<String>();
+                    let code = u32::from_str_radix(&hex, 16).unwrap();
+                    result.push(std::char::from_u32(code).unwrap());
+                }
+                Some('\n') => {
+                    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                        chars.next();
+                    }
+                }
+                Some(c) => result.push(c),
+                None => break,
+            },
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn raw(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let hashes = chars.by_ref().take_while(|&c| c != '"').count();
+    let end = format!("\"{}", "#".repeat(hashes));
+    let mut result = String::new();
+    for c in chars {
+        result.push(c);
+        if result.ends_with(&end) {
+            result.truncate(result.len() - end.len());
+            break;
+        }
+    }
+    result
+}
+
+/// Returns all string literals of the rust source
+fn literals(source: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut prev = ' ';
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().take_while(|&c| c != '\n').count();
+            }
+            '"' => result.push(unescape(&mut chars)),
+            'r' if !prev.is_alphanumeric() &&
+                matches!(chars.peek(), Some(&'#') | Some(&'"')) =>
+            {
+                result.push(raw(&mut chars));
+            }
+            // char literal, but not a lifetime
+            '\'' => {
+                let mut ahead = chars.clone();
+                match (ahead.next(), ahead.next()) {
+                    (Some('\\'), _) => {
+                        chars.by_ref().skip(1).take_while(|&c| c != '\'')
+                            .count();
+                    }
+                    (Some(_), Some('\'')) => {
+                        chars.next();
+                        chars.next();
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    result
+}
+
+fn byte_string(data: &[u8]) -> String {
+    let mut result = String::from("b\"");
+    for &b in data {
+        result.extend(ascii::escape_default(b).map(|c| c as char));
+    }
+    result.push('"');
+    result
+}
+
+fn main() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../src/tests");
+    let parser = Parser::new();
+    let mut seen = HashSet::new();
+    let mut code = String::new();
+    let mut table = String::new();
+    for &(module, prefixes, options) in TESTS {
+        let path = dir.join(format!("{}.rs", module));
+        println!("cargo:rerun-if-changed={}", path.display());
+        let mut source = String::new();
+        File::open(&path).and_then(|mut f| f.read_to_string(&mut source))
+            .unwrap();
+        let options = Some(Options::new()).into_iter()
+            .chain(options.iter().map(|f| f()))
+            .collect::<Vec<_>>();
+        for literal in literals(&source) {
+            for prefix in prefixes.iter().chain(Some(&"")) {
+                let template = format!("{}{}", prefix, literal);
+                for opt in &options {
+                    let parsed = parser.parse_with_options(opt, &template);
+                    let artifact = match parsed {
+                        Ok(tpl) => tpl.to_bytes(),
+                        Err(_) => continue,
+                    };
+                    if !seen.insert(artifact.clone()) {
+                        continue;
+                    }
+                    let name = format!("template{}", seen.len());
+                    let func = parser.codegen_with_options(opt,
+                        &name, &template);
+                    if let Ok(func) = func {
+                        code.push_str(&func);
+                        table.push_str(&format!("    ({}, {}),\n",
+                            byte_string(&artifact), name));
+                    }
+                }
+            }
+        }
+    }
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("generated.rs");
+    File::create(out).and_then(|mut f| {
+        f.write_all(code.as_bytes())?;
+        write!(f, "pub const TEMPLATES: &[(&[u8], \
+                   fn(&::trimmer::Context) \
+                   -> Result<String, ::trimmer::RenderError>)] = &[\n\
+                   {}];\n", table)
+    }).unwrap();
+}
@@ -0,0 +1,136 @@
+//! Runs render tests of trimmer through the rust code generator
+//!
+//! Test modules are compiled against the `Parser` and `Template` defined
+//! here. Templates that are string literals in the tests are rendered both
+//! by the interpreter and by the code generated in `build.rs`, and the
+//! results must be the same. Templates parsed with options are generated
+//! too, if the options are listed in `build.rs`.
+#![cfg(test)]
+#[macro_use] extern crate difference;
+#[cfg(feature="json")] extern crate serde_json;
+extern crate trimmer;
+
+use std::ops::Deref;
+
+pub use trimmer::{Context, Variable, Safe, Markup, RenderError, ParseError};
+pub use trimmer::Options;
+
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/generated.rs"));
+}
+
+mod grammar {
+    pub use Parser;
+}
+
+mod render {
+    /// Tests only print the extracted template for debugging
+    pub fn extract(tpl: ::Template) -> ::Template {
+        tpl
+    }
+}
+
+mod schema {
+    pub use trimmer::Type;
+}
+
+// shared with trimmer tests, which build with older compilers
+#[allow(clippy::redundant_field_names)]
+#[path="../../src/tests/diff.rs"] mod diff;
+
+mod tests {
+    pub use diff::assert_eq;
+}
+
+#[path="../../src/tests/boolean.rs"] mod boolean;
+#[path="../../src/tests/brackets.rs"] mod brackets;
+#[path="../../src/tests/delimiters.rs"] mod delimiters;
+#[path="../../src/tests/filter.rs"] mod filter;
+#[path="../../src/tests/html.rs"] mod html;
+#[path="../../src/tests/indent.rs"] mod indent;
+#[allow(clippy::legacy_numeric_constants)]
+#[path="../../src/tests/json.rs"] mod json;
+#[path="../../src/tests/math.rs"] mod math;
+#[path="../../src/tests/newline.rs"] mod newline;
+#[path="../../src/tests/oneline.rs"] mod oneline;
+#[path="../../src/tests/render.rs"] mod render_tests;
+#[path="../../src/tests/safe.rs"] mod safe;
+#[path="../../src/tests/validate.rs"] mod validate;
+#[path="../../src/tests/vars.rs"] mod vars;
+#[path="../../src/tests/whitespace.rs"] mod whitespace;
+
+
+type Generated = fn(&Context) -> Result<String, RenderError>;
+
+pub struct Parser(trimmer::Parser);
+
+#[derive(Debug)]
+pub struct Template {
+    source: String,
+    template: trimmer::Template,
+    generated: Option<Generated>,
+}
+
+impl Parser {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Parser {
+        Parser(trimmer::Parser::new())
+    }
+    pub fn parse(&self, data: &str) -> Result<Template, ParseError> {
+        self.parse_with_options(&Options::new(), data)
+    }
+    pub fn parse_with_options(&self, options: &Options, data: &str)
+        -> Result<Template, ParseError>
+    {
+        let template = self.0.parse_with_options(options, data)?;
+        // templates built at runtime (e.g. with `format!`) aren't known to
+        // `build.rs` unless listed as a prefix in `TESTS`
+        let artifact = template.to_bytes();
+        let generated = generated::TEMPLATES.iter()
+            .find(|&&(bytes, _)| bytes == &artifact[..])
+            .map(|&(_, func)| func);
+        Ok(Template { source: data.to_string(), template, generated })
+    }
+    pub fn format(&self, data: &str) -> Result<String, ParseError> {
+        self.0.format(data)
+    }
+}
+
+impl Template {
+    pub fn render(&self, context: &Context) -> Result<String, RenderError> {
+        let interpreted = self.template.render(context);
+        if let Some(func) = self.generated {
+            match (&interpreted, &func(context)) {
+                (Ok(a), Ok(b)) => {
+                    assert_eq!(a, b, "template {:?}", self.source);
+                }
+                (Err(a), Err(b)) => {
+                    assert_eq!(a.to_string(), b.to_string(),
+                        "template {:?}", self.source);
+                }
+                (a, b) => {
+                    panic!("template {:?}: {:?} != {:?}", self.source, a, b);
+                }
+            }
+        }
+        interpreted
+    }
+}
+
+#[cfg(feature="json")]
+pub fn render_json(tpl: &Template, json: &serde_json::Value)
+    -> Result<String, RenderError>
+{
+    let mut ctx = Context::new();
+    for (k, v) in json.as_object().unwrap() {
+        ctx.set(k, v);
+    }
+    tpl.render(&ctx)
+}
+
+impl Deref for Template {
+    type Target = trimmer::Template;
+    fn deref(&self) -> &trimmer::Template {
+        &self.template
+    }
+}
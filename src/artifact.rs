@@ -0,0 +1,688 @@
+//! Binary format of the precompiled templates
+//!
+//! Artifact starts with a magic and a format version, followed by options
+//! and the optimized body of the template. All integers are little endian,
+//! strings and sequences are prefixed by their length. Bump `VERSION`
+//! whenever anything that is written here changes.
+use std::collections::HashMap;
+use std::str;
+use std::usize;
+
+use regex::{self, Regex};
+
+use grammar::{self, Body, Statement, Expr};
+use grammar::{AssignTarget, CmpOperator, OutputMode};
+use preparser::Syntax;
+use render::{self, Template};
+use schema::{Type, Attr};
+use tokenizer::Delimiters;
+use validators::{Filter, Constraints, parse_escapes, escapes_name};
+use {Options, Pos};
+
+
+const MAGIC: &[u8] = b"TRIMMER\0";
+const VERSION: u32 = 5;
+
+quick_error! {
+    /// Error loading precompiled template with `Template::from_bytes`
+    #[derive(Debug)]
+    pub enum ArtifactError {
+        /// Data is not a precompiled template
+        BadMagic {
+            description("not a precompiled template")
+        }
+        /// Template was compiled by incompatible version of trimmer
+        UnsupportedVersion(version: u32, trimmer: String) {
+            description("unsupported precompiled template version")
+            display("template is precompiled by trimmer {} \
+                (format version {}), but this trimmer supports \
+                version {} only", trimmer, version, VERSION)
+        }
+        /// Data is truncated
+        UnexpectedEof {
+            description("unexpected end of precompiled template")
+        }
+        /// Data is corrupted
+        Invalid(message: &'static str) {
+            description("precompiled template is invalid")
+            display("precompiled template is invalid: {}", message)
+        }
+        /// Template uses an escaper registered with `Options::escaper`, but
+        /// it isn't passed to `Template::from_bytes_with_options`
+        CustomEscaper(name: String) {
+            description("template uses custom escaper")
+            display("template uses custom escaper {:?} that isn't \
+                registered in options", name)
+        }
+        /// Validator regex can't be compiled
+        BadRegex(value: String, err: regex::Error) {
+            description("validator regex is invalid")
+            display("validator regex {:?} is invalid: {}", value, err)
+        }
+    }
+}
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    /// Options to look up custom escapers in
+    options: Option<&'a Options>,
+}
+
+pub fn to_bytes(tpl: &grammar::Template) -> Vec<u8> {
+    let mut w = Writer { buf: Vec::new() };
+    w.buf.extend(MAGIC);
+    w.u32(VERSION);
+    w.str(env!("CARGO_PKG_VERSION"));
+    w.options(&tpl.options);
+    w.body(&tpl.body);
+    w.buf
+}
+
+pub fn from_bytes(data: &[u8], options: Option<&Options>)
+    -> Result<Template, ArtifactError>
+{
+    if !data.starts_with(MAGIC) {
+        return Err(ArtifactError::BadMagic);
+    }
+    let mut r = Reader { data: &data[MAGIC.len()..], options };
+    let version = r.u32()?;
+    // version of trimmer is only for error messages, so it's decoded
+    // leniently
+    let trimmer = String::from_utf8_lossy(r.bytes()?).into_owned();
+    if version != VERSION {
+        return Err(ArtifactError::UnsupportedVersion(version, trimmer));
+    }
+    let options = r.options()?;
+    let body = r.body()?;
+    if !r.data.is_empty() {
+        return Err(ArtifactError::Invalid("trailing data"));
+    }
+    render::template(grammar::Template { options, body })
+        .map_err(|_| ArtifactError::Invalid("unsupported expression"))
+}
+
+impl Writer {
+    fn u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+    fn u32(&mut self, val: u32) {
+        for i in 0..4 {
+            self.buf.push((val >> (i*8)) as u8);
+        }
+    }
+    fn u64(&mut self, val: u64) {
+        self.u32(val as u32);
+        self.u32((val >> 32) as u32);
+    }
+    fn len(&mut self, val: usize) {
+        self.u64(val as u64);
+    }
+    fn bool(&mut self, val: bool) {
+        self.u8(val as u8);
+    }
+    fn str(&mut self, val: &str) {
+        self.len(val.len());
+        self.buf.extend(val.as_bytes());
+    }
+    fn pos(&mut self, pos: (Pos, Pos)) {
+        for p in &[pos.0, pos.1] {
+            self.len(p.line);
+            self.len(p.column);
+        }
+    }
+    fn options(&mut self, options: &Options) {
+        self.u8(match options.syntax {
+            Syntax::Plain => 0,
+            Syntax::Indent => 1,
+            Syntax::Oneline => 2,
+        });
+        let delimiters = &options.delimiters;
+        self.str(&delimiters.expr_start);
+        self.str(&delimiters.expr_end);
+        self.str(&delimiters.statement);
+        self.str(&delimiters.comment_start);
+        self.str(&delimiters.comment_end);
+        self.u8(match options.new_line_at_eof {
+            None => 0,
+            Some(false) => 1,
+            Some(true) => 2,
+        });
+        self.bool(options.curly);
+        self.bool(options.square);
+        self.bool(options.round);
+        self.filter(&options.default_filter);
+        // sorted, so artifact doesn't depend on hash map order
+        let mut filters = options.filters.iter().collect::<Vec<_>>();
+        filters.sort_by_key(|&(name, _)| name);
+        self.len(filters.len());
+        for (name, filter) in filters {
+            self.str(name);
+            self.filter(filter);
+        }
+        self.len(options.schema.len());
+        for (name, typ) in &options.schema {
+            self.str(name);
+            self.typ(typ);
+        }
+    }
+    fn filter(&mut self, filter: &Filter) {
+        match *filter {
+            Filter::NoFilter => self.u8(0),
+            Filter::Validate(ref regex) => {
+                self.u8(1);
+                self.str(regex.as_str());
+            }
+            Filter::Escape(ref escape) => {
+                self.u8(2);
+                self.str(&escapes_name(escape));
+            }
+            Filter::Constraints(ref c) => {
+                self.u8(3);
+                self.bool(c.regex.is_some());
+                if let Some(ref regex) = c.regex {
+                    self.str(regex.as_str());
+                }
+                self.opt_len(c.min_length);
+                self.opt_len(c.max_length);
+                self.str(&c.categories.join(" "));
+                self.len(c.forbid.len());
+                for sub in &c.forbid {
+                    self.str(sub);
+                }
+                self.str(&escapes_name(&c.escapes));
+            }
+            Filter::Custom(ref c) => {
+                self.u8(4);
+                self.str(&c.name);
+            }
+        }
+    }
+    fn opt_len(&mut self, val: Option<usize>) {
+        self.bool(val.is_some());
+        if let Some(val) = val {
+            self.len(val);
+        }
+    }
+    fn typ(&mut self, typ: &Type) {
+        match *typ {
+            Type::Any => self.u8(0),
+            Type::Str => self.u8(1),
+            Type::Number => self.u8(2),
+            Type::Bool => self.u8(3),
+            Type::List(ref item) => {
+                self.u8(4);
+                self.typ(item);
+            }
+            Type::Map(ref value) => {
+                self.u8(5);
+                self.typ(value);
+            }
+            Type::Object(ref attrs) => {
+                self.u8(6);
+                self.len(attrs.len());
+                for attr in attrs {
+                    self.str(&attr.name);
+                    self.typ(&attr.typ);
+                    self.bool(attr.optional);
+                }
+            }
+        }
+    }
+    fn body(&mut self, body: &Body) {
+        self.len(body.statements.len());
+        for st in &body.statements {
+            self.statement(st);
+        }
+    }
+    fn target(&mut self, target: &AssignTarget) {
+        match *target {
+            AssignTarget::Var(ref name) => {
+                self.u8(0);
+                self.str(name);
+            }
+            AssignTarget::Pair(ref a, ref b) => {
+                self.u8(1);
+                self.str(a);
+                self.str(b);
+            }
+        }
+    }
+    fn mode(&mut self, mode: OutputMode) {
+        self.u8(match mode {
+            OutputMode::Strip => 0,
+            OutputMode::Space => 1,
+            OutputMode::Preserve => 2,
+        });
+    }
+    fn statement(&mut self, st: &Statement) {
+        use grammar::StatementCode::*;
+        self.pos(st.position);
+        match st.code {
+            OutputRaw(ref text) => {
+                self.u8(0);
+                self.str(text);
+            }
+            Output { left_ws, ref expr, ref validator, right_ws } => {
+                self.u8(1);
+                self.mode(left_ws);
+                self.expr(expr);
+                match *validator {
+                    Some(ref name) => {
+                        self.bool(true);
+                        self.str(name);
+                    }
+                    None => self.bool(false),
+                }
+                self.mode(right_ws);
+            }
+            Joiner => self.u8(2),
+            WhitespaceControl { left_ws, right_ws } => {
+                self.u8(6);
+                self.mode(left_ws);
+                self.mode(right_ws);
+            }
+            Cond { indent, ref conditional, ref otherwise } => {
+                self.u8(3);
+                self.len(indent);
+                self.len(conditional.len());
+                for (cond, body) in conditional {
+                    self.expr(cond);
+                    self.body(body);
+                }
+                self.body(otherwise);
+            }
+            Loop { indent, ref target, ref iterator, ref filter, ref body } => {
+                self.u8(4);
+                self.len(indent);
+                self.target(target);
+                self.expr(iterator);
+                match *filter {
+                    Some(ref filter) => {
+                        self.bool(true);
+                        self.expr(filter);
+                    }
+                    None => self.bool(false),
+                }
+                self.body(body);
+            }
+            Alias { ref target, ref value } => {
+                self.u8(5);
+                self.target(target);
+                self.expr(value);
+            }
+        }
+    }
+    fn expr(&mut self, expr: &Expr) {
+        use grammar::ExprCode::*;
+        self.pos(expr.position);
+        match expr.code {
+            Str(ref val) => {
+                self.u8(0);
+                self.str(val);
+            }
+            Int(val) => {
+                self.u8(1);
+                self.u64(val as u64);
+            }
+            Float(val) => {
+                self.u8(2);
+                self.u64(val.to_bits());
+            }
+            Var(ref name) => {
+                self.u8(3);
+                self.str(name);
+            }
+            Attr(ref value, ref name) => {
+                self.u8(4);
+                self.expr(value);
+                self.str(name);
+            }
+            Item(ref a, ref b) => self.pair(5, a, b),
+            And(ref a, ref b) => self.pair(6, a, b),
+            Or(ref a, ref b) => self.pair(7, a, b),
+            Not(ref value) => {
+                self.u8(8);
+                self.expr(value);
+            }
+            Comparison(ref first, ref rest) => {
+                self.u8(9);
+                self.expr(first);
+                self.len(rest.len());
+                for &(op, ref value) in rest {
+                    self.u8(match op {
+                        CmpOperator::Eq => 0,
+                        CmpOperator::Neq => 1,
+                        CmpOperator::LessEq => 2,
+                        CmpOperator::Less => 3,
+                        CmpOperator::GreaterEq => 4,
+                        CmpOperator::Greater => 5,
+                    });
+                    self.expr(value);
+                }
+            }
+            List(ref items) => {
+                self.u8(10);
+                self.len(items.len());
+                for item in items {
+                    self.expr(item);
+                }
+            }
+            Dict(ref pairs) => {
+                self.u8(11);
+                self.len(pairs.len());
+                for (key, value) in pairs {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            Add(ref a, ref b) => self.pair(12, a, b),
+            Sub(ref a, ref b) => self.pair(13, a, b),
+            Mul(ref a, ref b) => self.pair(14, a, b),
+            Div(ref a, ref b) => self.pair(15, a, b),
+            Mod(ref a, ref b) => self.pair(16, a, b),
+            // not produced by the parser yet
+            Filter(..) | Range(..) => unreachable!(),
+        }
+    }
+    fn pair(&mut self, tag: u8, a: &Expr, b: &Expr) {
+        self.u8(tag);
+        self.expr(a);
+        self.expr(b);
+    }
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ArtifactError> {
+        if self.data.len() < len {
+            return Err(ArtifactError::UnexpectedEof);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+    fn u8(&mut self) -> Result<u8, ArtifactError> {
+        Ok(self.take(1)?[0])
+    }
+    fn u32(&mut self) -> Result<u32, ArtifactError> {
+        let buf = self.take(4)?;
+        Ok(buf.iter().rev().fold(0, |val, &b| (val << 8) | b as u32))
+    }
+    fn u64(&mut self) -> Result<u64, ArtifactError> {
+        let low = self.u32()? as u64;
+        let high = self.u32()? as u64;
+        Ok((high << 32) | low)
+    }
+    fn len(&mut self) -> Result<usize, ArtifactError> {
+        let val = self.u64()?;
+        if val > usize::MAX as u64 {
+            return Err(ArtifactError::Invalid("length is too large"));
+        }
+        Ok(val as usize)
+    }
+    fn bool(&mut self) -> Result<bool, ArtifactError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(ArtifactError::Invalid("bad boolean")),
+        }
+    }
+    fn bytes(&mut self) -> Result<&'a [u8], ArtifactError> {
+        let len = self.len()?;
+        self.take(len)
+    }
+    fn str(&mut self) -> Result<String, ArtifactError> {
+        str::from_utf8(self.bytes()?)
+            .map(|s| s.to_string())
+            .map_err(|_| ArtifactError::Invalid("bad utf-8 in string"))
+    }
+    fn pos(&mut self) -> Result<(Pos, Pos), ArtifactError> {
+        let start = Pos { line: self.len()?, column: self.len()? };
+        let end = Pos { line: self.len()?, column: self.len()? };
+        Ok((start, end))
+    }
+    fn options(&mut self) -> Result<Options, ArtifactError> {
+        let mut options = Options::new();
+        options.syntax = match self.u8()? {
+            0 => Syntax::Plain,
+            1 => Syntax::Indent,
+            2 => Syntax::Oneline,
+            _ => return Err(ArtifactError::Invalid("bad syntax")),
+        };
+        options.delimiters = Delimiters {
+            expr_start: self.str()?,
+            expr_end: self.str()?,
+            statement: self.str()?,
+            comment_start: self.str()?,
+            comment_end: self.str()?,
+        };
+        options.new_line_at_eof = match self.u8()? {
+            0 => None,
+            1 => Some(false),
+            2 => Some(true),
+            _ => return Err(ArtifactError::Invalid("bad new_line_at_eof")),
+        };
+        options.curly = self.bool()?;
+        options.square = self.bool()?;
+        options.round = self.bool()?;
+        options.default_filter = self.filter()?;
+        let mut filters = HashMap::new();
+        for _ in 0..self.len()? {
+            let name = self.str()?;
+            filters.insert(name, self.filter()?);
+        }
+        options.filters = filters;
+        for _ in 0..self.len()? {
+            let name = self.str()?;
+            let typ = self.typ()?;
+            options.schema.push((name, typ));
+        }
+        Ok(options)
+    }
+    fn filter(&mut self) -> Result<Filter, ArtifactError> {
+        match self.u8()? {
+            0 => Ok(Filter::NoFilter),
+            1 => {
+                let source = self.str()?;
+                Regex::new(&source)
+                    .map(Filter::Validate)
+                    .map_err(|e| ArtifactError::BadRegex(source, e))
+            }
+            2 => {
+                parse_escapes(&self.str()?)
+                    .map(Filter::Escape)
+                    .map_err(|_| ArtifactError::Invalid("unknown escape"))
+            }
+            3 => {
+                let mut c = Constraints::new();
+                if self.bool()? {
+                    let source = self.str()?;
+                    c.regex = Some(Regex::new(&source)
+                        .map_err(|e| ArtifactError::BadRegex(source, e))?);
+                }
+                c.min_length = self.opt_len()?;
+                c.max_length = self.opt_len()?;
+                let categories = self.str()?;
+                c.set_categories(&categories)
+                    .map_err(|e| ArtifactError::BadRegex(categories, e))?;
+                for _ in 0..self.len()? {
+                    c.forbid.push(self.str()?);
+                }
+                let escapes = self.str()?;
+                if !escapes.is_empty() {
+                    c.escapes = parse_escapes(&escapes)
+                        .map_err(|_| ArtifactError::Invalid("unknown escape"))?;
+                }
+                Ok(Filter::Constraints(Box::new(c)))
+            }
+            4 => {
+                let name = self.str()?;
+                let filter = self.options
+                    .and_then(|o| o.filters.get(&name))
+                    .and_then(|f| match *f {
+                        Filter::Custom(ref c) if c.name == name => {
+                            Some(f.clone())
+                        }
+                        _ => None,
+                    });
+                filter.ok_or(ArtifactError::CustomEscaper(name))
+            }
+            _ => Err(ArtifactError::Invalid("bad filter")),
+        }
+    }
+    fn opt_len(&mut self) -> Result<Option<usize>, ArtifactError> {
+        if self.bool()? {
+            Ok(Some(self.len()?))
+        } else {
+            Ok(None)
+        }
+    }
+    fn typ(&mut self) -> Result<Type, ArtifactError> {
+        Ok(match self.u8()? {
+            0 => Type::Any,
+            1 => Type::Str,
+            2 => Type::Number,
+            3 => Type::Bool,
+            4 => Type::List(Box::new(self.typ()?)),
+            5 => Type::Map(Box::new(self.typ()?)),
+            6 => {
+                let mut attrs = Vec::new();
+                for _ in 0..self.len()? {
+                    attrs.push(Attr {
+                        name: self.str()?,
+                        typ: self.typ()?,
+                        optional: self.bool()?,
+                    });
+                }
+                Type::Object(attrs)
+            }
+            _ => return Err(ArtifactError::Invalid("bad type")),
+        })
+    }
+    fn body(&mut self) -> Result<Body, ArtifactError> {
+        let mut statements = Vec::new();
+        for _ in 0..self.len()? {
+            statements.push(self.statement()?);
+        }
+        Ok(Body { statements })
+    }
+    fn target(&mut self) -> Result<AssignTarget, ArtifactError> {
+        match self.u8()? {
+            0 => Ok(AssignTarget::Var(self.str()?)),
+            1 => Ok(AssignTarget::Pair(self.str()?, self.str()?)),
+            _ => Err(ArtifactError::Invalid("bad assignment target")),
+        }
+    }
+    fn mode(&mut self) -> Result<OutputMode, ArtifactError> {
+        match self.u8()? {
+            0 => Ok(OutputMode::Strip),
+            1 => Ok(OutputMode::Space),
+            2 => Ok(OutputMode::Preserve),
+            _ => Err(ArtifactError::Invalid("bad whitespace mode")),
+        }
+    }
+    fn statement(&mut self) -> Result<Statement, ArtifactError> {
+        use grammar::StatementCode::*;
+        let position = self.pos()?;
+        let code = match self.u8()? {
+            0 => OutputRaw(self.str()?),
+            1 => Output {
+                left_ws: self.mode()?,
+                expr: self.expr()?,
+                validator: if self.bool()? { Some(self.str()?) } else { None },
+                right_ws: self.mode()?,
+            },
+            2 => Joiner,
+            3 => {
+                let indent = self.len()?;
+                let mut conditional = Vec::new();
+                for _ in 0..self.len()? {
+                    conditional.push((self.expr()?, self.body()?));
+                }
+                Cond { indent, conditional, otherwise: self.body()? }
+            }
+            4 => Loop {
+                indent: self.len()?,
+                target: self.target()?,
+                iterator: self.expr()?,
+                filter: if self.bool()? { Some(self.expr()?) } else { None },
+                body: self.body()?,
+            },
+            5 => match self.target()? {
+                // unsupported by grammar yet
+                AssignTarget::Pair(..) => {
+                    return Err(ArtifactError::Invalid("bad assignment"));
+                }
+                target => Alias { target, value: self.expr()? },
+            },
+            6 => WhitespaceControl {
+                left_ws: self.mode()?,
+                right_ws: self.mode()?,
+            },
+            _ => return Err(ArtifactError::Invalid("bad statement")),
+        };
+        Ok(Statement { position, code })
+    }
+    fn boxed(&mut self) -> Result<Box<Expr>, ArtifactError> {
+        Ok(Box::new(self.expr()?))
+    }
+    fn expr(&mut self) -> Result<Expr, ArtifactError> {
+        use grammar::ExprCode::*;
+        let position = self.pos()?;
+        let code = match self.u8()? {
+            0 => Str(self.str()?),
+            1 => Int(self.u64()? as i64),
+            2 => Float(f64::from_bits(self.u64()?)),
+            3 => Var(self.str()?),
+            4 => Attr(self.boxed()?, self.str()?),
+            5 => Item(self.boxed()?, self.boxed()?),
+            6 => And(self.boxed()?, self.boxed()?),
+            7 => Or(self.boxed()?, self.boxed()?),
+            8 => Not(self.boxed()?),
+            9 => {
+                let first = self.boxed()?;
+                let mut rest = Vec::new();
+                for _ in 0..self.len()? {
+                    let op = match self.u8()? {
+                        0 => CmpOperator::Eq,
+                        1 => CmpOperator::Neq,
+                        2 => CmpOperator::LessEq,
+                        3 => CmpOperator::Less,
+                        4 => CmpOperator::GreaterEq,
+                        5 => CmpOperator::Greater,
+                        _ => return Err(ArtifactError::Invalid("bad operator")),
+                    };
+                    rest.push((op, self.expr()?));
+                }
+                if rest.is_empty() {
+                    return Err(ArtifactError::Invalid("empty comparison"));
+                }
+                Comparison(first, rest)
+            }
+            10 => {
+                let mut items = Vec::new();
+                for _ in 0..self.len()? {
+                    items.push(self.expr()?);
+                }
+                List(items)
+            }
+            11 => {
+                let mut pairs = Vec::new();
+                for _ in 0..self.len()? {
+                    pairs.push((self.expr()?, self.expr()?));
+                }
+                Dict(pairs)
+            }
+            12 => Add(self.boxed()?, self.boxed()?),
+            13 => Sub(self.boxed()?, self.boxed()?),
+            14 => Mul(self.boxed()?, self.boxed()?),
+            15 => Div(self.boxed()?, self.boxed()?),
+            16 => Mod(self.boxed()?, self.boxed()?),
+            _ => return Err(ArtifactError::Invalid("bad expression")),
+        };
+        Ok(Expr { position, code })
+    }
+}
@@ -0,0 +1,394 @@
+//! Read-only view of the parsed template
+//!
+//! This module is useful for tools that need to inspect templates:
+//! linters, dependency scanners, extractors of translatable strings and
+//! so on. Use `Template::ast()` to get the root `Body` and either match on
+//! `Statement::kind()` and `Expr::kind()` directly or implement `Visitor`.
+//!
+//! The types here are thin wrappers around the internal representation,
+//! so the latter can change without breaking this API. Note that the
+//! tree reflects the template after whitespace processing: raw text is
+//! already stripped or condensed according to the template syntax and
+//! comments are not included.
+use std::slice;
+
+use grammar;
+use {Pos};
+
+
+/// A sequence of statements: a whole template or a block inside a statement
+#[derive(Debug, Clone, Copy)]
+pub struct Body<'a>(&'a grammar::Body);
+
+/// A single statement of a template, including raw text and `{{ }}`
+#[derive(Debug, Clone, Copy)]
+pub struct Statement<'a>(&'a grammar::Statement);
+
+/// An expression in `{{ }}` or in a statement
+#[derive(Debug, Clone, Copy)]
+pub struct Expr<'a>(&'a grammar::Expr);
+
+/// Iterator over statements of the body
+#[derive(Debug, Clone)]
+pub struct Statements<'a>(slice::Iter<'a, grammar::Statement>);
+
+/// Whitespace control marker of the expression, statement or comment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Whitespace {
+    /// No marker, whitespace is kept as is
+    Preserve,
+    /// Dash `-` marker, adjacent whitespace is stripped
+    Strip,
+    /// Plus `+` marker, adjacent whitespace is replaced by single space
+    Space,
+}
+
+/// Variables assigned by `## for` or `## let`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target<'a> {
+    /// Single variable: `## for x in list`
+    Var(&'a str),
+    /// Key and value: `## for k, v in dict`
+    Pair(&'a str, &'a str),
+}
+
+/// Kind of the statement with its contents
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum StatementKind<'a> {
+    /// Raw text that is output as is
+    Raw(&'a str),
+    /// Expression output: `{{ expr | filter }}`
+    Output {
+        /// Expression to output
+        expr: Expr<'a>,
+        /// Name of the filter (validator) if specified explicitly
+        filter: Option<&'a str>,
+        /// Whitespace marker at the start of the expression
+        left_ws: Whitespace,
+        /// Whitespace marker at the end of the expression
+        right_ws: Whitespace,
+    },
+    /// Line joiner `##` at the end of the line
+    LineJoiner,
+    /// Whitespace control without output: a `{#- comment -#}` or one side
+    /// of the `##-` and `##+` statement lines
+    WhitespaceControl {
+        /// Marker for the whitespace before
+        left_ws: Whitespace,
+        /// Marker for the whitespace after
+        right_ws: Whitespace,
+    },
+    /// Conditional: `## if` with optional `## elif` and `## else`
+    If {
+        /// Condition and body for `## if` and each of `## elif`
+        branches: Vec<(Expr<'a>, Body<'a>)>,
+        /// Body of the `## else` (empty if there is no else)
+        otherwise: Body<'a>,
+    },
+    /// Loop: `## for target in iterator`
+    For {
+        /// Loop variable(s)
+        target: Target<'a>,
+        /// Expression to iterate over
+        iterator: Expr<'a>,
+        /// Condition of `## skip if` (multiple ones are joined by `or`)
+        skip_if: Option<Expr<'a>>,
+        /// Body of the loop
+        body: Body<'a>,
+    },
+    /// Assignment: `## let target = value`
+    Let {
+        /// Variable name
+        target: &'a str,
+        /// Expression that is assigned
+        value: Expr<'a>,
+    },
+}
+
+/// Comparison operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// `==`
+    Eq,
+    /// `!=`
+    Neq,
+    /// `<`
+    Less,
+    /// `<=`
+    LessEq,
+    /// `>`
+    Greater,
+    /// `>=`
+    GreaterEq,
+}
+
+/// Arithmetic operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MathOp {
+    /// `+`
+    Add,
+    /// `-`
+    Sub,
+    /// `*`
+    Mul,
+    /// `/`
+    Div,
+    /// `%`
+    Mod,
+}
+
+/// Kind of the expression with its contents
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ExprKind<'a> {
+    /// String literal
+    Str(&'a str),
+    /// Integer literal
+    Int(i64),
+    /// Floating point literal
+    Float(f64),
+    /// Variable reference
+    Var(&'a str),
+    /// Attribute access: `expr.name`
+    Attr(Expr<'a>, &'a str),
+    /// Item access: `expr[item]`
+    Item(Expr<'a>, Expr<'a>),
+    /// Boolean negation: `not expr`
+    Not(Expr<'a>),
+    /// Boolean `and`
+    And(Expr<'a>, Expr<'a>),
+    /// Boolean `or`
+    Or(Expr<'a>, Expr<'a>),
+    /// Chain of comparisons: `a < b <= c`
+    Comparison(Expr<'a>, Vec<(Comparison, Expr<'a>)>),
+    /// Arithmetic operation
+    Math(MathOp, Expr<'a>, Expr<'a>),
+    /// List literal: `[a, b]`
+    List(Vec<Expr<'a>>),
+    /// Dictionary literal: `{"a": b}`
+    Dict(Vec<(Expr<'a>, Expr<'a>)>),
+}
+
+/// Visitor of the template tree
+///
+/// All methods walk into the nested nodes by default, so you only need to
+/// override methods for the nodes you're interested in. Call the
+/// respective `walk_*` function to continue into nested nodes from the
+/// overridden method.
+pub trait Visitor<'a> {
+    /// Called for every statement, including nested ones
+    fn visit_statement(&mut self, statement: Statement<'a>) {
+        walk_statement(self, statement)
+    }
+    /// Called for every expression, including nested ones
+    fn visit_expr(&mut self, expr: Expr<'a>) {
+        walk_expr(self, expr)
+    }
+}
+
+/// Visit every statement of the body
+pub fn walk_body<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, body: Body<'a>)
+{
+    for statement in body.statements() {
+        visitor.visit_statement(statement);
+    }
+}
+
+/// Visit expressions and bodies nested in the statement
+pub fn walk_statement<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V,
+    statement: Statement<'a>)
+{
+    use self::StatementKind::*;
+    match statement.kind() {
+        Raw(_) | LineJoiner | WhitespaceControl { .. } => {}
+        Output { expr, .. } => visitor.visit_expr(expr),
+        If { branches, otherwise } => {
+            for (cond, body) in branches {
+                visitor.visit_expr(cond);
+                walk_body(visitor, body);
+            }
+            walk_body(visitor, otherwise);
+        }
+        For { iterator, skip_if, body, .. } => {
+            visitor.visit_expr(iterator);
+            if let Some(cond) = skip_if {
+                visitor.visit_expr(cond);
+            }
+            walk_body(visitor, body);
+        }
+        Let { value, .. } => visitor.visit_expr(value),
+    }
+}
+
+/// Visit subexpressions of the expression
+pub fn walk_expr<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, expr: Expr<'a>)
+{
+    use self::ExprKind::*;
+    match expr.kind() {
+        Str(_) | Int(_) | Float(_) | Var(_) => {}
+        Attr(e, _) | Not(e) => visitor.visit_expr(e),
+        Item(a, b) | And(a, b) | Or(a, b) | Math(_, a, b) => {
+            visitor.visit_expr(a);
+            visitor.visit_expr(b);
+        }
+        Comparison(first, rest) => {
+            visitor.visit_expr(first);
+            for (_, e) in rest {
+                visitor.visit_expr(e);
+            }
+        }
+        List(items) => {
+            for e in items {
+                visitor.visit_expr(e);
+            }
+        }
+        Dict(pairs) => {
+            for (k, v) in pairs {
+                visitor.visit_expr(k);
+                visitor.visit_expr(v);
+            }
+        }
+    }
+}
+
+pub(crate) fn body<'a>(body: &'a grammar::Body) -> Body<'a> {
+    Body(body)
+}
+
+impl<'a> Body<'a> {
+    /// Iterate over statements of the body
+    pub fn statements(&self) -> Statements<'a> {
+        Statements(self.0.statements.iter())
+    }
+    /// Returns true if there are no statements in the body
+    pub fn is_empty(&self) -> bool {
+        self.0.statements.is_empty()
+    }
+    /// Walk the body with the visitor
+    pub fn visit<V: Visitor<'a>>(&self, visitor: &mut V) {
+        walk_body(visitor, *self)
+    }
+}
+
+impl<'a> Iterator for Statements<'a> {
+    type Item = Statement<'a>;
+    fn next(&mut self) -> Option<Statement<'a>> {
+        self.0.next().map(Statement)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+fn whitespace(mode: grammar::OutputMode) -> Whitespace {
+    match mode {
+        grammar::OutputMode::Preserve => Whitespace::Preserve,
+        grammar::OutputMode::Strip => Whitespace::Strip,
+        grammar::OutputMode::Space => Whitespace::Space,
+    }
+}
+
+fn target<'a>(target: &'a grammar::AssignTarget) -> Target<'a> {
+    match *target {
+        grammar::AssignTarget::Var(ref name) => Target::Var(name),
+        grammar::AssignTarget::Pair(ref a, ref b) => Target::Pair(a, b),
+    }
+}
+
+impl<'a> Statement<'a> {
+    /// Start and end of the statement in the template source
+    pub fn position(&self) -> (Pos, Pos) {
+        self.0.position
+    }
+    /// Kind and contents of the statement
+    pub fn kind(&self) -> StatementKind<'a> {
+        use grammar::StatementCode::*;
+        match self.0.code {
+            OutputRaw(ref text) => StatementKind::Raw(text),
+            Output { left_ws, ref expr, ref validator, right_ws } => {
+                StatementKind::Output {
+                    expr: Expr(expr),
+                    filter: validator.as_ref().map(|x| &x[..]),
+                    left_ws: whitespace(left_ws),
+                    right_ws: whitespace(right_ws),
+                }
+            }
+            Joiner => StatementKind::LineJoiner,
+            WhitespaceControl { left_ws, right_ws } => {
+                StatementKind::WhitespaceControl {
+                    left_ws: whitespace(left_ws),
+                    right_ws: whitespace(right_ws),
+                }
+            }
+            Cond { ref conditional, ref otherwise, .. } => StatementKind::If {
+                branches: conditional.iter()
+                    .map(|(cond, body)| (Expr(cond), Body(body)))
+                    .collect(),
+                otherwise: Body(otherwise),
+            },
+            Loop { target: ref t, ref iterator, ref filter, ref body, .. } => {
+                StatementKind::For {
+                    target: target(t),
+                    iterator: Expr(iterator),
+                    skip_if: filter.as_ref().map(Expr),
+                    body: Body(body),
+                }
+            }
+            Alias { target: ref t, ref value } => StatementKind::Let {
+                target: match target(t) {
+                    Target::Var(name) => name,
+                    Target::Pair(..) => unreachable!(),
+                },
+                value: Expr(value),
+            },
+        }
+    }
+}
+
+impl<'a> Expr<'a> {
+    /// Start and end of the expression in the template source
+    pub fn position(&self) -> (Pos, Pos) {
+        self.0.position
+    }
+    /// Kind and contents of the expression
+    pub fn kind(&self) -> ExprKind<'a> {
+        use grammar::ExprCode::*;
+        use grammar::CmpOperator as C;
+        match self.0.code {
+            Str(ref s) => ExprKind::Str(s),
+            Int(x) => ExprKind::Int(x),
+            Float(x) => ExprKind::Float(x),
+            Var(ref name) => ExprKind::Var(name),
+            Attr(ref e, ref name) => ExprKind::Attr(Expr(e), name),
+            Item(ref e, ref item) => ExprKind::Item(Expr(e), Expr(item)),
+            Not(ref e) => ExprKind::Not(Expr(e)),
+            And(ref a, ref b) => ExprKind::And(Expr(a), Expr(b)),
+            Or(ref a, ref b) => ExprKind::Or(Expr(a), Expr(b)),
+            Comparison(ref first, ref rest) => ExprKind::Comparison(
+                Expr(first),
+                rest.iter().map(|(op, e)| {
+                    let op = match *op {
+                        C::Eq => self::Comparison::Eq,
+                        C::Neq => self::Comparison::Neq,
+                        C::Less => self::Comparison::Less,
+                        C::LessEq => self::Comparison::LessEq,
+                        C::Greater => self::Comparison::Greater,
+                        C::GreaterEq => self::Comparison::GreaterEq,
+                    };
+                    (op, Expr(e))
+                }).collect()),
+            Add(ref a, ref b) => ExprKind::Math(MathOp::Add, Expr(a), Expr(b)),
+            Sub(ref a, ref b) => ExprKind::Math(MathOp::Sub, Expr(a), Expr(b)),
+            Mul(ref a, ref b) => ExprKind::Math(MathOp::Mul, Expr(a), Expr(b)),
+            Div(ref a, ref b) => ExprKind::Math(MathOp::Div, Expr(a), Expr(b)),
+            Mod(ref a, ref b) => ExprKind::Math(MathOp::Mod, Expr(a), Expr(b)),
+            List(ref items) => ExprKind::List(items.iter().map(Expr).collect()),
+            Dict(ref pairs) => ExprKind::Dict(pairs.iter()
+                .map(|(k, v)| (Expr(k), Expr(v)))
+                .collect()),
+            // these are never produced by the parser
+            Filter(..) | Range(..) => unreachable!(),
+        }
+    }
+}
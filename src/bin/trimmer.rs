@@ -2,7 +2,8 @@ extern crate trimmer;
 extern crate argparse;
 #[cfg(feature="json")] extern crate serde_json;
 
-use std::io::{Read, Write, stdout};
+use std::env;
+use std::io::{Read, Write, stdout, stderr};
 use std::fs::File;
 use std::path::{Path};
 use std::process::exit;
@@ -11,7 +12,135 @@ use trimmer::Parser;
 #[cfg(feature="json")] use serde_json::Value;
 
 
+fn lint() -> ! {
+    let mut templates = Vec::<String>::new();
+    let mut deny_warnings = false;
+    {
+        use argparse::*;
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Check templates for suspicious constructs");
+        ap.refer(&mut templates)
+            .add_argument("template", Collect, "Templates to check");
+        ap.refer(&mut deny_warnings)
+            .add_option(&["--deny-warnings"], StoreTrue,
+                "Exit with non-zero code (4) if there are any warnings");
+        let mut args: Vec<_> = env::args().skip(2).collect();
+        args.insert(0, "trimmer lint".into());
+        match ap.parse(args, &mut stdout(), &mut stderr()) {
+            Ok(()) => {}
+            Err(code) => exit(code),
+        }
+    }
+    let parser = Parser::new();
+    let mut buf = String::with_capacity(4096);
+    let mut code = 0;
+    let mut warnings = 0;
+    for template in templates {
+        let path = Path::new(&template);
+        buf.truncate(0);
+        let read = File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut buf));
+        match read {
+            Ok(_) => {},
+            Err(e) => {
+                eprintln!("Error reading {:?}: {}", path, e);
+                code = 1;
+                continue;
+            }
+        }
+        match parser.lint(&buf) {
+            Ok(list) => {
+                for (pos, warning) in &list {
+                    eprintln!("{}:{}: {}", path.display(), pos, warning);
+                }
+                warnings += list.len();
+            }
+            Err(e) => {
+                eprintln!("Error parsing {:?}: {}", path, e);
+                code = 2;
+            }
+        }
+    }
+    if code == 0 && deny_warnings && warnings > 0 {
+        code = 4;
+    }
+    exit(code);
+}
+
+fn fmt() -> ! {
+    let mut templates = Vec::<String>::new();
+    let mut check = false;
+    {
+        use argparse::*;
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Format templates in canonical style. \
+            Files are rewritten in-place");
+        ap.refer(&mut templates)
+            .add_argument("template", Collect, "Templates to format");
+        ap.refer(&mut check)
+            .add_option(&["--check"], StoreTrue,
+                "Don't write files, just exit with non-zero code (4) if \
+                 any template is not formatted");
+        let mut args: Vec<_> = env::args().skip(2).collect();
+        args.insert(0, "trimmer fmt".into());
+        match ap.parse(args, &mut stdout(), &mut stderr()) {
+            Ok(()) => {}
+            Err(code) => exit(code),
+        }
+    }
+    let parser = Parser::new();
+    let mut buf = String::with_capacity(4096);
+    let mut code = 0;
+    for template in templates {
+        let path = Path::new(&template);
+        buf.truncate(0);
+        let read = File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut buf));
+        match read {
+            Ok(_) => {},
+            Err(e) => {
+                eprintln!("Error reading {:?}: {}", path, e);
+                code = 1;
+                continue;
+            }
+        }
+        let formatted = match parser.format(&buf) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("Error parsing {:?}: {}", path, e);
+                code = 2;
+                continue;
+            }
+        };
+        if formatted == buf {
+            continue;
+        }
+        if check {
+            eprintln!("{} is not formatted", path.display());
+            if code == 0 {
+                code = 4;
+            }
+        } else {
+            let res = File::create(path)
+                .and_then(|mut f| f.write_all(formatted.as_bytes()));
+            match res {
+                Ok(()) => {}
+                Err(e) => {
+                    eprintln!("Error writing {:?}: {}", path, e);
+                    code = 1;
+                }
+            }
+        }
+    }
+    exit(code);
+}
+
 fn main() {
+    match env::args().nth(1).as_ref().map(|x| &x[..]) {
+        Some("lint") => lint(),
+        Some("fmt") => fmt(),
+        _ => {}
+    }
     let mut vars = Vec::<String>::new();
     let mut templates = Vec::<String>::new();
     #[cfg(feature="json")]
@@ -0,0 +1,292 @@
+use grammar::{Body, Statement, Expr, AssignTarget};
+use grammar::{OutputMode, CmpOperator};
+use parse_error::{ParseError, ParseErrorEnum};
+use Pos;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MathOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+/// A single instruction of the compiled template
+///
+/// Expressions are evaluated on a stack of values, jump targets are indexes
+/// in the list of instructions.
+#[derive(Debug, PartialEq)]
+pub enum Instr {
+    // Statements
+    Raw(String),
+    Joiner,
+    WhitespaceControl {
+        left_ws: OutputMode,
+        right_ws: OutputMode,
+    },
+    /// Pops a value and writes it
+    Output {
+        left_ws: OutputMode,
+        validator: Option<String>,
+        position: Pos,
+        right_ws: OutputMode,
+    },
+    /// Pops a value and sets variable in the current scope
+    Set(String),
+    PushScope,
+    PopScope,
+    /// Pops a value, jumps if it's false
+    Condition(Pos, usize),
+    /// Pops a value, jumps if it's true (`## skip if`)
+    Skip(Pos, usize),
+    Jump(usize),
+    /// Pops a value and starts iteration over it, jumps on error
+    Iterate { position: Pos, pairs: bool, end: usize },
+    /// Pushes a scope and next item (or pair) of the innermost loop, or
+    /// finishes the loop and jumps if there are no more items
+    Next(usize),
+    // Expressions
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Var(String, Pos),
+    Attr(String, Pos),
+    Item(Pos),
+    Math(MathOp, Pos, Pos),
+    Not(Pos),
+    /// Jumps if result is known from the left side, pops it otherwise
+    And(Pos, usize),
+    /// Jumps if result is known from the left side, pops it otherwise
+    Or(Pos, usize),
+    /// Checks that value on top of the stack is comparable, if not
+    /// replaces it with undefined and jumps
+    CompareLeft(Pos, usize),
+    /// Compares two values on top of the stack, jumps if result is known
+    Compare { op: CmpOperator, position: Pos, last: bool, end: usize },
+    List(usize),
+    NewDict,
+    /// Pops a key and a value and inserts them into the current dict
+    Insert(Pos),
+    EndDict,
+}
+
+struct Compiler {
+    code: Vec<Instr>,
+}
+
+pub fn compile(body: &Body) -> Result<Vec<Instr>, ParseError> {
+    let mut cmp = Compiler { code: Vec::new() };
+    cmp.body(body)?;
+    Ok(cmp.code)
+}
+
+impl Compiler {
+    fn push(&mut self, instr: Instr) -> usize {
+        self.code.push(instr);
+        self.code.len() - 1
+    }
+    /// Sets jump target of instruction at `idx` to the current position
+    fn patch(&mut self, idx: usize) {
+        use self::Instr::*;
+        let target = self.code.len();
+        match self.code[idx] {
+            Condition(_, ref mut end) | Skip(_, ref mut end) |
+            Jump(ref mut end) | Next(ref mut end) |
+            Iterate { ref mut end, .. } |
+            And(_, ref mut end) | Or(_, ref mut end) |
+            CompareLeft(_, ref mut end) | Compare { ref mut end, .. }
+            => *end = target,
+            ref instr => unreachable!("can't patch {:?}", instr),
+        }
+    }
+    fn body(&mut self, body: &Body) -> Result<(), ParseError> {
+        for st in &body.statements {
+            self.statement(st)?;
+        }
+        Ok(())
+    }
+    fn scope(&mut self, body: &Body) -> Result<(), ParseError> {
+        self.push(Instr::PushScope);
+        self.body(body)?;
+        self.push(Instr::PopScope);
+        Ok(())
+    }
+    fn statement(&mut self, st: &Statement) -> Result<(), ParseError> {
+        use grammar::StatementCode::*;
+        match st.code {
+            OutputRaw(ref text) => {
+                self.push(Instr::Raw(text.clone()));
+            }
+            Joiner => {
+                self.push(Instr::Joiner);
+            }
+            WhitespaceControl { left_ws, right_ws } => {
+                self.push(Instr::WhitespaceControl { left_ws, right_ws });
+            }
+            Output { left_ws, ref expr, ref validator, right_ws } => {
+                self.expr(expr)?;
+                self.push(Instr::Output {
+                    left_ws,
+                    validator: validator.clone(),
+                    position: st.position.0,
+                    right_ws,
+                });
+            }
+            Alias { target: AssignTarget::Var(ref name), ref value } => {
+                self.expr(value)?;
+                self.push(Instr::Set(name.clone()));
+            }
+            // unsupported by grammar yet
+            Alias { target: AssignTarget::Pair(..), .. } => unreachable!(),
+            Cond { ref conditional, ref otherwise, .. } => {
+                let mut ends = Vec::new();
+                for (cond, body) in conditional {
+                    self.expr(cond)?;
+                    let skip = self.push(Instr::Condition(cond.position.0, 0));
+                    self.scope(body)?;
+                    ends.push(self.push(Instr::Jump(0)));
+                    self.patch(skip);
+                }
+                if !otherwise.statements.is_empty() {
+                    self.scope(otherwise)?;
+                }
+                for idx in ends {
+                    self.patch(idx);
+                }
+            }
+            Loop { ref target, ref iterator, ref filter, ref body, .. } => {
+                self.expr(iterator)?;
+                let start = self.push(Instr::Iterate {
+                    position: iterator.position.0,
+                    pairs: matches!(*target, AssignTarget::Pair(..)),
+                    end: 0,
+                });
+                let next = self.push(Instr::Next(0));
+                match *target {
+                    AssignTarget::Var(ref name) => {
+                        self.push(Instr::Set(name.clone()));
+                    }
+                    AssignTarget::Pair(ref a, ref b) => {
+                        self.push(Instr::Set(b.clone()));
+                        self.push(Instr::Set(a.clone()));
+                    }
+                }
+                let skip = match *filter {
+                    Some(ref filter) => {
+                        self.expr(filter)?;
+                        Some(self.push(Instr::Skip(filter.position.0, 0)))
+                    }
+                    None => None,
+                };
+                self.body(body)?;
+                if let Some(skip) = skip {
+                    self.patch(skip);
+                }
+                self.push(Instr::PopScope);
+                self.push(Instr::Jump(next));
+                self.patch(start);
+                self.patch(next);
+            }
+        }
+        Ok(())
+    }
+    fn expr(&mut self, expr: &Expr) -> Result<(), ParseError> {
+        use grammar::ExprCode::*;
+        let pos = expr.position.0;
+        match expr.code {
+            Str(ref s) => {
+                self.push(Instr::Str(s.clone()));
+            }
+            Int(i) => {
+                self.push(Instr::Int(i));
+            }
+            Float(f) => {
+                self.push(Instr::Float(f));
+            }
+            Var(ref name) => {
+                self.push(Instr::Var(name.clone(), pos));
+            }
+            Attr(ref value, ref name) => {
+                self.expr(value)?;
+                self.push(Instr::Attr(name.clone(), pos));
+            }
+            Item(ref value, ref index) => {
+                self.expr(value)?;
+                self.expr(index)?;
+                self.push(Instr::Item(pos));
+            }
+            Add(ref a, ref b) => self.math(MathOp::Add, a, b)?,
+            Sub(ref a, ref b) => self.math(MathOp::Sub, a, b)?,
+            Mul(ref a, ref b) => self.math(MathOp::Mul, a, b)?,
+            Div(ref a, ref b) => self.math(MathOp::Div, a, b)?,
+            Mod(ref a, ref b) => self.math(MathOp::Mod, a, b)?,
+            And(ref a, ref b) => {
+                self.expr(a)?;
+                let jump = self.push(Instr::And(pos, 0));
+                self.expr(b)?;
+                self.patch(jump);
+            }
+            Or(ref a, ref b) => {
+                self.expr(a)?;
+                let jump = self.push(Instr::Or(pos, 0));
+                self.expr(b)?;
+                self.patch(jump);
+            }
+            Not(ref value) => {
+                self.expr(value)?;
+                self.push(Instr::Not(pos));
+            }
+            Comparison(ref first, ref rest) => {
+                self.expr(first)?;
+                let mut ends = Vec::new();
+                for (idx, &(op, ref value)) in rest.iter().enumerate() {
+                    ends.push(self.push(Instr::CompareLeft(pos, 0)));
+                    self.expr(value)?;
+                    ends.push(self.push(Instr::Compare {
+                        op,
+                        position: pos,
+                        last: idx == rest.len() - 1,
+                        end: 0,
+                    }));
+                }
+                for idx in ends {
+                    self.patch(idx);
+                }
+            }
+            List(ref items) => {
+                for item in items {
+                    self.expr(item)?;
+                }
+                self.push(Instr::List(items.len()));
+            }
+            Dict(ref pairs) => {
+                self.push(Instr::NewDict);
+                for (key, value) in pairs {
+                    self.expr(key)?;
+                    self.expr(value)?;
+                    self.push(Instr::Insert(key.position.0));
+                }
+                self.push(Instr::EndDict);
+            }
+            Filter(..) => {
+                return Err(ParseErrorEnum::Unsupported(pos,
+                    "filter expression").into());
+            }
+            Range(..) => {
+                return Err(ParseErrorEnum::Unsupported(pos,
+                    "range expression").into());
+            }
+        }
+        Ok(())
+    }
+    fn math(&mut self, op: MathOp, a: &Expr, b: &Expr)
+        -> Result<(), ParseError>
+    {
+        self.expr(a)?;
+        self.expr(b)?;
+        self.push(Instr::Math(op, a.position.0, b.position.0));
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use {Parser, Template, Options, ParseError};
+
+
+quick_error! {
+    /// Error loading template into the `TemplateCache`
+    #[derive(Debug)]
+    pub enum CacheError {
+        /// Error reading template or its dependency
+        Io(path: PathBuf, err: io::Error) {
+            description("error reading template")
+            display("error reading {:?}: {}", path, err)
+        }
+        /// Error parsing template
+        Parse(path: PathBuf, err: ParseError) {
+            description("error parsing template")
+            display("error parsing {:?}: {}", path, err)
+        }
+    }
+}
+
+/// A cache of parsed templates keyed by path
+///
+/// When template is requested, modification times and sizes of the
+/// template file and its dependencies (see `add_dependency`) are checked
+/// and the template is reparsed if contents of any of them has changed.
+/// Files modified less than a second before the previous check are always
+/// compared by contents, as file system timestamps may be too coarse to
+/// notice the change. Use `check_changes(false)` to skip the check (i.e. in
+/// production).
+///
+/// Cache can be shared between threads. Note that lock is held while a
+/// template is being parsed.
+pub struct TemplateCache {
+    parser: Parser,
+    options: Options,
+    check_changes: bool,
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+struct Entry {
+    /// `None` when the last reload failed, dependencies are kept anyway
+    template: Option<Arc<Template>>,
+    /// Template file itself and then its dependencies
+    files: Vec<Stamp>,
+}
+
+struct Stamp {
+    path: PathBuf,
+    mtime: Option<SystemTime>,
+    len: u64,
+    /// File was read less than a second after it was modified, so it might
+    /// be modified again without changing the mtime
+    recent: bool,
+    hash: u64,
+}
+
+fn hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn is_recent(mtime: Option<SystemTime>) -> bool {
+    match mtime.map(|x| x.elapsed()) {
+        Some(Ok(age)) => age < Duration::from_secs(1),
+        // unknown or in the future
+        _ => true,
+    }
+}
+
+impl Stamp {
+    fn new(path: &Path) -> Result<(Stamp, Vec<u8>), CacheError> {
+        let io_err = |e: io::Error| CacheError::Io(path.to_path_buf(), e);
+        let meta = fs::metadata(path).map_err(&io_err)?;
+        let mut data = Vec::new();
+        fs::File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut data))
+            .map_err(&io_err)?;
+        let mtime = meta.modified().ok();
+        let stamp = Stamp {
+            path: path.to_path_buf(),
+            mtime,
+            len: meta.len(),
+            recent: is_recent(mtime),
+            hash: hash(&data),
+        };
+        Ok((stamp, data))
+    }
+    /// Returns true if file contents has changed since the last check
+    ///
+    /// Contents are compared only if mtime or size has changed, or if the
+    /// file was modified shortly before the last check.
+    fn changed(&mut self) -> Result<bool, CacheError> {
+        let meta = fs::metadata(&self.path)
+            .map_err(|e| CacheError::Io(self.path.to_path_buf(), e))?;
+        if !self.recent && meta.modified().ok() == self.mtime &&
+            meta.len() == self.len
+        {
+            return Ok(false);
+        }
+        let (stamp, _) = Stamp::new(&self.path)?;
+        let changed = stamp.hash != self.hash;
+        *self = stamp;
+        Ok(changed)
+    }
+}
+
+impl TemplateCache {
+    /// Create a cache that parses templates with the `parser`
+    pub fn new(parser: Parser) -> TemplateCache {
+        TemplateCache::with_options(parser, Options::new())
+    }
+    /// Create a cache that parses templates with some predefined options
+    pub fn with_options(parser: Parser, options: Options) -> TemplateCache {
+        TemplateCache {
+            parser,
+            options,
+            check_changes: true,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Enable or disable checking whether files have changed (default: on)
+    ///
+    /// When disabled, each template is parsed once and kept forever (or
+    /// until `invalidate` is called).
+    pub fn check_changes(&mut self, value: bool) -> &mut Self {
+        self.check_changes = value;
+        self
+    }
+    /// Get parsed template, parsing (or reparsing) it if needed
+    pub fn get<P: AsRef<Path>>(&self, path: P)
+        -> Result<Arc<Template>, CacheError>
+    {
+        let path = path.as_ref();
+        let mut entries = self.entries.lock().expect("cache is not poisoned");
+        if let Some(entry) = entries.get_mut(path) {
+            if !self.check_changes || !entry.changed()? {
+                if let Some(ref template) = entry.template {
+                    return Ok(template.clone());
+                }
+            }
+        }
+        let (stamp, template) = match self.load(path) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                // keep dependencies, so that template is reloaded when they
+                // change after the error is fixed
+                if let Some(entry) = entries.get_mut(path) {
+                    entry.template = None;
+                }
+                return Err(e);
+            }
+        };
+        let template = Arc::new(template);
+        match entries.get_mut(path) {
+            Some(entry) => {
+                // dependencies are added again by the application
+                entry.template = Some(template.clone());
+                entry.files = vec![stamp];
+            }
+            None => {
+                entries.insert(path.to_path_buf(), Entry {
+                    template: Some(template.clone()),
+                    files: vec![stamp],
+                });
+            }
+        }
+        Ok(template)
+    }
+    /// Mark `path` as depending on the `dependency` file
+    ///
+    /// Template is reparsed when the dependency changes or is removed. This
+    /// is useful when template is built from several files by the
+    /// application. Template is loaded if it isn't in the cache yet.
+    ///
+    /// Dependencies are dropped when template is reparsed, so that list
+    /// is up to date when the application adds them again after `get`.
+    pub fn add_dependency<P, D>(&self, path: P, dependency: D)
+        -> Result<(), CacheError>
+        where P: AsRef<Path>, D: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let dependency = dependency.as_ref();
+        self.get(path)?;
+        let (stamp, _) = Stamp::new(dependency)?;
+        let mut entries = self.entries.lock().expect("cache is not poisoned");
+        if let Some(entry) = entries.get_mut(path) {
+            entry.files.retain(|f| f.path != dependency);
+            entry.files.push(stamp);
+        }
+        Ok(())
+    }
+    /// Remove template from the cache, so it's parsed on next access
+    pub fn invalidate<P: AsRef<Path>>(&self, path: P) {
+        self.entries.lock().expect("cache is not poisoned")
+            .remove(path.as_ref());
+    }
+    fn load(&self, path: &Path) -> Result<(Stamp, Template), CacheError> {
+        let (stamp, data) = Stamp::new(path)?;
+        let data = String::from_utf8(data)
+            .map_err(|e| CacheError::Io(path.to_path_buf(),
+                io::Error::new(io::ErrorKind::InvalidData, e)))?;
+        let template = self.parser.parse_with_options(&self.options, &data)
+            .map_err(|e| CacheError::Parse(path.to_path_buf(), e))?;
+        Ok((stamp, template))
+    }
+}
+
+impl Entry {
+    fn changed(&mut self) -> Result<bool, CacheError> {
+        let mut changed = self.files[0].changed()?;
+        for dep in &mut self.files[1..] {
+            // check all of them to refresh stamps, and reload if dependency
+            // is removed, so it's dropped from the list
+            changed |= dep.changed().unwrap_or(true);
+        }
+        Ok(changed)
+    }
+}
@@ -0,0 +1,298 @@
+use std::fmt::Write;
+
+use grammar::{Template, Body, Statement, Expr};
+use grammar::{AssignTarget, CmpOperator, OutputMode};
+use preparser::Syntax;
+use parse_error::{ParseError, ParseErrorEnum};
+use validators::{Filter, escapes_name};
+use Pos;
+
+
+struct Generator {
+    buf: String,
+    indent: usize,
+}
+
+fn pos(pos: Pos) -> String {
+    format!("rt::Pos {{ line: {}, column: {} }}", pos.line, pos.column)
+}
+
+fn mode(mode: OutputMode) -> &'static str {
+    match mode {
+        OutputMode::Strip => "rt::OutputMode::Strip",
+        OutputMode::Space => "rt::OutputMode::Space",
+        OutputMode::Preserve => "rt::OutputMode::Preserve",
+    }
+}
+
+fn operator(op: CmpOperator) -> &'static str {
+    match op {
+        CmpOperator::Eq => "rt::CmpOperator::Eq",
+        CmpOperator::Neq => "rt::CmpOperator::Neq",
+        CmpOperator::LessEq => "rt::CmpOperator::LessEq",
+        CmpOperator::Less => "rt::CmpOperator::Less",
+        CmpOperator::GreaterEq => "rt::CmpOperator::GreaterEq",
+        CmpOperator::Greater => "rt::CmpOperator::Greater",
+    }
+}
+
+fn filter(filter: &Filter) -> Result<String, ParseError> {
+    Ok(match *filter {
+        Filter::NoFilter => "rt::Filter::NoFilter".to_string(),
+        Filter::Validate(ref re) => format!("rt::validate({:?})", re.as_str()),
+        Filter::Escape(ref esc) => {
+            format!("rt::escape({:?})", escapes_name(esc))
+        }
+        Filter::Constraints(ref c) => {
+            format!("rt::constraints({:?}, {:?}, {:?}, {:?}, &{:?}, {:?})",
+                c.regex.as_ref().map(|x| x.as_str()),
+                c.min_length, c.max_length,
+                c.categories.join(" "), c.forbid,
+                escapes_name(&c.escapes))
+        }
+        Filter::Custom(ref c) => {
+            return Err(ParseErrorEnum::CustomEscaper(c.name.clone()).into());
+        }
+    })
+}
+
+fn unsupported(e: &Expr, what: &'static str) -> ParseError {
+    ParseErrorEnum::Unsupported(e.position.0, what).into()
+}
+
+fn math(op: &str, a: &Expr, b: &Expr) -> Result<String, ParseError> {
+    Ok(format!(
+        "{{ let a = {}; let b = {}; rt::math(r, rt::{}, a, {}, b, {}) }}",
+        expr(a)?, expr(b)?, op, pos(a.position.0), pos(b.position.0)))
+}
+
+/// Returns rust expression that evaluates `e` into a `VarRef`
+///
+/// Every subexpression is evaluated in its own block, so that mutable
+/// borrows of the renderer don't overlap.
+fn expr(e: &Expr) -> Result<String, ParseError> {
+    use grammar::ExprCode::*;
+    let p = pos(e.position.0);
+    Ok(match e.code {
+        Str(ref s) => format!("rt::constant(r, &rt::Str({:?}))", s),
+        Int(i) => format!("rt::constant(r, &{}i64)", i),
+        Float(f) => format!("rt::constant(r, &{:?}f64)", f),
+        Var(ref name) => format!("rt::var(r, ctx, {:?}, {})", name, p),
+        Attr(ref v, ref name) => {
+            format!("{{ let v = {}; rt::attr(r, v, {:?}, {}) }}",
+                expr(v)?, name, p)
+        }
+        Item(ref v, ref index) => {
+            format!("{{ let v = {}; let i = {}; rt::item(r, v, i, {}) }}",
+                expr(v)?, expr(index)?, p)
+        }
+        And(ref a, ref b) => {
+            format!("{{ let a = {}; rt::and(r, a, {}, |r| {}) }}",
+                expr(a)?, p, expr(b)?)
+        }
+        Or(ref a, ref b) => {
+            format!("{{ let a = {}; rt::or(r, a, {}, |r| {}) }}",
+                expr(a)?, p, expr(b)?)
+        }
+        Not(ref v) => {
+            format!("{{ let v = {}; rt::not(r, v, {}) }}", expr(v)?, p)
+        }
+        Comparison(ref first, ref rest) => {
+            let mut items = String::new();
+            for (i, &(op, ref e)) in rest.iter().enumerate() {
+                write!(&mut items, "{} => ({}, {}), ",
+                    i, operator(op), expr(e)?).unwrap();
+            }
+            format!("{{ let v = {}; rt::comparison(r, v, {}, {}, \
+                |r, i| match i {{ {}_ => unreachable!() }}) }}",
+                expr(first)?, p, rest.len(), items)
+        }
+        List(ref items) => {
+            let mut buf = String::from("{ let mut items = Vec::new(); ");
+            for item in items {
+                write!(&mut buf, "items.push({}); ", expr(item)?).unwrap();
+            }
+            buf.push_str("rt::list(items) }");
+            buf
+        }
+        Dict(ref pairs) => {
+            let mut buf = String::from("{ let mut d = rt::Dict::new(); ");
+            for (k, v) in pairs {
+                write!(&mut buf,
+                    "{{ let k = {}; let v = {}; \
+                     rt::insert(r, &mut d, k, {}, v); }} ",
+                    expr(k)?, expr(v)?, pos(k.position.0)).unwrap();
+            }
+            buf.push_str("rt::dict(d) }");
+            buf
+        }
+        Add(ref a, ref b) => math("add", a, b)?,
+        Sub(ref a, ref b) => math("sub", a, b)?,
+        Mul(ref a, ref b) => math("mul", a, b)?,
+        Div(ref a, ref b) => math("div", a, b)?,
+        Mod(ref a, ref b) => math("modulo", a, b)?,
+        Filter(..) => return Err(unsupported(e, "filter expression")),
+        Range(..) => return Err(unsupported(e, "range expression")),
+    })
+}
+
+impl Generator {
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.indent {
+            self.buf.push_str("    ");
+        }
+        self.buf.push_str(line);
+        self.buf.push('\n');
+    }
+    fn scope(&mut self, body: &Body) -> Result<(), ParseError> {
+        self.line("let mut sub = ctx.sub();");
+        self.line("let ctx = &mut sub;");
+        self.body(body)
+    }
+    fn body(&mut self, body: &Body) -> Result<(), ParseError> {
+        for st in &body.statements {
+            self.statement(st)?;
+        }
+        Ok(())
+    }
+    fn condition(&mut self, prefix: &str, cond: &Expr)
+        -> Result<(), ParseError>
+    {
+        let line = format!("{}{{ let v = {}; rt::condition(r, &v, {}) }} {{",
+            prefix, expr(cond)?, pos(cond.position.0));
+        self.line(&line);
+        Ok(())
+    }
+    fn statement(&mut self, st: &Statement) -> Result<(), ParseError> {
+        use grammar::StatementCode::*;
+        match st.code {
+            OutputRaw(ref text) => {
+                self.line(&format!("rt::raw(r, {:?});", text));
+            }
+            Joiner => self.line("rt::joiner(r);"),
+            WhitespaceControl { left_ws, right_ws } => {
+                self.line(&format!("rt::whitespace_control(r, {}, {});",
+                    mode(left_ws), mode(right_ws)));
+            }
+            Output { left_ws, ref expr, ref validator, right_ws } => {
+                let validator = match *validator {
+                    Some(ref name) => format!("Some({:?})", name),
+                    None => "None".to_string(),
+                };
+                self.line("{");
+                self.indent += 1;
+                self.line(&format!("let v = {};", self::expr(expr)?));
+                self.line(&format!("rt::output(r, {}, &v, {}, {}, {})?;",
+                    mode(left_ws), validator, pos(st.position.0),
+                    mode(right_ws)));
+                self.indent -= 1;
+                self.line("}");
+            }
+            Alias { target: AssignTarget::Var(ref name), ref value } => {
+                self.line("{");
+                self.indent += 1;
+                self.line(&format!("let v = {};", expr(value)?));
+                self.line(&format!("rt::set(ctx, {:?}, v);", name));
+                self.indent -= 1;
+                self.line("}");
+            }
+            // unsupported by grammar yet
+            Alias { target: AssignTarget::Pair(..), .. } => unreachable!(),
+            Cond { ref conditional, ref otherwise, .. } => {
+                let mut prefix = "if ";
+                for (cond, body) in conditional {
+                    self.condition(prefix, cond)?;
+                    self.indent += 1;
+                    self.scope(body)?;
+                    self.indent -= 1;
+                    prefix = "} else if ";
+                }
+                if !otherwise.statements.is_empty() {
+                    self.line("} else {");
+                    self.indent += 1;
+                    self.scope(otherwise)?;
+                    self.indent -= 1;
+                }
+                self.line("}");
+            }
+            Loop { ref target, ref iterator, ref filter, ref body, .. } => {
+                self.line("{");
+                self.indent += 1;
+                self.line(&format!("let v = {};", expr(iterator)?));
+                match *target {
+                    AssignTarget::Var(ref name) => {
+                        self.line(&format!(
+                            "rt::for_each(r, v, {}, |r, item| {{",
+                            pos(iterator.position.0)));
+                        self.indent += 1;
+                        self.line("let mut sub = ctx.sub();");
+                        self.line("let ctx = &mut sub;");
+                        self.line(&format!("rt::set(ctx, {:?}, item);", name));
+                    }
+                    AssignTarget::Pair(ref a, ref b) => {
+                        self.line(&format!(
+                            "rt::for_each_pair(r, v, {}, |r, a, b| {{",
+                            pos(iterator.position.0)));
+                        self.indent += 1;
+                        self.line("let mut sub = ctx.sub();");
+                        self.line("let ctx = &mut sub;");
+                        self.line(&format!("rt::set(ctx, {:?}, a);", a));
+                        self.line(&format!("rt::set(ctx, {:?}, b);", b));
+                    }
+                }
+                if let Some(ref filter) = *filter {
+                    self.condition("if ", filter)?;
+                    self.indent += 1;
+                    self.line("return Ok(());");
+                    self.indent -= 1;
+                    self.line("}");
+                }
+                self.body(body)?;
+                self.line("Ok(())");
+                self.indent -= 1;
+                self.line("})?;");
+                self.indent -= 1;
+                self.line("}");
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn generate(name: &str, tpl: &Template) -> Result<String, ParseError> {
+    let options = &tpl.options;
+    let mut filters = options.filters.iter().collect::<Vec<_>>();
+    filters.sort_by_key(|&(name, _)| name);
+    let filters = filters.iter()
+        .map(|&(name, f)| Ok(format!("({:?}, {})", name, filter(f)?)))
+        .collect::<Result<Vec<_>, ParseError>>()?;
+
+    let mut gen = Generator {
+        buf: String::new(),
+        indent: 0,
+    };
+    gen.line("#[allow(unused_variables, unused_mut, unused_braces)]");
+    gen.line(&format!("pub fn {}(context: &::trimmer::Context)", name));
+    gen.line("    -> Result<String, ::trimmer::RenderError>");
+    gen.line("{");
+    gen.indent += 1;
+    gen.line("use ::trimmer::runtime as rt;");
+    gen.line("thread_local! {");
+    gen.line(&format!(
+        "    static TEMPLATE: ::trimmer::Template = rt::template({}, {:?}, \
+        {:?}, {}, vec![{}]);",
+        options.syntax == Syntax::Oneline, options.new_line_at_eof,
+        (options.curly, options.square, options.round),
+        filter(&options.default_filter)?,
+        filters.join(", ")));
+    gen.line("}");
+    gen.line("TEMPLATE.with(|tpl| rt::render(tpl, context, |r, ctx| {");
+    gen.indent += 1;
+    gen.body(&tpl.body)?;
+    gen.line("Ok(())");
+    gen.indent -= 1;
+    gen.line("}))");
+    gen.indent -= 1;
+    gen.line("}");
+    Ok(gen.buf)
+}
@@ -8,10 +8,10 @@ use std::i64;
 ///
 /// Use `into()` conversion to make the value.
 #[derive(Debug)]  // TODO(tailhook) make normal debug
-pub struct Comparable<'a>(ComparableInner<'a>);
+pub struct Comparable<'a>(pub(crate) ComparableInner<'a>);
 
 #[derive(Debug)]  // TODO(tailhook) make normal debug
-enum ComparableInner<'a> {
+pub(crate) enum ComparableInner<'a> {
     Bool(bool),
     I64(i64),
     U64(u64),
@@ -1,3 +1,19 @@
+use std::fmt::Write;
+
+use compare::Comparable;
+use render_error::DataError;
+use vars::Val;
+use {Var, Variable};
+
+fn is_alphanumeric(c: char) -> bool {
+    c < '\u{80}' && c.is_alphanumeric()
+}
+
+fn is_control(c: char) -> bool {
+    // C0 control characters and DEL
+    c < ' ' || c == '\x7f'
+}
+
 pub fn html_entities(dest: &mut String, src: &str) {
     for c in src.chars() {
         match c {
@@ -26,3 +42,318 @@ pub fn quoted_shell_argument(dest: &mut String, src: &str) {
     }
     dest.push('\'');
 }
+
+pub fn shell_word(dest: &mut String, src: &str) {
+    // Leave words that mean the same in any shell unquoted
+    let safe = !src.is_empty() && src.chars().all(|c| is_alphanumeric(c) ||
+        matches!(c, '_' | '-' | '.' | '/' | ':' | ',' | '+' | '@'));
+    if safe {
+        dest.push_str(src);
+    } else {
+        quoted_shell_argument(dest, src);
+    }
+}
+
+pub fn shell_double_quoted(dest: &mut String, src: &str) {
+    // Only these are special inside double quotes. Note: `!` is special
+    // only in interactive bash, and escaping it would keep the backslash
+    for c in src.chars() {
+        match c {
+            '$' | '`' | '"' | '\\' => {
+                dest.push('\\');
+                dest.push(c);
+            }
+            _ => dest.push(c),
+        }
+    }
+}
+
+pub fn ansi_c_shell_argument(dest: &mut String, src: &str) {
+    dest.push_str("$'");
+    for c in src.chars() {
+        match c {
+            '\'' => dest.push_str("\\'"),
+            '\\' => dest.push_str("\\\\"),
+            '\n' => dest.push_str("\\n"),
+            '\r' => dest.push_str("\\r"),
+            '\t' => dest.push_str("\\t"),
+            _ if is_control(c) => {
+                write!(dest, "\\x{:02x}", c as u32).unwrap();
+            }
+            _ => dest.push(c),
+        }
+    }
+    dest.push('\'');
+}
+
+pub fn indent(dest: &mut String, src: &str) {
+    // Continuation lines are aligned to the column where the value starts,
+    // `dest` is the output so far, so the column is the text since the last
+    // newline. Tabs are kept as is to align correctly with any tab width.
+    let line_start = dest.rfind('\n').map(|x| x+1).unwrap_or(0);
+    let prefix = dest[line_start..].chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    let mut lines = src.split('\n');
+    if let Some(first) = lines.next() {
+        dest.push_str(first);
+    }
+    for line in lines {
+        dest.push('\n');
+        if !line.is_empty() {
+            dest.push_str(&prefix);
+            dest.push_str(line);
+        }
+    }
+}
+
+pub fn json_string(dest: &mut String, src: &str) {
+    dest.push('"');
+    let mut prev = '\0';
+    for c in src.chars() {
+        match c {
+            '"' => dest.push_str("\\\""),
+            '\\' => dest.push_str("\\\\"),
+            '\n' => dest.push_str("\\n"),
+            '\r' => dest.push_str("\\r"),
+            '\t' => dest.push_str("\\t"),
+            '\x08' => dest.push_str("\\b"),
+            '\x0c' => dest.push_str("\\f"),
+            // So string can't close a `<script>` tag
+            '/' if prev == '<' => dest.push_str("\\/"),
+            // Line separators are valid in JSON but not in javascript
+            _ if is_control(c) || c == '\u{2028}' || c == '\u{2029}' => {
+                write!(dest, "\\u{:04x}", c as u32).unwrap();
+            }
+            _ => dest.push(c),
+        }
+        prev = c;
+    }
+    dest.push('"');
+}
+
+pub fn json_value(dest: &mut String, var: &Variable)
+    -> Result<(), DataError>
+{
+    use compare::ComparableInner::*;
+
+    match var.typename() {
+        "undefined" | "null" => {
+            dest.push_str("null");
+            return Ok(());
+        }
+        _ => {}
+    }
+    if let Ok(Comparable(value)) = var.as_comparable() {
+        match value {
+            Bool(true) => dest.push_str("true"),
+            Bool(false) => dest.push_str("false"),
+            I64(x) => write!(dest, "{}", x).unwrap(),
+            U64(x) => write!(dest, "{}", x).unwrap(),
+            F64(x) if x.is_finite() => write!(dest, "{}", x).unwrap(),
+            F64(_) => return Err(DataError::OutputError(var.typename())),
+            Str(x) => json_string(dest, x),
+            String(ref x) => json_string(dest, x),
+        }
+        return Ok(());
+    }
+    if let Ok(pairs) = var.iterate_pairs() {
+        dest.push('{');
+        for (idx, (key, value)) in pairs.enumerate() {
+            if idx > 0 {
+                dest.push(',');
+            }
+            let key = inner(&key);
+            match key.as_str_key() {
+                Ok(key) => json_string(dest, key),
+                Err(_) => json_string(dest, &key.output()?.0.to_string()),
+            }
+            dest.push(':');
+            json_value(dest, inner(&value))?;
+        }
+        dest.push('}');
+        return Ok(());
+    }
+    if let Ok(items) = var.iterate() {
+        dest.push('[');
+        for (idx, item) in items.enumerate() {
+            if idx > 0 {
+                dest.push(',');
+            }
+            json_value(dest, inner(&item))?;
+        }
+        dest.push(']');
+        return Ok(());
+    }
+    json_string(dest, &var.output()?.0.to_string());
+    Ok(())
+}
+
+fn inner<'a, 'render>(var: &'a Var<'a, 'render>)
+    -> &'a (Variable<'render> + 'render)
+{
+    match var.0 {
+        Val::Ref(x) => x,
+        Val::Rc(ref x) => &**x,
+    }
+}
+
+fn percent_encode(dest: &mut String, src: &str, keep: &[u8], plus: bool) {
+    for &b in src.as_bytes() {
+        match b {
+            _ if is_alphanumeric(b as char) => dest.push(b as char),
+            _ if keep.contains(&b) => dest.push(b as char),
+            b' ' if plus => dest.push('+'),
+            _ => write!(dest, "%{:02X}", b).unwrap(),
+        }
+    }
+}
+
+pub fn url_path_segment(dest: &mut String, src: &str) {
+    // Only unreserved chars (RFC 3986), so `/` and `?` are escaped
+    percent_encode(dest, src, b"-._~", false)
+}
+
+pub fn url_query_component(dest: &mut String, src: &str) {
+    // Query may contain `/`, `?`, `:` and `@` (RFC 3986), but `&`, `=`,
+    // `+`, `;` and `#` separate or change parameters, so they are escaped
+    percent_encode(dest, src, b"-._~/?:@!$()*,", false)
+}
+
+pub fn url_form(dest: &mut String, src: &str) {
+    // application/x-www-form-urlencoded as defined in WHATWG URL standard
+    percent_encode(dest, src, b"*-._", true)
+}
+
+pub fn url(dest: &mut String, src: &str) {
+    // Keeps reserved chars and percent-encoded sequences intact
+    percent_encode(dest, src, b"-._~:/?#[]@!$&'()*+,;=%", false)
+}
+
+pub fn safe_url(dest: &mut String, src: &str) {
+    let scheme_end = src.find(|c| matches!(c, ':' | '/' | '?' | '#'));
+    if let Some(end) = scheme_end {
+        if src[end..].starts_with(':') {
+            let scheme = src[..end].to_lowercase();
+            if !matches!(&scheme[..], "http" | "https" | "mailto") {
+                // An URL that does nothing, instead of `javascript:` and
+                // similar things
+                dest.push_str("about:invalid#unsafe-url");
+                return;
+            }
+        }
+    }
+    url(dest, src)
+}
+
+pub fn css(dest: &mut String, src: &str) {
+    // Leaves identifiers, numbers, colors and lists of them unquoted,
+    // anything else is a hex escape that can't end a string or a rule
+    for c in src.chars() {
+        match c {
+            _ if is_alphanumeric(c) => dest.push(c),
+            '-' | '_' | '.' | '#' | '%' | ',' | ' ' => dest.push(c),
+            _ if c as u32 >= 0x80 => dest.push(c),
+            _ => write!(dest, "\\{:x} ", c as u32).unwrap(),
+        }
+    }
+}
+
+pub fn yaml_string(dest: &mut String, src: &str) {
+    // Double-quoted scalar is the only style that can represent any string
+    dest.push('"');
+    for c in src.chars() {
+        match c {
+            '"' => dest.push_str("\\\""),
+            '\\' => dest.push_str("\\\\"),
+            '\0' => dest.push_str("\\0"),
+            '\n' => dest.push_str("\\n"),
+            '\r' => dest.push_str("\\r"),
+            '\t' => dest.push_str("\\t"),
+            '\u{85}' => dest.push_str("\\N"),
+            '\u{2028}' => dest.push_str("\\L"),
+            '\u{2029}' => dest.push_str("\\P"),
+            _ if c.is_control() => {
+                write!(dest, "\\x{:02x}", c as u32).unwrap();
+            }
+            '\u{feff}' => dest.push_str("\\ufeff"),
+            _ => dest.push(c),
+        }
+    }
+    dest.push('"');
+}
+
+pub fn toml_string(dest: &mut String, src: &str) {
+    // Basic string, i.e. the double-quoted one
+    dest.push('"');
+    for c in src.chars() {
+        match c {
+            '"' => dest.push_str("\\\""),
+            '\\' => dest.push_str("\\\\"),
+            '\n' => dest.push_str("\\n"),
+            '\r' => dest.push_str("\\r"),
+            '\t' => dest.push_str("\\t"),
+            '\x08' => dest.push_str("\\b"),
+            '\x0c' => dest.push_str("\\f"),
+            _ if is_control(c) => {
+                write!(dest, "\\u{:04x}", c as u32).unwrap();
+            }
+            _ => dest.push(c),
+        }
+    }
+    dest.push('"');
+}
+
+pub fn nginx_string(dest: &mut String, src: &str) -> Result<(), DataError> {
+    // There is no way to escape `$` in nginx, so variables can't be
+    // injected only if there are no dollar signs at all
+    if src.contains('$') {
+        return Err(DataError::ValidationError(src.to_string(),
+            "contains `$` which can't be escaped in nginx".to_string()));
+    }
+    dest.push('"');
+    for c in src.chars() {
+        match c {
+            '"' => dest.push_str("\\\""),
+            '\\' => dest.push_str("\\\\"),
+            '\n' => dest.push_str("\\n"),
+            '\r' => dest.push_str("\\r"),
+            '\t' => dest.push_str("\\t"),
+            _ => dest.push(c),
+        }
+    }
+    dest.push('"');
+    Ok(())
+}
+
+pub fn systemd_string(dest: &mut String, src: &str) {
+    // Quoted word with C-style escapes, `%` starts a specifier
+    dest.push('"');
+    for c in src.chars() {
+        match c {
+            '"' => dest.push_str("\\\""),
+            '\\' => dest.push_str("\\\\"),
+            '%' => dest.push_str("%%"),
+            '\n' => dest.push_str("\\n"),
+            '\r' => dest.push_str("\\r"),
+            '\t' => dest.push_str("\\t"),
+            _ if is_control(c) => {
+                write!(dest, "\\x{:02x}", c as u32).unwrap();
+            }
+            _ => dest.push(c),
+        }
+    }
+    dest.push('"');
+}
+
+pub fn csv_field(dest: &mut String, src: &str) {
+    // RFC 4180, always quoted so separators and newlines are safe
+    dest.push('"');
+    for c in src.chars() {
+        match c {
+            '"' => dest.push_str("\"\""),
+            _ => dest.push(c),
+        }
+    }
+    dest.push('"');
+}
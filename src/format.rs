@@ -0,0 +1,430 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+use combine::StreamOnce;
+
+use grammar::{Body, Expr, ExprCode, CmpOperator, AssignTarget, OutputMode};
+use preparser::Syntax;
+use tokenizer::{Tokenizer, Kind, Delimiters};
+use {Options, Pos};
+
+
+/// The `## keyword` line as written in the source
+struct Header<'a> {
+    keyword: &'a str,
+    /// Whitespace control sign `-` or `+` (or empty)
+    sign: &'a str,
+    indent: &'a str,
+    position: Pos,
+    comments: Vec<&'a str>,
+}
+
+/// Parts of the source that are not represented in the `Body`
+struct Source<'a> {
+    /// Comments and early statements which are copied verbatim
+    verbatim: VecDeque<(Pos, &'a str)>,
+    headers: VecDeque<Header<'a>>,
+    joiners: VecDeque<&'a str>,
+}
+
+struct Formatter<'a> {
+    buf: String,
+    source: Source<'a>,
+    delimiters: &'a Delimiters,
+    indent_syntax: bool,
+}
+
+fn scan<'a>(tok: &'a Tokenizer, defaults: &'a Delimiters,
+    delimiters: &'a Delimiters, data: &'a str)
+    -> Source<'a>
+{
+    let mut source = Source {
+        verbatim: VecDeque::new(),
+        headers: VecDeque::new(),
+        joiners: VecDeque::new(),
+    };
+    let mut stream = tok.scan(data, defaults, delimiters);
+    let mut header = None;
+    loop {
+        let start = stream.position();
+        let offset = stream.offset();
+        let tok = match stream.uncons() {
+            Ok(tok) => tok,
+            Err(_) => break,
+        };
+        let text = &data[offset..stream.offset()];
+        match tok.kind {
+            Kind::Eof => break,
+            Kind::StStart => {
+                let indent = tok.value.len() - tok.value.trim_left().len();
+                let rest = &tok.value[indent+delimiters.statement.len()..];
+                let keyword = rest.trim_left_matches(&['+', '-'][..]);
+                header = Some(Header {
+                    keyword: keyword.trim(),
+                    sign: &rest[..rest.len() - keyword.len()],
+                    indent: &tok.value[..indent],
+                    position: start,
+                    comments: Vec::new(),
+                });
+            }
+            Kind::Newline => {
+                if let Some(header) = header.take() {
+                    source.headers.push_back(header);
+                }
+            }
+            Kind::Comment => match header {
+                Some(ref mut header) => header.comments.push(text.trim()),
+                None => source.verbatim.push_back((start, text)),
+            },
+            Kind::EarlyStatement | Kind::ControlComment
+            => source.verbatim.push_back((start, text)),
+            Kind::LineJoiner => source.joiners.push_back(text),
+            _ => {}
+        }
+    }
+    source
+}
+
+fn precedence(code: &ExprCode) -> u8 {
+    use grammar::ExprCode::*;
+    match *code {
+        Filter(..) | Range(..) => 0,
+        Or(..) => 1,
+        And(..) => 2,
+        Comparison(..) => 3,
+        Add(..) | Sub(..) => 4,
+        Mul(..) | Div(..) | Mod(..) => 5,
+        Not(..) => 6,
+        Attr(..) | Item(..) => 7,
+        Str(..) | Int(..) | Float(..) | Var(..) | List(..) | Dict(..) => 8,
+    }
+}
+
+fn write_str(buf: &mut String, value: &str) {
+    let quote = if value.contains('"') && !value.contains('\'') {
+        '\''
+    } else {
+        '"'
+    };
+    buf.push(quote);
+    for ch in value.chars() {
+        match ch {
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            '\x08' => buf.push_str("\\b"),
+            '\x0c' => buf.push_str("\\f"),
+            ch if ch == quote => {
+                buf.push('\\');
+                buf.push(ch);
+            }
+            ch => buf.push(ch),
+        }
+    }
+    buf.push(quote);
+}
+
+fn write_expr(buf: &mut String, expr: &Expr, min_precedence: u8) {
+    use grammar::ExprCode::*;
+
+    let parens = precedence(&expr.code) < min_precedence;
+    if parens {
+        buf.push('(');
+    }
+    match expr.code {
+        Str(ref s) => write_str(buf, s),
+        Int(x) => write!(buf, "{}", x).unwrap(),
+        Float(x) => {
+            let start = buf.len();
+            write!(buf, "{}", x).unwrap();
+            if !buf[start..].contains('.') {
+                buf.push_str(".0");
+            }
+        }
+        Var(ref name) => buf.push_str(name),
+        Attr(ref e, ref name) => {
+            write_expr(buf, e, 7);
+            buf.push('.');
+            buf.push_str(name);
+        }
+        Item(ref e, ref item) => {
+            write_expr(buf, e, 7);
+            buf.push('[');
+            write_expr(buf, item, 0);
+            buf.push(']');
+        }
+        Filter(ref e, ref filter) => {
+            write_expr(buf, e, 1);
+            buf.push_str(" | ");
+            write_expr(buf, filter, 1);
+        }
+        Range(ref a, ref b) => {
+            if let Some(ref a) = *a {
+                write_expr(buf, a, 1);
+            }
+            buf.push_str("..");
+            if let Some(ref b) = *b {
+                write_expr(buf, b, 1);
+            }
+        }
+        Not(ref e) => {
+            buf.push_str("not ");
+            write_expr(buf, e, 7);
+        }
+        And(ref a, ref b) => write_binary(buf, a, "and", b, 2),
+        Or(ref a, ref b) => write_binary(buf, a, "or", b, 1),
+        Add(ref a, ref b) => write_binary(buf, a, "+", b, 4),
+        Sub(ref a, ref b) => write_binary(buf, a, "-", b, 4),
+        Mul(ref a, ref b) => write_binary(buf, a, "*", b, 5),
+        Div(ref a, ref b) => write_binary(buf, a, "/", b, 5),
+        Mod(ref a, ref b) => write_binary(buf, a, "%", b, 5),
+        Comparison(ref first, ref rest) => {
+            write_expr(buf, first, 4);
+            for (op, e) in rest {
+                buf.push_str(match *op {
+                    CmpOperator::Eq => " == ",
+                    CmpOperator::Neq => " != ",
+                    CmpOperator::LessEq => " <= ",
+                    CmpOperator::Less => " < ",
+                    CmpOperator::GreaterEq => " >= ",
+                    CmpOperator::Greater => " > ",
+                });
+                write_expr(buf, e, 4);
+            }
+        }
+        List(ref items) => {
+            buf.push('[');
+            for (idx, item) in items.iter().enumerate() {
+                if idx > 0 {
+                    buf.push_str(", ");
+                }
+                write_expr(buf, item, 0);
+            }
+            buf.push(']');
+        }
+        Dict(ref pairs) => {
+            buf.push('{');
+            for (idx, (key, value)) in pairs.iter().enumerate() {
+                if idx > 0 {
+                    buf.push_str(", ");
+                }
+                write_expr(buf, key, 0);
+                buf.push_str(": ");
+                write_expr(buf, value, 0);
+            }
+            buf.push('}');
+        }
+    }
+    if parens {
+        buf.push(')');
+    }
+}
+
+fn write_binary(buf: &mut String, a: &Expr, op: &str, b: &Expr,
+    precedence: u8)
+{
+    // all binary operators are left-associative
+    write_expr(buf, a, precedence);
+    buf.push(' ');
+    buf.push_str(op);
+    buf.push(' ');
+    write_expr(buf, b, precedence+1);
+}
+
+fn write_target(buf: &mut String, target: &AssignTarget) {
+    match *target {
+        AssignTarget::Var(ref name) => buf.push_str(name),
+        AssignTarget::Pair(ref a, ref b) => {
+            buf.push_str(a);
+            buf.push_str(", ");
+            buf.push_str(b);
+        }
+    }
+}
+
+/// Splits `## skip if` conditions joined by the parser back
+fn split_filter(filter: &Expr, num: usize) -> Vec<&Expr> {
+    let mut result = Vec::with_capacity(num);
+    let mut cur = filter;
+    for _ in 1..num {
+        match cur.code {
+            ExprCode::Or(ref a, ref b) => {
+                result.push(&**b);
+                cur = a;
+            }
+            _ => break,
+        }
+    }
+    result.push(cur);
+    result.reverse();
+    result
+}
+
+impl<'a> Formatter<'a> {
+    fn flush(&mut self, position: Pos) {
+        while self.source.verbatim.front()
+            .map(|&(pos, _)| pos < position).unwrap_or(false)
+        {
+            let (_, text) = self.source.verbatim.pop_front().unwrap();
+            self.buf.push_str(text);
+        }
+    }
+    fn next_header(&mut self) -> Header<'a> {
+        let header = self.source.headers.pop_front()
+            .expect("every statement has a header line");
+        self.flush(header.position);
+        header
+    }
+    fn peek_header(&self) -> Option<&'a str> {
+        self.source.headers.front().map(|h| h.keyword)
+    }
+    fn header_indent(&self, header: &Header<'a>, depth: usize) -> String {
+        if self.indent_syntax {
+            // indentation of statements is significant in this syntax
+            header.indent.to_string()
+        } else {
+            "    ".repeat(depth)
+        }
+    }
+    fn write_header<F>(&mut self, header: &Header, indent: &str, f: F)
+        where F: FnOnce(&mut String)
+    {
+        self.buf.push_str(indent);
+        self.buf.push_str(&self.delimiters.statement);
+        self.buf.push_str(header.sign);
+        self.buf.push(' ');
+        f(&mut self.buf);
+        for comment in &header.comments {
+            self.buf.push_str("  ");
+            self.buf.push_str(comment);
+        }
+        self.buf.push('\n');
+    }
+    fn body(&mut self, body: &Body, depth: usize) {
+        use grammar::StatementCode::*;
+
+        for st in &body.statements {
+            match st.code {
+                OutputRaw(ref text) => {
+                    self.flush(st.position.0);
+                    self.buf.push_str(text);
+                }
+                Output { left_ws, ref expr, ref validator, right_ws } => {
+                    self.flush(st.position.0);
+                    self.buf.push_str(&self.delimiters.expr_start);
+                    self.buf.push_str(match left_ws {
+                        OutputMode::Preserve => " ",
+                        OutputMode::Strip => "- ",
+                        OutputMode::Space => "+ ",
+                    });
+                    write_expr(&mut self.buf, expr, 0);
+                    if let Some(ref validator) = *validator {
+                        self.buf.push_str(" | ");
+                        self.buf.push_str(validator);
+                    }
+                    self.buf.push_str(match right_ws {
+                        OutputMode::Preserve => " ",
+                        OutputMode::Strip => " -",
+                        OutputMode::Space => " +",
+                    });
+                    self.buf.push_str(&self.delimiters.expr_end);
+                }
+                // written as part of the comment or statement line
+                WhitespaceControl { .. } => {}
+                Joiner => {
+                    self.flush(st.position.0);
+                    match self.source.joiners.pop_front() {
+                        Some(text) => self.buf.push_str(text),
+                        None => {
+                            self.buf.push_str(&self.delimiters.statement);
+                            self.buf.push('\n');
+                        }
+                    }
+                }
+                Cond { ref conditional, ref otherwise, .. } => {
+                    let header = self.next_header();
+                    let indent = self.header_indent(&header, depth);
+                    for (idx, (cond, body)) in conditional.iter().enumerate()
+                    {
+                        let header = if idx == 0 {
+                            &header
+                        } else {
+                            &self.next_header()
+                        };
+                        let keyword = if idx == 0 { "if " } else { "elif " };
+                        self.write_header(header, &indent, |buf| {
+                            buf.push_str(keyword);
+                            write_expr(buf, cond, 0);
+                        });
+                        self.body(body, depth+1);
+                    }
+                    // else might have an empty body
+                    if self.peek_header() == Some("else") {
+                        let header = self.next_header();
+                        self.write_header(&header, &indent,
+                            |buf| buf.push_str("else"));
+                        self.body(otherwise, depth+1);
+                    }
+                    let header = self.next_header();
+                    self.write_header(&header, &indent,
+                        |buf| buf.push_str("endif"));
+                }
+                Loop { ref target, ref iterator, ref filter, ref body, .. }
+                => {
+                    let header = self.next_header();
+                    let indent = self.header_indent(&header, depth);
+                    self.write_header(&header, &indent, |buf| {
+                        buf.push_str("for ");
+                        write_target(buf, target);
+                        buf.push_str(" in ");
+                        write_expr(buf, iterator, 0);
+                    });
+                    if let Some(ref filter) = *filter {
+                        let num = self.source.headers.iter()
+                            .take_while(|h| h.keyword == "skip").count();
+                        for cond in split_filter(filter, num) {
+                            let header = self.next_header();
+                            let indent = self.header_indent(&header, depth+1);
+                            self.write_header(&header, &indent, |buf| {
+                                buf.push_str("skip if ");
+                                write_expr(buf, cond, 0);
+                            });
+                        }
+                    }
+                    self.body(body, depth+1);
+                    let header = self.next_header();
+                    self.write_header(&header, &indent,
+                        |buf| buf.push_str("endfor"));
+                }
+                Alias { ref target, ref value } => {
+                    let header = self.next_header();
+                    let indent = self.header_indent(&header, depth);
+                    self.write_header(&header, &indent, |buf| {
+                        buf.push_str("let ");
+                        write_target(buf, target);
+                        buf.push_str(" = ");
+                        write_expr(buf, value, 0);
+                    });
+                }
+            }
+        }
+    }
+}
+
+pub fn format(tok: &Tokenizer, defaults: &Delimiters, options: &Options,
+    body: &Body, data: &str)
+    -> String
+{
+    let mut formatter = Formatter {
+        buf: String::with_capacity(data.len()),
+        source: scan(tok, defaults, &options.delimiters, data),
+        delimiters: &options.delimiters,
+        indent_syntax: options.syntax == Syntax::Indent,
+    };
+    formatter.body(body, 0);
+    while let Some((_, text)) = formatter.source.verbatim.pop_front() {
+        formatter.buf.push_str(text);
+    }
+    formatter.buf
+}
@@ -1,24 +1,38 @@
+use std::iter::FromIterator;
+
 use combine::{Parser as CombineParser, ParseResult};
 use combine::combinator::{position, parser, many, optional, skip_many};
 use combine::combinator::{sep_end_by};
 
+use codegen;
+use format;
+use html;
 use indent;
+use lint::{self, Warning};
 use oneline;
 use optimize;
 use parse_error::ParseError;
 use preparser::{Preparser, Syntax};
 use render::{self, template};
+use schema;
 use tokenizer::{Tokenizer, TokenStream, Token, Kind};
 use {Options, Pos};
 
 
+/// Comparison operator
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum CmpOperator {
+    /// `==`
     Eq,
+    /// `!=`
     Neq,
+    /// `<=`
     LessEq,
+    /// `<`
     Less,
+    /// `>=`
     GreaterEq,
+    /// `>`
     Greater,
 }
 
@@ -63,10 +77,14 @@ pub struct Body {
     pub statements: Vec<Statement>,
 }
 
+/// Whitespace handling around `{{ expression }}`
 #[derive(Debug, PartialEq, Clone, Copy, PartialOrd, Ord, Eq)]
 pub enum OutputMode {
+    /// `{{-`, strip all whitespace
     Strip,
+    /// `{{+`, replace whitespace with a single space
     Space,
+    /// `{{`, keep whitespace as is
     Preserve,
 }
 
@@ -80,6 +98,11 @@ pub enum StatementCode {
         right_ws: OutputMode,
     },
     Joiner,
+    /// Whitespace control of `##-`, `##+` lines and `{#- -#}` comments
+    WhitespaceControl {
+        left_ws: OutputMode,
+        right_ws: OutputMode,
+    },
     Cond {
         indent: usize,
         conditional: Vec<(Expr, Body)>,
@@ -110,6 +133,26 @@ pub struct Statement {
     pub code: StatementCode,
 }
 
+/// Block statement with whitespace control markers of its first and last
+/// lines, i.e. `##- if` and `##- endif`
+type Controlled = (OutputMode, StatementCode, OutputMode);
+
+/// Statement with whitespace control before and after it
+type Group = (OutputMode, Statement, OutputMode);
+
+impl FromIterator<Group> for Body {
+    fn from_iter<I: IntoIterator<Item=Group>>(iter: I) -> Body {
+        let mut statements = Vec::new();
+        for (left_ws, statement, right_ws) in iter {
+            let (start, end) = statement.position;
+            statements.extend(control(start, left_ws, OutputMode::Preserve));
+            statements.push(statement);
+            statements.extend(control(end, OutputMode::Preserve, right_ws));
+        }
+        Body { statements }
+    }
+}
+
 #[derive(Debug)]
 pub struct Template {
     pub options: Options,
@@ -427,8 +470,8 @@ fn expression<'a>(input: TokenStream<'a>)
         .and(optional(operator("|").skip(ws()).with(kind(Ident))))
         .skip(ws()).and(kind(ExprEnd))
     .map(|(((start, expr), validator), end)| {
-        let left_ws = OutputMode::start(&start);
-        let right_ws = OutputMode::end(&end);
+        let left_ws = OutputMode::trailing(start.value);
+        let right_ws = OutputMode::leading(end.value);
         // TODO(tailhook) parse validator
         StatementCode::Output { left_ws, expr,
             validator: validator.map(|x| x.value.to_string()), right_ws }
@@ -463,51 +506,103 @@ fn let_target<'a>(input: TokenStream<'a>)
     .parse_stream(input)
 }
 
+/// Whitespace control of the statement line, i.e. `##-` or `##+`
+fn line_mode(tok: &Token) -> OutputMode {
+    OutputMode::trailing(tok.value.split_whitespace().next().unwrap_or(""))
+}
+
+fn control(pos: Pos, left_ws: OutputMode, right_ws: OutputMode)
+    -> Option<Statement>
+{
+    if left_ws == OutputMode::Preserve && right_ws == OutputMode::Preserve {
+        return None;
+    }
+    Some(Statement {
+        position: (pos, pos),
+        code: StatementCode::WhitespaceControl { left_ws, right_ws },
+    })
+}
+
+/// Applies whitespace control of the line preceding the body
+fn prepend(body: &mut Body, pos: Pos, mode: OutputMode) {
+    if let Some(st) = control(pos, OutputMode::Preserve, mode) {
+        body.statements.insert(0, st);
+    }
+}
+
+/// Applies whitespace control of the line following the body
+fn append(body: &mut Body, pos: Pos, mode: OutputMode) {
+    body.statements.extend(control(pos, mode, OutputMode::Preserve));
+}
+
 fn if_stmt<'a>(input: TokenStream<'a>)
-    -> ParseResult<StatementCode, TokenStream<'a>>
+    -> ParseResult<Controlled, TokenStream<'a>>
 {
     use tokenizer::Kind::*;
     use self::StatementCode::*;
     use helpers::*;
 
-    st_start("if")
+    (position(), st_start("if"))
         .skip(ws())
         .and(parser(top_level_expression))
         .skip(ws())
         .skip(kind(Newline))
     .and(parser(body))
     .and(many::<Vec<_>, _>(
-        st_start("elif")
+        (position(), st_start("elif"))
         .skip(ws())
-        .with(parser(top_level_expression))
+        .and(parser(top_level_expression))
         .skip(kind(Newline))
         .and(parser(body))))
     .and(optional(
-        st_start("else").skip(ws()).skip(kind(Newline))
-        .with(parser(body))))
-    .skip(st_start("endif")).skip(ws()).skip(kind(Newline))
-    .map(|((((if_token, condition), block), mut elifs), else_block)| {
-        Cond {
-            indent: if_token.value.len() - if_token.value.trim_left().len(),
-            conditional: {
-                elifs.insert(0, (condition, block));
-                elifs
-            },
-            otherwise: else_block.unwrap_or_else(|| Body {
-                statements: Vec::new(),
-            }),
+        (position(), st_start("else")).skip(ws()).skip(kind(Newline))
+        .and(parser(body))))
+    .and((position(), st_start("endif"))).skip(ws()).skip(kind(Newline))
+    .map(|(((((if_line, condition), mut block), elifs), else_block),
+           endif_line)|
+    {
+        let (if_pos, if_token) = if_line;
+        prepend(&mut block, if_pos, line_mode(&if_token));
+        let mut conditional = vec![(condition, block)];
+        for (((pos, token), condition), mut block) in elifs {
+            let mode = line_mode(&token);
+            append(&mut conditional.last_mut().unwrap().1, pos, mode);
+            prepend(&mut block, pos, mode);
+            conditional.push((condition, block));
         }
+        let mut otherwise = Body { statements: Vec::new() };
+        let (endif_pos, endif_token) = endif_line;
+        let endif_mode = line_mode(&endif_token);
+        match else_block {
+            Some(((pos, token), mut block)) => {
+                let mode = line_mode(&token);
+                append(&mut conditional.last_mut().unwrap().1, pos, mode);
+                prepend(&mut block, pos, mode);
+                append(&mut block, endif_pos, endif_mode);
+                otherwise = block;
+            }
+            None => {
+                append(&mut conditional.last_mut().unwrap().1,
+                       endif_pos, endif_mode);
+            }
+        }
+        let value = if_token.value;
+        (line_mode(&if_token), Cond {
+            indent: value.len() - value.trim_left().len(),
+            conditional,
+            otherwise,
+        }, endif_mode)
     })
     .parse_stream(input)
 }
 fn for_stmt<'a>(input: TokenStream<'a>)
-    -> ParseResult<StatementCode, TokenStream<'a>>
+    -> ParseResult<Controlled, TokenStream<'a>>
 {
     use tokenizer::Kind::*;
     use self::StatementCode::*;
     use helpers::*;
 
-    st_start("for")
+    (position(), st_start("for"))
         .skip(ws())
         .and(parser(for_target))
         .skip(keyword("in"))
@@ -515,39 +610,47 @@ fn for_stmt<'a>(input: TokenStream<'a>)
         .and(parser(top_level_expression))
         .skip(ws())
         .skip(kind(Newline))
-    .and(many::<Vec<_>, _>(st_start("skip")
+    .and(many::<Vec<_>, _>((position(), st_start("skip"))
         .skip(ws())
-        .and(keyword("if"))
+        .skip(keyword("if"))
         .skip(ws())
-        .with(parser(top_level_expression))
+        .and(parser(top_level_expression))
         .skip(ws())
         .skip(kind(Newline))))
     .and(parser(body))
-    .skip(st_start("endfor")).skip(ws()).skip(kind(Newline))
-    .map(|((((for_token, target), list), mut filter), block)| {
-        Loop {
-            indent: for_token.value.len() - for_token.value.trim_left().len(),
+    .and((position(), st_start("endfor"))).skip(ws()).skip(kind(Newline))
+    .map(|(((((for_line, target), list), skips), mut block), endfor_line)| {
+        let (for_pos, for_token) = for_line;
+        let (endfor_pos, endfor_token) = endfor_line;
+        let mut filter: Option<Expr> = None;
+        for &((pos, ref token), _) in skips.iter().rev() {
+            prepend(&mut block, pos, line_mode(token));
+        }
+        for (_, cond) in skips {
+            filter = Some(match filter {
+                Some(a) => Expr {
+                    position: (a.position.0, cond.position.1),
+                    code: ExprCode::Or(Box::new(a), Box::new(cond)),
+                },
+                None => cond,
+            });
+        }
+        prepend(&mut block, for_pos, line_mode(&for_token));
+        append(&mut block, endfor_pos, line_mode(&endfor_token));
+        let value = for_token.value;
+        (line_mode(&for_token), Loop {
+            indent: value.len() - value.trim_left().len(),
             target: target,
             iterator: list,
-            filter: if filter.len() > 0 {
-                let init = filter.remove(0);
-                Some(filter.into_iter().fold(init, |a: Expr, b: Expr| {
-                    Expr {
-                        position: (a.position.0, b.position.1),
-                        code: ExprCode::Or(Box::new(a), Box::new(b)),
-                    }
-                }))
-            } else {
-                None
-            },
+            filter,
             body: block,
-        }
+        }, line_mode(&endfor_token))
     })
     .parse_stream(input)
 }
 
 fn let_stmt<'a>(input: TokenStream<'a>)
-    -> ParseResult<StatementCode, TokenStream<'a>>
+    -> ParseResult<Controlled, TokenStream<'a>>
 {
     use tokenizer::Kind::*;
     use self::StatementCode::*;
@@ -555,19 +658,22 @@ fn let_stmt<'a>(input: TokenStream<'a>)
 
     st_start("let")
         .skip(ws())
-        .with(parser(let_target))
+        .and(parser(let_target))
         .skip(ws())
         .skip(operator("="))
         .skip(ws())
         .and(parser(top_level_expression))
         .skip(ws())
         .skip(kind(Newline))
-    .map(|(target, value)| Alias { target, value })
+    .map(|((token, target), value)| {
+        let mode = line_mode(&token);
+        (mode, Alias { target, value }, mode)
+    })
     .parse_stream(input)
 }
 
 fn block<'a>(input: TokenStream<'a>)
-    -> ParseResult<StatementCode, TokenStream<'a>>
+    -> ParseResult<Controlled, TokenStream<'a>>
 {
     parser(if_stmt)
     .or(parser(for_stmt))
@@ -575,8 +681,12 @@ fn block<'a>(input: TokenStream<'a>)
     .parse_stream(input)
 }
 
+fn plain(code: StatementCode) -> Controlled {
+    (OutputMode::Preserve, code, OutputMode::Preserve)
+}
+
 fn statement<'a>(input: TokenStream<'a>)
-    -> ParseResult<Statement, TokenStream<'a>>
+    -> ParseResult<Group, TokenStream<'a>>
 {
     use tokenizer::Kind::*;
     use helpers::*;
@@ -584,16 +694,22 @@ fn statement<'a>(input: TokenStream<'a>)
     use self::StatementCode::*;
 
     let statements =
-        kind(Raw).map(|tok| OutputRaw(tok.value.to_string()))
-        .or(parser(expression))
+        kind(Raw).map(|tok| plain(OutputRaw(tok.value.to_string())))
+        .or(parser(expression).map(plain))
         .or(parser(block))
         // Whitespace out of any blocks is output as is
-        .or(kind(Whitespace).map(|tok| OutputRaw(tok.value.to_string())))
-        .or(kind(LineJoiner).map(|_| Joiner))
-        .or(kind(Newline).map(|tok| OutputRaw(tok.value.to_string())));
-    (position(), statements, position()).map(|(s, c, e)| Statement {
-        position: (s, e),
-        code: c,
+        .or(kind(Whitespace)
+            .map(|tok| plain(OutputRaw(tok.value.to_string()))))
+        .or(kind(LineJoiner).map(|_| plain(Joiner)))
+        .or(kind(ControlComment).map(|tok| plain(WhitespaceControl {
+            left_ws: OutputMode::leading(tok.value),
+            right_ws: OutputMode::trailing(tok.value),
+        })))
+        .or(kind(Newline)
+            .map(|tok| plain(OutputRaw(tok.value.to_string()))));
+    (position(), statements, position())
+    .map(|(s, (left_ws, c, right_ws), e)| {
+        (left_ws, Statement { position: (s, e), code: c }, right_ws)
     }).parse_stream(input)
 }
 
@@ -604,27 +720,26 @@ fn body<'a>(input: TokenStream<'a>)
     use helpers::kind;
 
     optional(skip_many(kind(Comment)))
-    .with(many(parser(statement).skip(skip_many(kind(Comment))))).map(|x| {
-        Body {
-            statements: x,
-        }
-    }).parse_stream(input)
+    .with(many(parser(statement).skip(skip_many(kind(Comment)))))
+    .parse_stream(input)
 }
 
 impl OutputMode {
-    fn start(tok: &Token) -> OutputMode {
-        if tok.value.ends_with("+") {
+    /// Mode of the sign at the end of the marker: `{{-`, `##+`
+    fn trailing(text: &str) -> OutputMode {
+        if text.ends_with("+") {
             OutputMode::Space
-        } else if tok.value.ends_with("-") {
+        } else if text.ends_with("-") {
             OutputMode::Strip
         } else {
             OutputMode::Preserve
         }
     }
-    fn end(tok: &Token) -> OutputMode {
-        if tok.value.starts_with("+") {
+    /// Mode of the sign at the start of the marker: `-}}`
+    fn leading(text: &str) -> OutputMode {
+        if text.starts_with("+") {
             OutputMode::Space
-        } else if tok.value.starts_with("-") {
+        } else if text.starts_with("-") {
             OutputMode::Strip
         } else {
             OutputMode::Preserve
@@ -654,19 +769,100 @@ impl Parser {
     /// Parse and compile a template with some predefined options set
     pub fn parse_with_options(&self, options: &Options, data: &str)
         -> Result<render::Template, ParseError>
+    {
+        template(self.compile(options, data)?)
+    }
+    /// Format template source in a canonical way
+    ///
+    /// Expressions are printed with normalized spacing and statements are
+    /// indented according to their nesting (except in `indent` syntax where
+    /// indentation of statements is significant and left as is). Raw text,
+    /// comments and `syntax`, `validate`, `filter` and `requires` statements
+    /// are kept intact, so formatted template renders exactly the same.
+    pub fn format(&self, data: &str) -> Result<String, ParseError> {
+        self.format_with_options(&Options::new(), data)
+    }
+    /// Same as `format` but with some predefined options set
+    pub fn format_with_options(&self, options: &Options, data: &str)
+        -> Result<String, ParseError>
+    {
+        let (final_options, body) = self.parse_body(options, data)?;
+        Ok(format::format(&self.tok, &options.delimiters, &final_options,
+                          &body, data))
+    }
+    /// Parse a template and check it for suspicious constructs
+    ///
+    /// Returns the list of warnings sorted by position. Warnings don't
+    /// prevent template from being rendered, but usually point to a mistake
+    /// in a template (see `Warning` for the list of checks). Errors that
+    /// prevent template from being compiled are returned as `ParseError`.
+    pub fn lint(&self, data: &str) -> Result<Vec<(Pos, Warning)>, ParseError>
+    {
+        self.lint_with_options(&Options::new(), data)
+    }
+    /// Same as `lint` but with some predefined options set
+    pub fn lint_with_options(&self, options: &Options, data: &str)
+        -> Result<Vec<(Pos, Warning)>, ParseError>
+    {
+        Ok(lint::lint(&self.compile(options, data)?, data))
+    }
+    /// Compile a template into rust code
+    ///
+    /// Returns source of the function named `name` with the following
+    /// signature:
+    ///
+    /// ```rust,ignore
+    /// pub fn name(context: &Context) -> Result<String, RenderError>
+    /// ```
+    ///
+    /// The function renders exactly the same output as
+    /// `Template::render` for the same context, but doesn't need to parse
+    /// template at runtime. This is meant to be used in `build.rs`:
+    ///
+    /// ```rust,ignore
+    /// let code = Parser::new().codegen("render_page", &source)?;
+    /// fs::write(Path::new(&env::var("OUT_DIR")?).join("page.rs"), code)?;
+    /// ```
+    ///
+    /// And then `include!(concat!(env!("OUT_DIR"), "/page.rs"));` in the
+    /// crate itself.
+    pub fn codegen(&self, name: &str, data: &str)
+        -> Result<String, ParseError>
+    {
+        self.codegen_with_options(&Options::new(), name, data)
+    }
+    /// Same as `codegen` but with some predefined options set
+    pub fn codegen_with_options(&self, options: &Options, name: &str,
+        data: &str)
+        -> Result<String, ParseError>
+    {
+        codegen::generate(name, &self.compile(options, data)?)
+    }
+    /// Parses template into a body that closely matches the source
+    ///
+    /// No postprocessing and optimizations are done, so all the whitespace
+    /// is kept in the same statements as in the source text.
+    pub(crate) fn parse_body(&self, options: &Options, data: &str)
+        -> Result<(Options, Body), ParseError>
     {
         use combine::combinator::{skip_many, parser};
         use tokenizer::Kind::{EarlyStatement, Comment};
         use helpers::{kind};
 
-        let options = self.pre.scan(data, options.clone())?;
-        let s = self.tok.scan(data);
+        let result = self.pre.scan(data, options.clone())?;
+        let s = self.tok.scan(data, &options.delimiters, &result.delimiters);
 
         let mut p =
             skip_many(kind(EarlyStatement).or(kind(Comment)))
             .with(parser(body)).skip(kind(Kind::Eof));
 
         let (body, _) = p.parse(s)?;
+        Ok((result, body))
+    }
+    pub(crate) fn compile(&self, options: &Options, data: &str)
+        -> Result<Template, ParseError>
+    {
+        let (options, body) = self.parse_body(options, data)?;
         let body = match options.syntax {
             Syntax::Oneline => {
                 // easier after optimizer
@@ -682,12 +878,15 @@ impl Parser {
                 self.optimizer.optimize(&options, body)
             }
         };
-        let tpl = Template {
+        let mut tpl = Template {
             options: options,
             body: body,
         };
+        html::autoescape(&mut tpl)?;
+        if !tpl.options.schema.is_empty() {
+            schema::check(&tpl)?;
+        }
         // TODO(tailhook) should we assert EOF?
-        // TODO(tailhook) execute checks
-        return Ok(template(tpl));
+        return Ok(tpl);
     }
 }
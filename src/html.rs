@@ -0,0 +1,415 @@
+//! Context-aware escaping for `builtin.html_auto` filter
+//!
+//! The raw text of the template is scanned when the template is parsed, and
+//! every output statement that uses `builtin.html_auto` gets a filter
+//! suitable for the place in HTML document where it's printed.
+use std::collections::HashMap;
+use std::iter::once;
+
+use grammar::{Body, Template};
+use parse_error::{ParseError, ParseErrorEnum};
+use validators::BuiltinEscape::{self, *};
+use validators::Filter;
+use {Pos};
+
+
+/// Value of the element's attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Attr {
+    Normal,
+    Url,
+    Script,
+    Style,
+}
+
+/// Kind of the element's contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Element {
+    Normal,
+    Script,
+    Style,
+    Title,
+    Textarea,
+}
+
+/// Part of the URL in the attribute value
+///
+/// URL stays at the start until static text fixes the scheme, so every
+/// expression printed there is checked by `builtin.safe_url`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UrlPart {
+    Start,
+    /// At the start, after an expression
+    StartExpr,
+    Path,
+    Query,
+}
+
+/// Lexical context of javascript, regular expressions aren't tracked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Js {
+    Code,
+    Str(char),
+    StrEscape(char),
+    LineComment,
+    BlockComment,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    Text,
+    /// Inside `<title>` or `<textarea>`, no tags until the closing one
+    RcData(&'static str),
+    Script(Js),
+    Style,
+    TagName(String),
+    Tag(Element),
+    AttrName(Element, String),
+    AfterAttrName(Element, Attr),
+    BeforeValue(Element, Attr),
+    /// Attribute value, quote is `None` when value is unquoted
+    Value(Element, Attr, Option<char>, UrlPart, Js),
+    Comment,
+    /// Closing tag, doctype and similar things skipped till `>`
+    Declaration,
+}
+
+/// Name of the context and escapes used in this context
+type Context = (&'static str, Vec<BuiltinEscape>);
+
+struct Scanner<'a> {
+    state: State,
+    default_filter: &'a Filter,
+    filters: Vec<Context>,
+}
+
+fn attr_kind(name: &str) -> Attr {
+    match name {
+        "href" | "src" | "action" | "formaction" | "cite" | "poster"
+        | "background" | "codebase" | "data" | "longdesc" | "usemap"
+        | "manifest" | "icon" | "xlink:href" => Attr::Url,
+        "style" => Attr::Style,
+        _ if name.starts_with("on") => Attr::Script,
+        _ => Attr::Normal,
+    }
+}
+
+fn element(name: &str) -> Element {
+    match name {
+        "script" => Element::Script,
+        "style" => Element::Style,
+        "title" => Element::Title,
+        "textarea" => Element::Textarea,
+        _ => Element::Normal,
+    }
+}
+
+fn contents(elem: Element) -> State {
+    match elem {
+        Element::Normal => State::Text,
+        Element::Script => State::Script(Js::Code),
+        Element::Style => State::Style,
+        Element::Title => State::RcData("title"),
+        Element::Textarea => State::RcData("textarea"),
+    }
+}
+
+fn lowercase(c: char) -> char {
+    // Only ASCII letters, like browsers do for tag and attribute names
+    if c < '\u{80}' && c.is_uppercase() {
+        (c as u8 + (b'a' - b'A')) as char
+    } else {
+        c
+    }
+}
+
+fn closes(rest: &str, tag: &str) -> bool {
+    rest.starts_with("</") &&
+        rest.get(2..tag.len()+2)
+            .map(|x| x.chars().map(lowercase).eq(tag.chars()))
+            .unwrap_or(false)
+}
+
+fn js(state: Js, c: char, rest: &str) -> Js {
+    use self::Js::*;
+    match state {
+        Code => match c {
+            '"' | '\'' | '`' => Str(c),
+            '/' if rest.starts_with("//") => LineComment,
+            '/' if rest.starts_with("/*") => BlockComment,
+            _ => Code,
+        },
+        Str(q) if c == '\\' => StrEscape(q),
+        Str(q) if c == q => Code,
+        Str(q) | StrEscape(q) => Str(q),
+        LineComment if c == '\n' => Code,
+        LineComment => LineComment,
+        BlockComment if c == '*' && rest.starts_with("*/") => Code,
+        BlockComment => BlockComment,
+    }
+}
+
+impl<'a> Scanner<'a> {
+    fn feed(&mut self, text: &str) -> Result<(), &'static str> {
+        use self::State::*;
+        let mut iter = text.char_indices();
+        while let Some((idx, c)) = iter.next() {
+            let rest = &text[idx..];
+            self.state = match self.state {
+                Text if rest.starts_with("<!--") => {
+                    iter.nth(2);
+                    Comment
+                }
+                Text if c == '<' => match rest[1..].chars().next() {
+                    Some(n) if n < '\u{80}' && n.is_alphabetic() => {
+                        TagName(String::new())
+                    }
+                    Some('/') | Some('!') | Some('?') => Declaration,
+                    _ => Text,
+                },
+                Text => Text,
+                RcData(tag) if closes(rest, tag) => Declaration,
+                RcData(tag) => RcData(tag),
+                // browsers don't know anything about javascript strings
+                Script(_) if closes(rest, "script") => Declaration,
+                Script(state) => Script(js(state, c, rest)),
+                Style if closes(rest, "style") => Declaration,
+                Style => Style,
+                TagName(ref mut name) => match c {
+                    '>' => contents(element(name)),
+                    '/' => Tag(element(name)),
+                    _ if c.is_whitespace() => Tag(element(name)),
+                    _ => {
+                        name.push(lowercase(c));
+                        continue;
+                    }
+                },
+                Tag(elem) => match c {
+                    '>' => contents(elem),
+                    '/' => Tag(elem),
+                    _ if c.is_whitespace() => Tag(elem),
+                    _ => AttrName(elem, lowercase(c).to_string()),
+                },
+                AttrName(elem, ref mut name) => match c {
+                    '>' => contents(elem),
+                    '/' => Tag(elem),
+                    '=' => BeforeValue(elem, attr_kind(name)),
+                    _ if c.is_whitespace() => {
+                        AfterAttrName(elem, attr_kind(name))
+                    }
+                    _ => {
+                        name.push(lowercase(c));
+                        continue;
+                    }
+                },
+                AfterAttrName(elem, attr) => match c {
+                    '>' => contents(elem),
+                    '/' => Tag(elem),
+                    '=' => BeforeValue(elem, attr),
+                    _ if c.is_whitespace() => AfterAttrName(elem, attr),
+                    _ => AttrName(elem, lowercase(c).to_string()),
+                },
+                BeforeValue(elem, attr) => match c {
+                    '>' => contents(elem),
+                    '"' | '\'' => {
+                        Value(elem, attr, Some(c), UrlPart::Start, Js::Code)
+                    }
+                    _ if c.is_whitespace() => BeforeValue(elem, attr),
+                    _ => value(elem, attr, None, UrlPart::Start, Js::Code,
+                               c, rest)?,
+                },
+                Value(elem, _, Some(q), _, _) if c == q => Tag(elem),
+                Value(elem, _, None, _, _) if c == '>' => contents(elem),
+                Value(elem, _, None, _, _) if c.is_whitespace() => {
+                    Tag(elem)
+                }
+                Value(elem, attr, quote, url, state) => {
+                    value(elem, attr, quote, url, state, c, rest)?
+                }
+                Comment if rest.starts_with("-->") => {
+                    iter.nth(1);
+                    Text
+                }
+                Comment => Comment,
+                Declaration if c == '>' => Text,
+                Declaration => Declaration,
+            };
+        }
+        Ok(())
+    }
+    fn context(&self) -> Result<Context, &'static str> {
+        use self::State::*;
+        Ok(match self.state {
+            Text | RcData(..) => ("text", vec![HtmlEntities]),
+            Script(Js::Code) => ("script", vec![Json]),
+            Script(..) => {
+                return Err("expression inside of a javascript string or \
+                            comment, put it outside of the quotes");
+            }
+            Style => ("style", vec![Css]),
+            Value(_, attr, Some(_), url, state) => match (attr, url, state) {
+                (Attr::Normal, _, _) => ("attr", vec![HtmlEntities]),
+                (Attr::Url, UrlPart::Start, _)
+                | (Attr::Url, UrlPart::StartExpr, _) => {
+                    ("url_start", vec![SafeUrl, HtmlEntities])
+                }
+                (Attr::Url, UrlPart::Path, _) => {
+                    ("url", vec![Url, HtmlEntities])
+                }
+                (Attr::Url, UrlPart::Query, _) => {
+                    ("url_query", vec![UrlQueryComponent, HtmlEntities])
+                }
+                (Attr::Script, _, Js::Code) => {
+                    ("script_attr", vec![Json, HtmlEntities])
+                }
+                (Attr::Script, _, _) => {
+                    return Err("expression inside of a javascript string \
+                                or comment, put it outside of the quotes");
+                }
+                (Attr::Style, _, _) => ("style_attr", vec![Css, HtmlEntities]),
+            },
+            Value(..) | BeforeValue(..) => {
+                return Err("value of the attribute must be quoted");
+            }
+            Comment => return Err("expression inside of HTML comment"),
+            TagName(..) | Tag(..) | AttrName(..) | AfterAttrName(..)
+            | Declaration => {
+                return Err("expression inside of HTML tag, only attribute \
+                            values can be printed");
+            }
+        })
+    }
+    fn is_auto(&self, filters: &HashMap<String, Filter>,
+        validator: &Option<String>)
+        -> bool
+    {
+        let filter = match *validator {
+            Some(ref name) => match filters.get(name) {
+                Some(filter) => filter,
+                None => return false,
+            },
+            None => self.default_filter,
+        };
+        match *filter {
+            Filter::Escape(ref escapes) => escapes[..] == [HtmlAuto],
+            _ => false,
+        }
+    }
+    fn visit_body(&mut self, filters: &HashMap<String, Filter>,
+        body: &mut Body)
+        -> Result<(), ParseError>
+    {
+        use grammar::StatementCode::*;
+
+        for st in &mut body.statements {
+            let pos = st.position.0;
+            match st.code {
+                OutputRaw(ref text) => {
+                    self.feed(text).map_err(|e| error(pos, e))?;
+                }
+                Output { ref mut validator, .. } => {
+                    if !self.is_auto(filters, validator) {
+                        continue;
+                    }
+                    let (name, escapes) = self.context()
+                        .map_err(|e| error(pos, e))?;
+                    if !self.filters.iter().any(|&(n, _)| n == name) {
+                        self.filters.push((name, escapes));
+                    }
+                    *validator = Some(filter_name(name));
+                    if let State::Value(e, Attr::Url, q, UrlPart::Start, j)
+                        = self.state
+                    {
+                        self.state = State::Value(e, Attr::Url, q,
+                                                  UrlPart::StartExpr, j);
+                    }
+                }
+                Joiner | WhitespaceControl { .. } | Alias { .. } => {}
+                Cond { ref mut conditional, ref mut otherwise, .. } => {
+                    let start = self.state.clone();
+                    let mut end = None;
+                    let branches = conditional.iter_mut()
+                        .map(|&mut (_, ref mut body)| body)
+                        .chain(once(otherwise));
+                    for body in branches {
+                        self.state = start.clone();
+                        self.visit_body(filters, body)?;
+                        match end {
+                            Some(ref end) if *end != self.state => {
+                                return Err(error(pos,
+                                    "branches of the condition end in \
+                                     different HTML context"));
+                            }
+                            Some(_) => {}
+                            None => end = Some(self.state.clone()),
+                        }
+                    }
+                }
+                Loop { ref mut body, .. } => {
+                    let start = self.state.clone();
+                    self.visit_body(filters, body)?;
+                    if self.state != start {
+                        return Err(error(pos,
+                            "loop body ends in different HTML context \
+                             than it starts"));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn value(elem: Element, attr: Attr, quote: Option<char>,
+    url: UrlPart, state: Js, c: char, rest: &str)
+    -> Result<State, &'static str>
+{
+    use self::UrlPart::*;
+    let url = match (url, c) {
+        (_, '?') | (_, '#') | (Query, _) => Query,
+        (_, '/') | (Path, _) => Path,
+        (Start, ':') => Path,
+        (StartExpr, ':') => {
+            return Err("scheme of the URL depends on the expression, \
+                        put the whole scheme into the expression");
+        }
+        (Start, _) | (StartExpr, _) => url,
+    };
+    let state = match attr {
+        Attr::Script => js(state, c, rest),
+        _ => state,
+    };
+    Ok(State::Value(elem, attr, quote, url, state))
+}
+
+fn error(pos: Pos, message: &str) -> ParseError {
+    ParseErrorEnum::InvalidSyntax(pos, message.to_string()).into()
+}
+
+fn filter_name(context: &str) -> String {
+    format!("builtin.html_auto.{}", context)
+}
+
+/// Replaces `builtin.html_auto` filter of every output by the filter
+/// suitable for the context
+pub fn autoescape(tpl: &mut Template) -> Result<(), ParseError> {
+    let auto = Filter::Escape(vec![HtmlAuto]);
+    if tpl.options.default_filter != auto &&
+        !tpl.options.filters.values().any(|f| *f == auto)
+    {
+        return Ok(());
+    }
+    let filters = {
+        let mut scanner = Scanner {
+            state: State::Text,
+            default_filter: &tpl.options.default_filter,
+            filters: Vec::new(),
+        };
+        scanner.visit_body(&tpl.options.filters, &mut tpl.body)?;
+        scanner.filters
+    };
+    for (name, escapes) in filters {
+        tpl.options.filters.insert(filter_name(name), Filter::Escape(escapes));
+    }
+    Ok(())
+}
@@ -4,7 +4,7 @@
 //! [Github](https://github.com/tailhook/trimmer/) |
 //! [Crate](https://crates.io/crates/trimmer)
 //!
-#![recursion_limit="100"]
+#![recursion_limit="128"]
 #![warn(missing_docs)]
 
 extern crate combine;
@@ -15,43 +15,60 @@ extern crate regex;
 #[cfg(test)] #[macro_use] extern crate difference;
 #[cfg(feature="json")] extern crate serde_json;
 
+mod artifact;
+pub mod ast;
+mod bytecode;
+mod cache;
+mod codegen;
 mod compare;
 mod escape;
+mod format;
 mod grammar;
 mod helpers;
+mod html;
 mod indent;
+mod lint;
 mod number;
 mod oneline;
 mod optimize;
 mod options;
 mod output;
-mod owning;
 mod parse_error;
 mod position;
 mod preparser;
 mod render;
 mod render_error;
+#[doc(hidden)] pub mod runtime;
+mod safe;
+mod schema;
 mod std_vars;
 mod box_vars;
 mod tokenizer;
+mod usage;
 mod validators;
 mod varmap;
 mod vars;
 #[cfg(feature="json")] mod serde;
 #[cfg(test)] mod tests;
 
+pub use artifact::ArtifactError;
+pub use cache::{TemplateCache, CacheError};
 pub use grammar::Parser;
+pub use lint::Warning;
 pub use parse_error::ParseError;
 pub use position::Pos;
 pub use render_error::{RenderError, DataError};
 pub use render::Template;
+pub use safe::{Safe, Markup};
+pub use schema::{Type, Attr};
+pub use usage::{Usage, VarUsage, FilterUsage};
 pub use vars::{Variable};
 pub use varmap::Context;
 pub use number::Number;
 pub use compare::Comparable;
 #[cfg(feature="json")] pub use serde::render_json;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use vars::Val;
 
@@ -63,8 +80,15 @@ use vars::Val;
 /// can be prefilled with better default that suit your application. For
 /// example, if you use template for a log message it's good idea to use
 /// `## syntax: oneline` but it's tedious to write it every time.
+///
+/// Validators and filters registered here (`Options::validator`,
+/// `Options::escape`, `Options::escaper`) are defaults too: a `## validate`
+/// or `## filter` statement with the same name in the template replaces
+/// the registered one, and a constraint statement like
+/// `## validate name max_length: 10` adds a constraint to it.
 pub struct Options {
     syntax: preparser::Syntax,
+    delimiters: tokenizer::Delimiters,
     new_line_at_eof: Option<bool>,
     // parenthesis
     curly: bool,
@@ -73,6 +97,11 @@ pub struct Options {
 
     default_filter: validators::Filter,
     filters: HashMap<String, validators::Filter>,
+    schema: Vec<(String, schema::Type)>,
+
+    // directives that template can't override
+    locked_syntax: bool,
+    locked_filters: HashSet<String>,
 }
 
 /// Variable reference returned from methods of Variable trait
@@ -85,5 +114,6 @@ pub struct Var<'a, 'render: 'a>(Val<'a, 'render>);
 /// A value returned from `Variable::output`
 ///
 /// Usually it should borrow some displayable value, but may also
-/// contain an boxed thing.
-pub struct Output<'x>(output::OutImpl<'x>);
+/// contain an boxed thing. The flag is set for values that must not be
+/// escaped (see `Output::safe`).
+pub struct Output<'x>(output::OutImpl<'x>, bool);
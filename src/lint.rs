@@ -0,0 +1,372 @@
+use compare::{compare, Comparable};
+use grammar::{Body, Expr, ExprCode, AssignTarget, Template as Tpl};
+use preparser::Syntax;
+use validators::Filter;
+use {Options, Pos};
+
+
+quick_error! {
+    /// A suspicious, but valid, construct found by `Parser::lint`
+    #[derive(Debug, PartialEq)]
+    pub enum Warning {
+        /// Variable assigned by `## let` is never used afterwards
+        UnusedLet(name: String) {
+            description("variable is assigned but never used")
+            display("variable {:?} is assigned but never used", name)
+        }
+        /// Loop variable has the same name as a variable in outer scope
+        ShadowedVariable(name: String) {
+            description("loop variable shadows outer variable")
+            display("loop variable {:?} shadows outer variable", name)
+        }
+        /// Condition doesn't depend on any variable
+        ConstantCondition {
+            description("condition doesn't depend on any variable")
+            display("condition doesn't depend on any variable")
+        }
+        /// Output isn't validated or escaped while template declares
+        /// validators, i.e. filter of the output accepts any value
+        UnvalidatedOutput {
+            description("output is not validated or escaped")
+            display("output is not validated or escaped")
+        }
+        /// Indentation of the line mixes tabs and spaces
+        MixedIndentation {
+            description("indentation mixes tabs and spaces")
+            display("indentation mixes tabs and spaces")
+        }
+        /// Branch can never be taken because of the previous conditions
+        UnreachableBranch {
+            description("branch is unreachable")
+            display("branch is unreachable")
+        }
+    }
+}
+
+enum Binding {
+    Let,
+    Loop,
+}
+
+struct Linter<'a> {
+    options: &'a Options,
+    check_validators: bool,
+    locals: Vec<(&'a str, Pos, Binding, bool)>,
+    warnings: Vec<(Pos, Warning)>,
+}
+
+/// Characters which are special in at least one of the usual output formats
+const SPECIAL_CHARS: &str = "<>&'\"`;{}$\\";
+
+/// Returns true if filter lets any value through
+///
+/// Validators are checked against a value containing special characters,
+/// so `.*` or `[^\n]+` are permissive, and `[a-z]+` isn't.
+fn is_permissive(filter: &Filter) -> bool {
+    match *filter {
+        Filter::NoFilter => true,
+        Filter::Validate(ref re) => re.is_match(SPECIAL_CHARS),
+        Filter::Constraints(ref c) => {
+            c.escapes.is_empty() && c.check(SPECIAL_CHARS).is_ok()
+        }
+        Filter::Escape(..) | Filter::Custom(..) => false,
+    }
+}
+
+fn is_constant(expr: &Expr) -> bool {
+    use grammar::ExprCode::*;
+    match expr.code {
+        Str(..) | Int(..) | Float(..) => true,
+        Var(..) => false,
+        Attr(ref e, _) | Not(ref e) => is_constant(e),
+        Item(ref a, ref b) | Filter(ref a, ref b) |
+        And(ref a, ref b) | Or(ref a, ref b) |
+        Add(ref a, ref b) | Sub(ref a, ref b) |
+        Mul(ref a, ref b) | Div(ref a, ref b) | Mod(ref a, ref b) => {
+            is_constant(a) && is_constant(b)
+        }
+        Comparison(ref first, ref rest) => {
+            is_constant(first) && rest.iter().all(|(_, e)| is_constant(e))
+        }
+        List(ref items) => items.iter().all(is_constant),
+        Dict(ref pairs) => {
+            pairs.iter().all(|(k, v)| is_constant(k) && is_constant(v))
+        }
+        Range(ref a, ref b) => {
+            a.as_ref().map(|x| is_constant(x)).unwrap_or(true) &&
+            b.as_ref().map(|x| is_constant(x)).unwrap_or(true)
+        }
+    }
+}
+
+fn comparable<'a>(expr: &'a Expr) -> Option<Comparable<'a>> {
+    match expr.code {
+        ExprCode::Str(ref s) => Some(s[..].into()),
+        ExprCode::Int(x) => Some(x.into()),
+        ExprCode::Float(x) => Some(x.into()),
+        _ => None,
+    }
+}
+
+/// Evaluates condition if it consists of literals only
+fn constant_bool(expr: &Expr) -> Option<bool> {
+    use grammar::ExprCode::*;
+    match expr.code {
+        Str(ref s) => Some(!s.is_empty()),
+        Int(x) => Some(x != 0),
+        Float(x) => Some(x != 0.),
+        List(ref items) if items.is_empty() => Some(false),
+        Dict(ref pairs) if pairs.is_empty() => Some(false),
+        Not(ref e) => constant_bool(e).map(|x| !x),
+        And(ref a, ref b) => match (constant_bool(a), constant_bool(b)) {
+            (Some(false), _) | (_, Some(false)) => Some(false),
+            (Some(true), Some(true)) => Some(true),
+            _ => None,
+        },
+        Or(ref a, ref b) => match (constant_bool(a), constant_bool(b)) {
+            (Some(true), _) | (_, Some(true)) => Some(true),
+            (Some(false), Some(false)) => Some(false),
+            _ => None,
+        },
+        Comparison(ref first, ref rest) => {
+            let mut left: &Expr = first;
+            for (op, right) in rest {
+                match (comparable(left), comparable(right)) {
+                    (Some(a), Some(b)) => match compare(&a, &b, *op) {
+                        Ok(true) => {}
+                        Ok(false) => return Some(false),
+                        Err(()) => return None,
+                    },
+                    _ => return None,
+                }
+                left = right;
+            }
+            Some(true)
+        }
+        _ => None,
+    }
+}
+
+/// Compares expressions ignoring their positions
+fn same_expr(a: &Expr, b: &Expr) -> bool {
+    use grammar::ExprCode::*;
+    match (&a.code, &b.code) {
+        (Str(a), Str(b)) => a == b,
+        (Int(a), Int(b)) => a == b,
+        (Float(a), Float(b)) => a == b,
+        (Var(a), Var(b)) => a == b,
+        (Attr(a, x), Attr(b, y)) => {
+            x == y && same_expr(a, b)
+        }
+        (Not(a), Not(b)) => same_expr(a, b),
+        (Item(a1, a2), Item(b1, b2)) |
+        (Filter(a1, a2), Filter(b1, b2)) |
+        (And(a1, a2), And(b1, b2)) |
+        (Or(a1, a2), Or(b1, b2)) |
+        (Add(a1, a2), Add(b1, b2)) |
+        (Sub(a1, a2), Sub(b1, b2)) |
+        (Mul(a1, a2), Mul(b1, b2)) |
+        (Div(a1, a2), Div(b1, b2)) |
+        (Mod(a1, a2), Mod(b1, b2)) => {
+            same_expr(a1, b1) && same_expr(a2, b2)
+        }
+        (Comparison(a, x), Comparison(b, y)) => {
+            same_expr(a, b) && x.len() == y.len() &&
+            x.iter().zip(y).all(|((xo, xe), (yo, ye))| {
+                xo == yo && same_expr(xe, ye)
+            })
+        }
+        (List(x), List(y)) => {
+            x.len() == y.len() &&
+            x.iter().zip(y).all(|(a, b)| same_expr(a, b))
+        }
+        (Dict(x), Dict(y)) => {
+            x.len() == y.len() &&
+            x.iter().zip(y).all(|((xk, xv), (yk, yv))| {
+                same_expr(xk, yk) && same_expr(xv, yv)
+            })
+        }
+        _ => false,
+    }
+}
+
+impl<'a> Linter<'a> {
+    fn warn(&mut self, pos: Pos, warning: Warning) {
+        self.warnings.push((pos, warning));
+    }
+    fn pop_scope(&mut self, scope: usize) {
+        while self.locals.len() > scope {
+            let (name, pos, kind, used) = self.locals.pop().unwrap();
+            if let (Binding::Let, false) = (kind, used) {
+                self.warn(pos, Warning::UnusedLet(name.to_string()));
+            }
+        }
+    }
+    fn bind_loop(&mut self, pos: Pos, name: &'a str) {
+        if self.locals.iter().any(|&(n, ..)| n == name) {
+            self.warn(pos, Warning::ShadowedVariable(name.to_string()));
+        }
+        self.locals.push((name, pos, Binding::Loop, false));
+    }
+    fn visit_body(&mut self, body: &'a Body) {
+        use grammar::StatementCode::*;
+
+        let scope = self.locals.len();
+        for st in &body.statements {
+            match st.code {
+                OutputRaw(..) | Joiner | WhitespaceControl { .. } => {}
+                Output { ref expr, ref validator, .. } => {
+                    self.visit_expr(expr);
+                    if self.check_validators {
+                        // unknown validator fails at render time anyway
+                        let filter = validator.as_ref()
+                            .and_then(|name| self.options.filters.get(name))
+                            .unwrap_or(&self.options.default_filter);
+                        if is_permissive(filter) {
+                            self.warn(st.position.0,
+                                      Warning::UnvalidatedOutput);
+                        }
+                    }
+                }
+                Cond { ref conditional, ref otherwise, .. } => {
+                    let mut always_true = false;
+                    for (idx, (cond, body)) in conditional.iter().enumerate()
+                    {
+                        self.visit_expr(cond);
+                        if always_true || conditional[..idx].iter()
+                            .any(|(prev, _)| same_expr(prev, cond))
+                        {
+                            self.warn(cond.position.0,
+                                      Warning::UnreachableBranch);
+                        } else if is_constant(cond) {
+                            self.warn(cond.position.0,
+                                      Warning::ConstantCondition);
+                        }
+                        always_true = always_true ||
+                            constant_bool(cond) == Some(true);
+                        self.visit_body(body);
+                    }
+                    self.visit_body(otherwise);
+                }
+                Loop { ref target, ref iterator, ref filter, ref body, .. }
+                => {
+                    self.visit_expr(iterator);
+                    let inner = self.locals.len();
+                    match *target {
+                        AssignTarget::Var(ref name) => {
+                            self.bind_loop(st.position.0, name);
+                        }
+                        AssignTarget::Pair(ref a, ref b) => {
+                            self.bind_loop(st.position.0, a);
+                            self.bind_loop(st.position.0, b);
+                        }
+                    }
+                    if let Some(ref filter) = *filter {
+                        self.visit_expr(filter);
+                    }
+                    self.visit_body(body);
+                    self.pop_scope(inner);
+                }
+                Alias { ref target, ref value } => {
+                    self.visit_expr(value);
+                    match *target {
+                        AssignTarget::Var(ref name) => {
+                            self.locals.push(
+                                (name, st.position.0, Binding::Let, false));
+                        }
+                        AssignTarget::Pair(..) => unreachable!(),
+                    }
+                }
+            }
+        }
+        self.pop_scope(scope);
+    }
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        use grammar::ExprCode::*;
+
+        match expr.code {
+            Str(..) | Int(..) | Float(..) => {}
+            Var(ref name) => {
+                if let Some(local) = self.locals.iter_mut().rev()
+                    .find(|&&mut (n, ..)| n == name)
+                {
+                    local.3 = true;
+                }
+            }
+            Attr(ref e, _) | Not(ref e) => self.visit_expr(e),
+            Item(ref a, ref b) | Filter(ref a, ref b) |
+            And(ref a, ref b) | Or(ref a, ref b) |
+            Add(ref a, ref b) | Sub(ref a, ref b) |
+            Mul(ref a, ref b) | Div(ref a, ref b) | Mod(ref a, ref b) => {
+                self.visit_expr(a);
+                self.visit_expr(b);
+            }
+            Comparison(ref first, ref rest) => {
+                self.visit_expr(first);
+                for (_, e) in rest {
+                    self.visit_expr(e);
+                }
+            }
+            List(ref items) => {
+                for e in items {
+                    self.visit_expr(e);
+                }
+            }
+            Dict(ref pairs) => {
+                for (k, v) in pairs {
+                    self.visit_expr(k);
+                    self.visit_expr(v);
+                }
+            }
+            Range(ref a, ref b) => {
+                if let Some(ref a) = *a {
+                    self.visit_expr(a);
+                }
+                if let Some(ref b) = *b {
+                    self.visit_expr(b);
+                }
+            }
+        }
+    }
+}
+
+fn check_indentation(data: &str, warnings: &mut Vec<(Pos, Warning)>) {
+    let mut first_char = None;
+    for (idx, line) in data.lines().enumerate() {
+        let indent = &line[..line.len() - line.trim_left().len()];
+        if indent.is_empty() || indent.len() == line.len() {
+            continue;
+        }
+        let tabs = indent.contains('\t');
+        let spaces = indent.contains(' ');
+        let mixed = match (tabs && spaces, first_char) {
+            (true, _) => true,
+            (false, None) => {
+                first_char = Some(tabs);
+                false
+            }
+            (false, Some(tabs_first)) => tabs_first != tabs,
+        };
+        if mixed {
+            warnings.push((Pos { line: idx+1, column: 1 },
+                           Warning::MixedIndentation));
+        }
+    }
+}
+
+pub fn lint(tpl: &Tpl, data: &str) -> Vec<(Pos, Warning)> {
+    let mut linter = Linter {
+        options: &tpl.options,
+        check_validators: !tpl.options.filters.is_empty() ||
+            tpl.options.default_filter != Filter::NoFilter,
+        locals: Vec::new(),
+        warnings: Vec::new(),
+    };
+    linter.visit_body(&tpl.body);
+    let mut warnings = linter.warnings;
+    if tpl.options.syntax == Syntax::Indent {
+        check_indentation(data, &mut warnings);
+    }
+    warnings.sort_by_key(|&(pos, _)| pos);
+    warnings
+}
@@ -118,6 +118,7 @@ fn norm(n: NumberInner) -> NumberInner {
     }
 }
 
+/// Evaluates `a + b`
 pub fn add<'x>(a: Number, b: Number) -> VarRef<'x> {
     use self::NumberInner::*;
     match (norm(a.0), norm(b.0)) {
@@ -142,6 +143,7 @@ pub fn add<'x>(a: Number, b: Number) -> VarRef<'x> {
     }
 }
 
+/// Evaluates `a - b`
 pub fn sub<'x>(a: Number, b: Number) -> VarRef<'x> {
     use self::NumberInner::*;
     match (norm(a.0), norm(b.0)) {
@@ -170,6 +172,7 @@ pub fn sub<'x>(a: Number, b: Number) -> VarRef<'x> {
     }
 }
 
+/// Evaluates `a * b`
 pub fn mul<'x>(a: Number, b: Number) -> VarRef<'x> {
     use self::NumberInner::*;
     match (norm(a.0), norm(b.0)) {
@@ -191,6 +194,7 @@ pub fn mul<'x>(a: Number, b: Number) -> VarRef<'x> {
     }
 }
 
+/// Evaluates `a / b`
 pub fn div<'x>(a: Number, b: Number) -> VarRef<'x> {
     use self::NumberInner::*;
     match (norm(a.0), norm(b.0)) {
@@ -218,6 +222,7 @@ pub fn div<'x>(a: Number, b: Number) -> VarRef<'x> {
     }
 }
 
+/// Evaluates `a % b`
 pub fn modulo<'x>(a: Number, b: Number) -> VarRef<'x> {
     use self::NumberInner::*;
     match (norm(a.0), norm(b.0)) {
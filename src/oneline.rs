@@ -53,7 +53,7 @@ impl Postprocess {
         }
         let st = st.into_iter().map(|s| {
             let code = match s.code {
-                Joiner => Joiner,
+                s@Joiner | s@WhitespaceControl { .. } => s,
                 OutputRaw(text) => OutputRaw(
                     text.split_whitespace()
                         .collect::<Vec<_>>().join(" ")
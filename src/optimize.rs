@@ -21,6 +21,7 @@ fn is_line_statement(code: &StatementCode) -> bool {
     use grammar::StatementCode::*;
     match *code {
         Joiner => false,
+        WhitespaceControl {..} => false,
         OutputRaw(..) => false,
         Output {..} => false,
         Cond {..} => true,
@@ -72,7 +73,7 @@ impl Optimizer {
         }
         let s = dst.into_iter().map(|s| {
             let code = match s.code {
-                Joiner => Joiner,
+                s@Joiner | s@WhitespaceControl { .. } |
                 s@OutputRaw(..) | s@Alias { .. } | s@Output {..} => s,
                 Cond { indent, conditional, otherwise } => Cond {
                     indent,
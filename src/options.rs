@@ -1,7 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
-use preparser::Syntax;
-use validators::Filter;
+use parse_error::{ParseError, ParseErrorEnum};
+use preparser::{Syntax, validator_regex};
+use schema::Type;
+use tokenizer::Delimiters;
+use validators::{Filter, CustomEscape, parse_escapes};
 use {Options};
 
 impl Options {
@@ -9,12 +13,16 @@ impl Options {
     pub fn new() -> Options {
         Options {
             syntax: Syntax::Plain,
+            delimiters: Delimiters::default(),
             new_line_at_eof: None,
             curly: false,
             square: false,
             round: false,
             default_filter: Filter::NoFilter,
             filters: HashMap::new(),
+            schema: Vec::new(),
+            locked_syntax: false,
+            locked_filters: HashSet::new(),
         }
     }
     /// Enables `oneline` syntax by default
@@ -33,4 +41,164 @@ impl Options {
         self.syntax = Syntax::Indent;
         self
     }
+    /// Force (`true`) or forbid (`false`) a newline at the end of output
+    ///
+    /// This is equivalent to `## newline_at_eof: yes|no` in a template.
+    /// When enabled, all trailing newlines are replaced by exactly one,
+    /// otherwise they are all stripped. By default output is left as is.
+    pub fn new_line_at_eof(&mut self, value: bool) -> &mut Self {
+        self.new_line_at_eof = Some(value);
+        self
+    }
+    /// Require `{` and `}` to be balanced in every printed value
+    ///
+    /// This is checked at render time after escaping, so a value can't
+    /// break out of a block in nginx config or JSON. Brackets inside quoted
+    /// strings are not special, they must be balanced too.
+    pub fn balance_curly(&mut self) -> &mut Self {
+        self.curly = true;
+        self
+    }
+    /// Require `[` and `]` to be balanced in every printed value
+    ///
+    /// See `balance_curly` for details.
+    pub fn balance_square(&mut self) -> &mut Self {
+        self.square = true;
+        self
+    }
+    /// Require `(` and `)` to be balanced in every printed value
+    ///
+    /// See `balance_curly` for details.
+    pub fn balance_round(&mut self) -> &mut Self {
+        self.round = true;
+        self
+    }
+    /// Declare a variable that must be passed to the template
+    ///
+    /// This is equivalent to `## requires name: type` in a template.
+    /// When at least one variable is declared, the template is checked
+    /// against the declared types at parse time and any undeclared
+    /// variable is an error.
+    pub fn require(&mut self, name: &str, typ: Type) -> &mut Self {
+        self.schema.retain(|(n, _)| n != name);
+        self.schema.push((name.to_string(), typ));
+        self
+    }
+    /// Set markers of the expression instead of `{{` and `}}`
+    ///
+    /// This is equivalent to `## delimiters: <start> <end>` in a template.
+    /// Whitespace control signs still work, i.e. `<%-` and `+%>`.
+    /// Markers are checked when the template is parsed.
+    pub fn expression_delimiters(&mut self, start: &str, end: &str)
+        -> &mut Self
+    {
+        self.delimiters.expr_start = start.to_string();
+        self.delimiters.expr_end = end.to_string();
+        self
+    }
+    /// Set the prefix of statements instead of `##`
+    ///
+    /// Line comments and line joiners are changed accordingly, e.g. with
+    /// the `%%` prefix, line comment starts with `%%#` and line joiner is
+    /// `%%` at the end of the line.
+    pub fn statement_prefix(&mut self, prefix: &str) -> &mut Self {
+        self.delimiters.statement = prefix.to_string();
+        self
+    }
+    /// Set markers of the comment instead of `{#` and `#}`
+    pub fn comment_delimiters(&mut self, start: &str, end: &str)
+        -> &mut Self
+    {
+        self.delimiters.comment_start = start.to_string();
+        self.delimiters.comment_end = end.to_string();
+        self
+    }
+    /// Add a validator, the same as `## validate name: regex`
+    ///
+    /// Name `default` sets the default validator. The `^` and `$` anchors
+    /// are added if not present. Like with all other filters registered in
+    /// options, a `## validate` or `## filter` statement with the same name
+    /// in the template overrides this one.
+    pub fn validator(&mut self, name: &str, regex: &str)
+        -> Result<&mut Self, ParseError>
+    {
+        let regex = validator_regex(regex)?;
+        self.set_filter(name, Filter::Validate(regex));
+        Ok(self)
+    }
+    /// Add a chain of builtin escapes, the same as `## filter name: escapes`
+    ///
+    /// For example `options.escape("h", "builtin.html_entities")`. Name
+    /// `default` sets the default filter.
+    pub fn escape(&mut self, name: &str, escapes: &str)
+        -> Result<&mut Self, ParseError>
+    {
+        let escapes = parse_escapes(escapes)?;
+        self.set_filter(name, Filter::Escape(escapes));
+        Ok(self)
+    }
+    /// Add an escaper defined by a function
+    ///
+    /// The function receives the output buffer and the value to escape.
+    /// Name `default` sets the default filter. Templates that use custom
+    /// escapers can't be compiled into rust code, and precompiled ones
+    /// must be loaded with `Template::from_bytes_with_options`.
+    pub fn escaper<F>(&mut self, name: &str, func: F) -> &mut Self
+        where F: Fn(&mut String, &str) + Send + Sync + 'static
+    {
+        self.set_filter(name, Filter::Custom(CustomEscape {
+            name: name.to_string(),
+            func: Arc::new(func),
+        }));
+        self
+    }
+    /// Use validator or filter registered with `name` by default
+    ///
+    /// The filter must be registered in these options before. The template
+    /// can still override the default with `## validate default: ...`
+    /// or `## filter default: ...`.
+    pub fn default_filter(&mut self, name: &str)
+        -> Result<&mut Self, ParseError>
+    {
+        let filter = self.filters.get(name).cloned()
+            .ok_or_else(|| ParseErrorEnum::BadFilter(name.to_string()))?;
+        self.default_filter = filter;
+        Ok(self)
+    }
+    /// Forbid template to change syntax set in these options
+    ///
+    /// A `## syntax` statement in the template is an error unless it
+    /// declares the same syntax.
+    pub fn lock_syntax(&mut self) -> &mut Self {
+        self.locked_syntax = true;
+        self
+    }
+    /// Forbid template to change the default filter set in these options
+    ///
+    /// This is a shortcut for `lock_filter("default")`, so template can't
+    /// declare new filters too (see `lock_filter`).
+    pub fn lock_default_filter(&mut self) -> &mut Self {
+        self.lock_filter("default")
+    }
+    /// Forbid template to change validator or filter with the name
+    ///
+    /// Any `## validate` or `## filter` statement that changes the filter,
+    /// including adding a constraint, is an error when the template is
+    /// parsed. Redeclaring exactly the same filter is allowed.
+    ///
+    /// When at least one filter is locked, all other filters registered in
+    /// these options are locked too, and template can't declare new
+    /// filters, otherwise it could bypass the locked filter with something
+    /// like `## validate raw: .*`.
+    pub fn lock_filter(&mut self, name: &str) -> &mut Self {
+        self.locked_filters.insert(name.to_string());
+        self
+    }
+    fn set_filter(&mut self, name: &str, filter: Filter) {
+        if name == "default" {
+            self.default_filter = filter;
+        } else {
+            self.filters.insert(name.to_string(), filter);
+        }
+    }
 }
@@ -11,7 +11,7 @@ pub enum OutImpl<'a> {
 
 impl<'a, T: Display + 'a> From<&'a T> for Output<'a> {
     fn from(t: &'a T) -> Output<'a> {
-        Output(OutImpl::Borrow(t))
+        Output(OutImpl::Borrow(t), false)
     }
 }
 
@@ -21,11 +21,20 @@ impl<'a> Output<'a> {
     /// Usually you should use a reference like `self.into()`, but sometimes
     /// something more complex may be returned using `Output::owned(value)`
     pub fn owned<T: Display + 'a>(t: T) -> Output<'a> {
-        Output(OutImpl::Owned(Box::new(t)))
+        Output(OutImpl::Owned(Box::new(t)), false)
     }
     /// Returns an empty output
     pub fn empty() -> Output<'a> {
-        Output(OutImpl::Borrow(EMPTY_STR))
+        Output(OutImpl::Borrow(EMPTY_STR), false)
+    }
+    /// Mark output as already escaped
+    ///
+    /// Such output is printed as is when escaping filter is used for the
+    /// expression (`## filter`), but is still checked by validators
+    /// (`## validate`). Usually you should wrap a value into `Safe`
+    /// instead of using this method directly.
+    pub fn safe(self) -> Output<'a> {
+        Output(self.0, true)
     }
 }
 
@@ -29,17 +29,89 @@ quick_error! {
             description("Template must start with `## syntax: indent`")
             display("Template must start with `## syntax: indent`")
         }
+        /// Invalid `## delimiters` directive or delimiters in `Options`
+        InvalidDelimiters(value: String, reason: &'static str) {
+            description("invalid delimiters")
+            display("Delimiters {:?} are invalid: {}", value, reason)
+        }
+        /// Duplicate delimiters directive
+        DuplicateDelimitersDirective {
+            description("duplicate delimiters directive")
+            display("duplicate delimiters directive")
+        }
+        /// Value of `## newline_at_eof` is neither `yes` nor `no`
+        InvalidNewlineDirective(value: String) {
+            description("invalid newline_at_eof directive")
+            display("newline_at_eof must be `yes` or `no`, not {:?}", value)
+        }
+        /// Duplicate newline_at_eof directive
+        DuplicateNewlineDirective {
+            description("duplicate newline_at_eof directive")
+            display("duplicate newline_at_eof directive")
+        }
+        /// Template overrides a directive locked in `Options`
+        LockedDirective(directive: String) {
+            description("directive is locked by the application")
+            display("{} is locked by the application and can't be \
+                overridden in template", directive)
+        }
         BadRegexValidator(value: String, err: regex::Error) {
             description("Validator regexp is invalid")
             display("Validator regex {:?} is invalid: {}", value, err)
         }
+        /// Invalid constraint in `## validate name constraint: value`
+        BadConstraint(name: String, value: String, reason: String) {
+            description("validator constraint is invalid")
+            display("Validator constraint {} {:?} is invalid: {}",
+                name, value, reason)
+        }
+        /// Custom escaper can't be compiled into rust code
+        CustomEscaper(name: String) {
+            description("custom escaper can't be compiled into rust code")
+            display("custom escaper {:?} can't be compiled into rust code",
+                name)
+        }
+        /// Expression that can't be compiled yet
+        Unsupported(position: Pos, what: &'static str) {
+            description("expression is not supported yet")
+            display("{}:{}: {} is not supported yet",
+                position.line, position.column, what)
+        }
+        /// Escapes can't be combined in the order given
+        BadEscapeChain(value: String, reason: &'static str) {
+            description("escape chain is invalid")
+            display("Escape chain {:?} is invalid: {}", value, reason)
+        }
         BadFilter(value: String) {
             display("Filter {:?} is unknown", value)
         }
+        /// Invalid type in `## requires` directive
+        BadType(value: String, error: String) {
+            description("type declaration is invalid")
+            display("Type {:?} is invalid: {}", value, error)
+        }
+        /// Template doesn't match the types of the declared variables
+        SchemaMismatch(errors: Vec<(Pos, String)>) {
+            description("template doesn't match declared variable types")
+            display("{}", errors.iter()
+                .map(|&(p, ref e)| format!("{}: {}", p, e))
+                .collect::<Vec<_>>().join("\n"))
+        }
     }
 }
 
 
+impl ParseError {
+    /// Position of the error in template source, if known
+    pub fn position(&self) -> Option<Pos> {
+        match self.0 {
+            ParseErrorEnum::InvalidSyntax(pos, _) => Some(pos),
+            ParseErrorEnum::Unsupported(pos, _) => Some(pos),
+            _ => None,
+        }
+    }
+}
+
 impl<'a> From<CombineError<TokenStream<'a>>> for ParseError {
     fn from(e: CombineError<TokenStream<'a>>) -> ParseError {
         let mut buf = String::with_capacity(100);
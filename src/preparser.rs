@@ -1,7 +1,7 @@
 use regex::{Regex, RegexSet};
 
 use parse_error::{ParseError, ParseErrorEnum};
-use validators::Filter;
+use validators::{Filter, Constraints, parse_escapes};
 use {Options};
 
 
@@ -21,23 +21,30 @@ pub enum Syntax {
 pub enum Token {
     Syntax,
     Validate,
+    Constraint,
     Filter,
-    Comment,
+    Requires,
+    Delimiters,
+    NewlineAtEof,
 }
 
 impl Preparser {
     pub fn new() -> Preparser {
         use self::Token::*;
 
+        // matched against the line with the statement prefix stripped
         let list = &[
-            (r"^##\s*syntax:\s*(\w+)(?:\n|$)", Syntax),
-            (r"^##\s*validate\s+(\w+):[ \t]*(.*)\s*(?:\n|$)",
+            (r"^\s*syntax:\s*(\w+)(?:\n|$)", Syntax),
+            (r"^\s*validate\s+(\w+):[ \t]*(.*)\s*(?:\n|$)",
                 Validate),
-            (r"^##\s*filter\s+(\w+):[ \t]*(.*)\s*(?:\n|$)",
+            (r"^\s*validate\s+(\w+)\s+(\w+):[ \t]*(.*?)\s*(?:\n|$)",
+                Constraint),
+            (r"^\s*filter\s+(\w+):[ \t]*(.*)\s*(?:\n|$)",
                 Filter),
-            (r"^#.*(?:\n|$)", Comment),
-            (r"^###.*(?:\n|$)", Comment),
-            (r"^\s*\n", Comment),
+            (r"^\s*requires\s+(\w+):[ \t]*(.*)\s*(?:\n|$)",
+                Requires),
+            (r"^\s*delimiters:[ \t]*(.*)(?:\n|$)", Delimiters),
+            (r"^\s*newline_at_eof:[ \t]*(\S*)\s*(?:\n|$)", NewlineAtEof),
         ];
         Preparser {
             set: RegexSet::new(list.iter().map(|&(r, _)| r)).unwrap(),
@@ -52,13 +59,31 @@ impl Preparser {
     {
         let mut options = defaults;
         let mut has_syntax = false;
+        let mut has_delimiters = false;
+        let mut has_newline = false;
+        options.delimiters.check()?;
         let mut cur = data;
         loop {
-            match self.set.matches(cur).into_iter().next() {
-                None => break,
+            let line_end = cur.find('\n').map(|x| x+1).unwrap_or(cur.len());
+            let statement = &options.delimiters.statement[..];
+            let rest = if cur.starts_with(statement) {
+                &cur[statement.len()..]
+            } else if cur[..line_end].ends_with('\n') &&
+                    cur[..line_end].trim().is_empty() {
+                // Skip empty line
+                cur = &cur[line_end..];
+                continue;
+            } else {
+                break;
+            };
+            match self.set.matches(rest).into_iter().next() {
+                None => {
+                    // Skip comments and other statements
+                    cur = &cur[line_end..];
+                }
                 Some(idx) => {
-                    let m = self.list[idx].0.captures(cur).unwrap();
-                    cur = &cur[m.get(0).unwrap().end()..];
+                    let m = self.list[idx].0.captures(rest).unwrap();
+                    cur = &rest[m.get(0).unwrap().end()..];
                     match self.list[idx].1 {
                         Token::Syntax => {
                             if has_syntax {
@@ -68,60 +93,91 @@ impl Preparser {
                             }
                             has_syntax = true;
                             let kind = m.get(1).unwrap().as_str();
-                            if kind == "indent" {
-                                options.syntax = Syntax::Indent;
+                            let syntax = if kind == "indent" {
+                                Syntax::Indent
                             } else if kind == "oneline" {
-                                options.syntax = Syntax::Oneline;
+                                Syntax::Oneline
                             } else {
                                 return Err(
                                     ParseErrorEnum::InvalidSyntaxDirective
                                     .into());
+                            };
+                            if options.locked_syntax &&
+                                options.syntax != syntax
+                            {
+                                return Err(ParseErrorEnum::
+                                    LockedDirective("syntax".into()).into());
                             }
+                            options.syntax = syntax;
                         }
                         Token::Validate => {
                             let name = m.get(1).unwrap().as_str();
-                            let mut regex = m.get(2).unwrap()
-                                .as_str().to_string();
-                            // Strip comment
-                            if let Some(end) = regex.as_bytes()
-                                .iter().position(|&x| x == b'#')
-                            {
-                                if end > 0 && regex[..end].ends_with(" ") {
-                                    let nlen = regex[..end].trim().len();
-                                    regex.truncate(nlen);
+                            let regex = validator_regex(
+                                m.get(2).unwrap().as_str())?;
+                            let filter = match filter(&options, name) {
+                                Some(Filter::Constraints(c)) => {
+                                    let mut c = c.clone();
+                                    c.regex = Some(regex);
+                                    Filter::Constraints(c)
                                 }
+                                _ => Filter::Validate(regex),
+                            };
+                            set_filter(&mut options, name, filter)?;
+                        }
+                        Token::Constraint => {
+                            let name = m.get(1).unwrap().as_str();
+                            let key = m.get(2).unwrap().as_str();
+                            let value = m.get(3).unwrap().as_str();
+                            let mut constraints = Constraints::new();
+                            match filter(&options, name) {
+                                Some(Filter::Constraints(c)) => {
+                                    constraints = (**c).clone();
+                                }
+                                Some(Filter::Validate(re)) => {
+                                    constraints.regex = Some(re.clone());
+                                }
+                                _ => {}
                             }
-                            // add anchors if not there
-                            if !regex.starts_with("^") {
-                                regex.insert(0, '^');
-                            }
-                            if !regex.ends_with("$") {
-                                regex.push('$');
-                            }
-                            let regex = Regex::new(&regex)
-                                .map_err(|e| ParseErrorEnum::BadRegexValidator(
-                                    regex.to_string(), e))?;
-                            if name == "default" {
-                                options.default_filter =
-                                    Filter::Validate(regex);
-                            } else {
-                                options.filters.insert(
-                                    name.to_string(), Filter::Validate(regex));
-                            }
+                            constraint(&mut constraints, key, value)?;
+                            set_filter(&mut options, name,
+                                Filter::Constraints(Box::new(constraints)))?;
                         }
                         Token::Filter => {
                             let name = m.get(1).unwrap().as_str();
-                            let filter = m.get(2).unwrap().as_str().parse()?;
-                            if name == "default" {
-                                options.default_filter =
-                                    Filter::Escape(filter);
-                            } else {
-                                options.filters.insert(name.to_string(),
-                                    Filter::Escape(filter));
+                            let filter = parse_escapes(
+                                m.get(2).unwrap().as_str())?;
+                            set_filter(&mut options, name,
+                                Filter::Escape(filter))?;
+                        }
+                        Token::Requires => {
+                            let name = m.get(1).unwrap().as_str();
+                            let typ = m.get(2).unwrap().as_str().parse()?;
+                            options.require(name, typ);
+                        }
+                        Token::Delimiters => {
+                            if has_delimiters {
+                                return Err(ParseErrorEnum::
+                                    DuplicateDelimitersDirective.into());
                             }
+                            has_delimiters = true;
+                            let value = m.get(1).unwrap().as_str();
+                            options.delimiters =
+                                options.delimiters.parse(value)?;
                         }
-                        Token::Comment => {
-                            // Skip
+                        Token::NewlineAtEof => {
+                            if has_newline {
+                                return Err(ParseErrorEnum::
+                                    DuplicateNewlineDirective.into());
+                            }
+                            has_newline = true;
+                            options.new_line_at_eof =
+                                match m.get(1).unwrap().as_str() {
+                                    "yes" => Some(true),
+                                    "no" => Some(false),
+                                    value => return Err(ParseErrorEnum::
+                                        InvalidNewlineDirective(
+                                            value.to_string()).into()),
+                                };
                         }
                     }
                 }
@@ -131,6 +187,96 @@ impl Preparser {
     }
 }
 
+/// Returns the filter with the name, `default` is the default filter
+fn filter<'a>(options: &'a Options, name: &str) -> Option<&'a Filter> {
+    if name == "default" {
+        Some(&options.default_filter)
+    } else {
+        options.filters.get(name)
+    }
+}
+
+/// Replaces the filter unless filters are locked by the application
+///
+/// While any filter is locked, template can't declare new filters or
+/// change any of the registered ones, otherwise it could print values
+/// through a permissive one.
+fn set_filter(options: &mut Options, name: &str, value: Filter)
+    -> Result<(), ParseError>
+{
+    let locked = !options.locked_filters.is_empty() &&
+        filter(options, name) != Some(&value);
+    if locked {
+        return Err(ParseErrorEnum::LockedDirective(
+            format!("filter {:?}", name)).into());
+    }
+    if name == "default" {
+        options.default_filter = value;
+    } else {
+        options.filters.insert(name.to_string(), value);
+    }
+    Ok(())
+}
+
+/// Compiles a regex of `## validate` statement, adding anchors
+pub fn validator_regex(value: &str) -> Result<Regex, ParseError> {
+    let mut regex = value.to_string();
+    // Strip comment
+    if let Some(end) = regex.as_bytes()
+        .iter().position(|&x| x == b'#')
+    {
+        if end > 0 && regex[..end].ends_with(" ") {
+            let nlen = regex[..end].trim().len();
+            regex.truncate(nlen);
+        }
+    }
+    // add anchors if not there
+    if !regex.starts_with("^") {
+        regex.insert(0, '^');
+    }
+    if !regex.ends_with("$") {
+        regex.push('$');
+    }
+    Regex::new(&regex)
+        .map_err(|e| ParseErrorEnum::BadRegexValidator(
+            regex.to_string(), e).into())
+}
+
+/// Applies `## validate name key: value` statement to the constraints
+fn constraint(constraints: &mut Constraints, key: &str, value: &str)
+    -> Result<(), ParseError>
+{
+    let bad = |reason: String| -> ParseError {
+        ParseErrorEnum::BadConstraint(
+            key.to_string(), value.to_string(), reason).into()
+    };
+    match key {
+        "min_length" => {
+            constraints.min_length = Some(value.parse()
+                .map_err(|e| bad(format!("{}", e)))?);
+        }
+        "max_length" => {
+            constraints.max_length = Some(value.parse()
+                .map_err(|e| bad(format!("{}", e)))?);
+        }
+        "categories" => {
+            constraints.set_categories(value)
+                .map_err(|e| bad(format!("{}", e)))?;
+        }
+        "forbid" => {
+            if value.is_empty() {
+                return Err(bad("substring is empty".into()));
+            }
+            constraints.forbid.push(value.to_string());
+        }
+        "escape" => {
+            constraints.escapes = parse_escapes(value)?;
+        }
+        _ => return Err(bad("unknown constraint".into())),
+    }
+    Ok(())
+}
+
 
 #[cfg(test)]
 mod test {
@@ -177,6 +323,18 @@ mod test {
         assert_eq!(opt.syntax, Syntax::Oneline);
     }
 
+    #[test]
+    fn newline_at_eof() {
+        let opt = Preparser::new().scan("## newline_at_eof: no\n",
+            Options::new().new_line_at_eof(true).clone()).unwrap();
+        assert_eq!(opt.new_line_at_eof, Some(false));
+        let opt = Preparser::new().scan("## newline_at_eof: yes\n",
+            Options::new()).unwrap();
+        assert_eq!(opt.new_line_at_eof, Some(true));
+        let opt = Preparser::new().scan("", Options::new()).unwrap();
+        assert_eq!(opt.new_line_at_eof, None);
+    }
+
     #[test]
     fn minimal() {
         let opt = Preparser::new().scan("", Options::new()).unwrap();
@@ -52,10 +52,16 @@ quick_error! {
             description("unknown validator")
             display("validator {:?} is not defined", name)
         }
-        /// Output did not match regex
-        RegexValidationError(data: String, regex: String) {
+        /// Output did not pass the validator, `reason` describes
+        /// the failed constraint
+        ValidationError(data: String, reason: String) {
             description("validation error")
-            display("output {:?} should match regex {:?}", data, regex)
+            display("output {:?} {}", data, reason)
+        }
+        /// Output has unbalanced or mismatched bracket
+        UnbalancedBrackets(data: String, bracket: char) {
+            description("unbalanced brackets in output")
+            display("output {:?} has unbalanced bracket {:?}", data, bracket)
         }
         /// The object can't be boolean
         BoolUnsupported(typename: &'static str) {
@@ -0,0 +1,744 @@
+//! Support code for rust code generated by `Parser::codegen`
+//!
+//! Template interpreter uses the same functions, which makes generated
+//! code render exactly the same output (and errors) as `Template::render`.
+//!
+//! This module is not a stable API, only generated code should use it.
+use std::fmt::{self, Write};
+use std::cmp::min;
+use std::mem::transmute;
+use std::rc::Rc;
+use std::collections::HashMap;
+
+use owning_ref::{OwningRef, ErasedRcRef, Erased};
+use regex::Regex;
+use grammar::OutputMode::{Preserve, Strip, Space};
+use grammar::{self, Body};
+
+use preparser::Syntax;
+use compare::compare;
+use escape;
+use render;
+use render_error::{RenderError, DataError};
+use validators::{BuiltinEscape, Constraints, parse_escapes, escape_all};
+use varmap;
+use vars::{UNDEFINED, TRUE, FALSE, Val, RefVar};
+use {Template, Context, Number, Output, Comparable, Variable, Var, Options};
+
+pub use grammar::{OutputMode, CmpOperator};
+pub use number::{add, sub, mul, div, modulo};
+pub use position::Pos;
+pub use render::Renderer;
+pub use validators::Filter;
+pub use varmap::SubContext;
+pub use vars::VarRef;
+
+/// A dict being constructed by `{"key": value}` expression
+pub type Dict<'render> = HashMap<String, RefVar<'render>>;
+
+/// String literal embedded into the generated code
+///
+/// It behaves exactly like `String` literal in the interpreted template.
+#[derive(Debug)]
+pub struct Str(pub &'static str);
+
+impl<'x> Variable<'x> for Str {
+    fn typename(&self) -> &'static str {
+        "String"
+    }
+    fn as_str_key(&self) -> Result<&str, DataError> {
+        Ok(self.0)
+    }
+    fn output(&self) -> Result<Output<'_>, DataError> {
+        Ok((&self.0).into())
+    }
+    fn as_bool(&self) -> Result<bool, DataError> {
+        Ok(!self.0.is_empty())
+    }
+    fn as_comparable(&self) -> Result<Comparable<'_>, DataError> {
+        Ok(self.0.into())
+    }
+}
+
+/// Create a template with empty body to hold options of generated code
+pub fn template(oneline: bool, new_line_at_eof: Option<bool>,
+    brackets: (bool, bool, bool),
+    default_filter: Filter, filters: Vec<(&str, Filter)>)
+    -> Template
+{
+    let mut options = Options::new();
+    if oneline {
+        options.syntax = Syntax::Oneline;
+    }
+    options.new_line_at_eof = new_line_at_eof;
+    let (curly, square, round) = brackets;
+    options.curly = curly;
+    options.square = square;
+    options.round = round;
+    options.default_filter = default_filter;
+    options.filters = filters.into_iter()
+        .map(|(name, filter)| (name.to_string(), filter))
+        .collect();
+    render::template(grammar::Template {
+        options,
+        body: Body { statements: Vec::new() },
+    }).expect("empty body is compiled")
+}
+
+/// Make a validator filter
+pub fn validate(regex: &str) -> Filter {
+    Filter::Validate(Regex::new(regex).expect("regex is valid"))
+}
+
+/// Make a validator filter combined of several constraints
+pub fn constraints(regex: Option<&str>,
+    min_length: Option<usize>, max_length: Option<usize>,
+    categories: &str, forbid: &[&str], escapes: &str)
+    -> Filter
+{
+    let mut c = Constraints::new();
+    c.regex = regex.map(|x| Regex::new(x).expect("regex is valid"));
+    c.min_length = min_length;
+    c.max_length = max_length;
+    c.set_categories(categories).expect("categories are valid");
+    c.forbid = forbid.iter().map(|x| x.to_string()).collect();
+    if !escapes.is_empty() {
+        c.escapes = parse_escapes(escapes).expect("escape is known");
+    }
+    Filter::Constraints(Box::new(c))
+}
+
+/// Make a builtin escape filter
+pub fn escape(name: &str) -> Filter {
+    Filter::Escape(parse_escapes(name).expect("escape is known"))
+}
+
+/// Render the template using `body` function
+pub fn render<'render, F>(tpl: &Template,
+    root: &Context<'render>, body: F)
+    -> Result<String, RenderError>
+    where F: for<'x> FnOnce(&mut Renderer, &mut SubContext<'x, 'render>)
+                 -> fmt::Result
+{
+    let mut rnd = renderer(tpl);
+    body(&mut rnd, &mut SubContext::from(root))?;
+    finish(rnd)
+}
+
+pub(crate) fn renderer(tpl: &Template) -> Renderer {
+    Renderer {
+        template: render::inner(tpl).clone(),
+        buf: String::new(),
+        errors: Vec::new(),
+        nothing: Rc::new(()),
+        tail_mode: Preserve,
+        frozen: 0,
+    }
+}
+
+pub(crate) fn finish(mut rnd: Renderer) -> Result<String, RenderError> {
+    if !rnd.errors.is_empty() {
+        return Err(RenderError::Data(rnd.errors));
+    }
+    if let Some(newline) = rnd.template.options.new_line_at_eof {
+        let len = rnd.buf.trim_right_matches('\n').len();
+        rnd.buf.truncate(len);
+        // empty output is kept empty, it's not a line
+        if newline && len > 0 {
+            rnd.buf.push('\n');
+        }
+    }
+    Ok(rnd.buf)
+}
+
+/// Write raw text of the template
+pub fn raw(r: &mut Renderer, text: &str) {
+    let base_mode = if r.template.options.syntax == Syntax::Oneline {
+        Space
+    } else {
+        Preserve
+    };
+    r.tail_mode = match r.tail_mode {
+        Preserve => {
+            let trim_len = text.trim_right().len();
+            r.buf.push_str(text);
+            if trim_len > 0 && trim_len <= text.len() {
+                let spaces = text.len() - trim_len;
+                r.frozen = r.buf.len() - spaces;
+            }
+            base_mode
+        }
+        Strip => {
+            let off = r.frozen;
+            r.buf.truncate(off);
+            let s = text.trim_left();
+            if !s.is_empty() {
+                let trim_len = s.trim_right().len();
+                let spaces = s.len() - trim_len;
+                r.buf.push_str(s);
+                r.frozen = r.buf.len() - spaces;
+                base_mode
+            } else {
+                Strip
+            }
+        }
+        Space => {
+            let s = text.trim_left();
+            if !s.is_empty() {
+                let off = r.frozen;
+                r.buf.truncate(off);
+                let trim_len = s.trim_right().len();
+                let spaces = s.len() - trim_len;
+                if !r.buf.is_empty() {
+                    r.buf.push(' ');
+                }
+                r.buf.push_str(s);
+                r.frozen = r.buf.len() - spaces;
+                base_mode
+            } else {
+                Space
+            }
+        }
+    }
+}
+
+/// Line joiner `##` at the end of the line
+pub fn joiner(r: &mut Renderer) {
+    r.tail_mode = Space;
+}
+
+/// Whitespace control of the statement line or comment: `{#- c +#}`
+///
+/// Whitespace before is handled like in `output`, but if both sides are
+/// `Space` whitespace around is collapsed into a single space.
+pub fn whitespace_control(r: &mut Renderer, left_ws: OutputMode,
+    right_ws: OutputMode)
+{
+    match min(left_ws, r.tail_mode) {
+        Preserve => {
+            r.frozen = r.buf.len();
+            r.tail_mode = right_ws;
+        }
+        Strip => {
+            let off = r.frozen;
+            r.buf.truncate(off);
+            r.tail_mode = right_ws;
+        }
+        Space if right_ws == Strip => {
+            let off = r.frozen;
+            r.buf.truncate(off);
+            if !r.buf.is_empty() {
+                r.buf.push(' ');
+            }
+            r.frozen = r.buf.len();
+            r.tail_mode = Strip;
+        }
+        Space => {
+            r.tail_mode = Space;
+        }
+    }
+}
+
+/// Output `{{ expression }}`, `pos` is position of the whole statement
+pub fn output(r: &mut Renderer, left_ws: OutputMode, var: &VarRef<'_>,
+    validator: Option<&str>, pos: Pos, right_ws: OutputMode)
+    -> fmt::Result
+{
+    use render_error::DataError::*;
+
+    match min(left_ws, r.tail_mode) {
+        Preserve => {},
+        Strip => {
+            let off = r.frozen;
+            r.buf.truncate(off);
+        }
+        Space => {
+            let off = r.frozen;
+            r.buf.truncate(off);
+            if !r.buf.is_empty() {
+                r.buf.push(' ');
+            }
+        }
+    }
+    let filter = match validator {
+        Some(name) => {
+            match r.template.options.filters.get(name) {
+                Some(val) => val,
+                None => {
+                    r.errors.push((pos, UnknownValidator(name.to_string())));
+                    &r.template.options.default_filter
+                }
+            }
+        }
+        None => {
+            &r.template.options.default_filter
+        }
+    };
+    let start = r.buf.len();
+    match *filter {
+        Filter::Escape(ref escapes)
+        if escapes.first() == Some(&BuiltinEscape::Json)
+        => {
+            // serializes the variable itself, not its output
+            let mut buf = String::with_capacity(1024);
+            let res = match escape::json_value(&mut buf, &**var) {
+                Ok(()) => escape_all(&escapes[1..], &mut r.buf, &buf),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                r.errors.push((pos, e));
+            }
+        }
+        _ => match var.output() {
+            Ok(value) => match *filter {
+                Filter::NoFilter => {
+                    write!(&mut r.buf, "{}", value.0)?;
+                }
+                Filter::Validate(ref re) => {
+                    write!(&mut r.buf, "{}", value.0)?;
+                    if !re.is_match(&r.buf[start..]) {
+                        r.errors.push((pos,
+                            DataError::ValidationError(
+                                r.buf[start..].to_string(),
+                                format!("should match regex {:?}",
+                                    re.as_str()))));
+                    }
+                }
+                Filter::Constraints(ref c) => {
+                    let mut buf = String::with_capacity(1024);
+                    write!(&mut buf, "{}", value.0)?;
+                    if let Err(reason) = c.check(&buf) {
+                        r.errors.push((pos,
+                            DataError::ValidationError(buf.clone(), reason)));
+                    }
+                    if value.1 {
+                        r.buf.push_str(&buf);
+                    } else if let Err(e) =
+                        escape_all(&c.escapes, &mut r.buf, &buf)
+                    {
+                        r.errors.push((pos, e));
+                    }
+                }
+                Filter::Escape(_) | Filter::Custom(_) if value.1 => {
+                    write!(&mut r.buf, "{}", value.0)?;
+                }
+                Filter::Escape(ref escapes) => {
+                    let mut buf = String::with_capacity(1024);
+                    write!(&mut buf, "{}", value.0)?;
+                    if let Err(e) = escape_all(escapes, &mut r.buf, &buf) {
+                        r.errors.push((pos, e));
+                    }
+                }
+                Filter::Custom(ref c) => {
+                    let mut buf = String::with_capacity(1024);
+                    write!(&mut buf, "{}", value.0)?;
+                    (c.func)(&mut r.buf, &buf);
+                }
+            },
+            Err(e) => {
+                r.errors.push((pos, e));
+            }
+        }
+    }
+    if let Some(bracket) = unbalanced(&r.template.options, &r.buf[start..]) {
+        r.errors.push((pos, UnbalancedBrackets(
+            r.buf[start..].to_string(), bracket)));
+    }
+    r.frozen = r.buf.len();
+    r.tail_mode = right_ws;
+    Ok(())
+}
+
+/// Returns first bracket that is not balanced in the output value
+///
+/// Only brackets enabled in options are checked.
+fn unbalanced(options: &Options, value: &str) -> Option<char> {
+    if !(options.curly || options.square || options.round) {
+        return None;
+    }
+    let mut stack = Vec::new();
+    for c in value.chars() {
+        match c {
+            '{' if options.curly => stack.push(('{', '}')),
+            '[' if options.square => stack.push(('[', ']')),
+            '(' if options.round => stack.push(('(', ')')),
+            '}' if options.curly => {}
+            ']' if options.square => {}
+            ')' if options.round => {}
+            _ => continue,
+        }
+        if let '}' | ']' | ')' = c {
+            match stack.pop() {
+                Some((_, close)) if close == c => {}
+                _ => return Some(c),
+            }
+        }
+    }
+    stack.pop().map(|(open, _)| open)
+}
+
+/// Evaluates condition of `## if` and `## skip if`
+///
+/// Values which can't be converted to boolean are true, errors are
+/// treated as false.
+pub fn condition(r: &mut Renderer, value: &VarRef<'_>, pos: Pos) -> bool {
+    use render_error::DataError::*;
+
+    match value.as_bool() {
+        Ok(true) | Err(BoolUnsupported(..)) => true,
+        Ok(false) => false,
+        Err(e) => {
+            r.errors.push((pos, e));
+            false
+        }
+    }
+}
+
+fn nothing<'render>(r: &Renderer) -> Rc<Erased+'render> {
+    r.nothing.clone()
+}
+
+/// Value that is returned on errors
+pub fn undefined<'render>(r: &Renderer) -> VarRef<'render> {
+    OwningRef::new(nothing(r)).map(|_| UNDEFINED as &Variable)
+}
+
+/// Boolean result of an operator
+pub fn boolean<'render>(r: &Renderer, value: bool) -> VarRef<'render> {
+    OwningRef::new(nothing(r))
+        .map(|_| if value { TRUE } else { FALSE } as &Variable)
+}
+
+/// Literal value embedded into the code
+pub fn constant<'render, V>(r: &Renderer, value: &'static V)
+    -> VarRef<'render>
+    where V: Variable<'render> + 'static
+{
+    OwningRef::new(nothing(r)).map(|_| value as &Variable)
+}
+
+/// Get variable from the context
+pub fn var<'x, 'render>(r: &mut Renderer, ctx: &SubContext<'x, 'render>,
+    name: &str, pos: Pos)
+    -> VarRef<'render>
+{
+    found(r, varmap::get(ctx, name), pos)
+}
+
+pub(crate) fn found<'render>(r: &mut Renderer,
+    value: Result<VarRef<'render>, DataError>, pos: Pos)
+    -> VarRef<'render>
+{
+    match value {
+        Ok(x) => x,
+        Err(e) => {
+            r.errors.push((pos, e));
+            undefined(r)
+        }
+    }
+}
+
+/// Set local variable, used for `## let` and loop targets
+pub fn set<'x, 'render>(ctx: &mut SubContext<'x, 'render>,
+    name: &'static str, value: VarRef<'render>)
+{
+    let name: ErasedRcRef<str> = OwningRef::new(Rc::new(()))
+        .map(|_| name).erase_owner();
+    varmap::set(ctx, name, value);
+}
+
+/// Evaluates `value.name`
+pub fn attr<'render>(r: &mut Renderer, value: VarRef<'render>,
+    name: &str, pos: Pos)
+    -> VarRef<'render>
+{
+    use render_error::DataError::*;
+
+    match value.try_map(|v| match v.attr(name) {
+        Ok(Var(Val::Ref(x))) => Ok(x),
+        Ok(Var(Val::Rc(v))) => Err(v),
+        Err(e) => {
+            if !matches!(e, AttrNotFound) {
+                r.errors.push((pos, e));
+            }
+            Err(undefined(r))
+        }
+    }) {
+        Ok(x) => x,
+        Err(v) => v,
+    }
+}
+
+/// Evaluates `value[index]`
+pub fn item<'render>(r: &mut Renderer, value: VarRef<'render>,
+    index: VarRef<'render>, pos: Pos)
+    -> VarRef<'render>
+{
+    use render_error::DataError::*;
+
+    match value.try_map(|v| match v.index(&*index) {
+        Ok(Var(Val::Ref(x))) => Ok(x),
+        Ok(Var(Val::Rc(v))) => Err(v),
+        Err(e) => {
+            // we allow AttrNotFound too, for the cases where
+            // square brackets equal to attribute access (JSON)
+            if !matches!(e, IndexNotFound | AttrNotFound) {
+                r.errors.push((pos, e));
+            }
+            Err(undefined(r))
+        }
+    }) {
+        Ok(x) => x,
+        Err(v) => v,
+    }
+}
+
+/// Evaluates math operator `op` (one of `add`, `sub`, ...)
+pub fn math<'render>(r: &mut Renderer,
+    op: fn(Number, Number) -> VarRef<'render>,
+    left: VarRef<'render>, left_pos: Pos,
+    right: VarRef<'render>, right_pos: Pos)
+    -> VarRef<'render>
+{
+    match (left.as_number(), right.as_number()) {
+        (Ok(a), Ok(b)) => {
+            op(a, b)
+        }
+        (Ok(_), Err(eb)) => {
+            r.errors.push((right_pos, eb));
+            left
+        }
+        (Err(ea), Ok(_)) => {
+            r.errors.push((left_pos, ea));
+            right
+        }
+        (Err(ea), Err(eb)) => {
+            r.errors.push((left_pos, ea));
+            r.errors.push((right_pos, eb));
+            undefined(r)
+        }
+    }
+}
+
+/// Evaluates `left and right`, `right` is evaluated lazily
+pub fn and<'render, F>(r: &mut Renderer, left: VarRef<'render>, pos: Pos,
+    right: F)
+    -> VarRef<'render>
+    where F: FnOnce(&mut Renderer) -> VarRef<'render>
+{
+    match and_left(r, left, pos) {
+        Some(value) => value,
+        None => right(r),
+    }
+}
+
+/// Returns result of `left and ...` if it's known without the right side
+pub(crate) fn and_left<'render>(r: &mut Renderer, left: VarRef<'render>,
+    pos: Pos)
+    -> Option<VarRef<'render>>
+{
+    use render_error::DataError::*;
+
+    match left.as_bool() {
+        Ok(true) | Err(BoolUnsupported(_)) => None,
+        Ok(false) => Some(left),
+        Err(e) => {
+            r.errors.push((pos, e));
+            // this is kinda undefined, so false
+            Some(undefined(r))
+        }
+    }
+}
+
+/// Evaluates `left or right`, `right` is evaluated lazily
+pub fn or<'render, F>(r: &mut Renderer, left: VarRef<'render>, pos: Pos,
+    right: F)
+    -> VarRef<'render>
+    where F: FnOnce(&mut Renderer) -> VarRef<'render>
+{
+    match or_left(r, left, pos) {
+        Some(value) => value,
+        None => right(r),
+    }
+}
+
+/// Returns result of `left or ...` if it's known without the right side
+pub(crate) fn or_left<'render>(r: &mut Renderer, left: VarRef<'render>,
+    pos: Pos)
+    -> Option<VarRef<'render>>
+{
+    use render_error::DataError::*;
+
+    match left.as_bool() {
+        Ok(true) | Err(BoolUnsupported(_)) => Some(left),
+        Ok(false) => None,
+        Err(e) => {
+            r.errors.push((pos, e));
+            // this is kinda undefined, so false
+            None
+        }
+    }
+}
+
+/// Evaluates `not value`
+pub fn not<'render>(r: &mut Renderer, value: VarRef<'render>, pos: Pos)
+    -> VarRef<'render>
+{
+    match value.as_bool() {
+        Ok(value) => boolean(r, !value),
+        Err(e) => {
+            r.errors.push((pos, e));
+            undefined(r)
+        }
+    }
+}
+
+/// Evaluates chain of comparisons `first < a <= b ...`
+///
+/// The `next` function returns operator and the value for each of the `num`
+/// items of the chain. Values are evaluated lazily.
+pub fn comparison<'render, F>(r: &mut Renderer, first: VarRef<'render>,
+    pos: Pos, num: usize, mut next: F)
+    -> VarRef<'render>
+    where F: FnMut(&mut Renderer, usize) -> (CmpOperator, VarRef<'render>)
+{
+    assert!(num > 0);
+    let mut cur_exp = first;
+    for i in 0..num {
+        if !comparable(r, &cur_exp, pos) {
+            return undefined(r);
+        }
+        let (oper, rexpr) = next(r, i);
+        match compare_pair(r, &cur_exp, &rexpr, oper, pos) {
+            Some(true) => {}
+            Some(false) => return boolean(r, false),
+            None => return undefined(r),
+        }
+        cur_exp = rexpr;
+    }
+    boolean(r, true)
+}
+
+/// Checks that left side of the comparison can be compared at all
+pub(crate) fn comparable(r: &mut Renderer, value: &VarRef<'_>, pos: Pos)
+    -> bool
+{
+    match value.as_comparable() {
+        Ok(_) => true,
+        Err(e) => {
+            r.errors.push((pos, e));
+            false
+        }
+    }
+}
+
+/// Compares two values, returns `None` on error
+pub(crate) fn compare_pair(r: &mut Renderer,
+    left: &VarRef<'_>, right: &VarRef<'_>, oper: CmpOperator, pos: Pos)
+    -> Option<bool>
+{
+    use render_error::DataError::*;
+
+    let (lcmp, rcmp) = match (left.as_comparable(), right.as_comparable()) {
+        (Ok(l), Ok(r)) => (l, r),
+        (Err(e), _) | (_, Err(e)) => {
+            r.errors.push((pos, e));
+            return None;
+        }
+    };
+    match compare(&lcmp, &rcmp, oper) {
+        Ok(value) => Some(value),
+        Err(()) => {
+            r.errors.push((pos,
+                Incomparable(left.typename(), right.typename())));
+            None
+        }
+    }
+}
+
+/// Makes a value of `[a, b, ...]` expression
+pub fn list<'render>(items: Vec<VarRef<'render>>) -> VarRef<'render> {
+    let list = items.into_iter().map(RefVar).collect::<Vec<_>>();
+    OwningRef::new(Rc::new(list))
+        .map(|x: &Vec<_>| x as &Variable).erase_owner()
+}
+
+/// Adds a `key: value` pair to the dict being constructed
+pub fn insert<'render>(r: &mut Renderer, dict: &mut Dict<'render>,
+    key: VarRef<'render>, key_pos: Pos, value: VarRef<'render>)
+{
+    match key.as_str_key() {
+        Ok(kstr) => {
+            // TODO(tailhook) no clone?
+            dict.insert(kstr.to_string(), RefVar(value));
+        }
+        Err(e) => {
+            r.errors.push((key_pos, e));
+        }
+    }
+}
+
+/// Makes a value of `{key: value, ...}` expression
+pub fn dict<'render>(dict: Dict<'render>) -> VarRef<'render> {
+    OwningRef::new(Rc::new(dict))
+        .map(|x: &HashMap<_, _>| x as &Variable).erase_owner()
+}
+
+/// Runs `body` for each item of the `value` in `## for x in value`
+pub fn for_each<'render, F>(r: &mut Renderer, value: VarRef<'render>,
+    pos: Pos, mut body: F)
+    -> fmt::Result
+    where F: FnMut(&mut Renderer, VarRef<'render>) -> fmt::Result
+{
+    let iter = match value.iterate() {
+        Ok(iter) => iter,
+        Err(e) => {
+            r.errors.push((pos, e));
+            // treating as empty loop
+            return Ok(());
+        }
+    };
+    for item in iter {
+        body(r, loop_var(&value, item))?;
+    }
+    Ok(())
+}
+
+/// Runs `body` for each pair of the `value` in `## for a, b in value`
+pub fn for_each_pair<'render, F>(r: &mut Renderer, value: VarRef<'render>,
+    pos: Pos, mut body: F)
+    -> fmt::Result
+    where F: FnMut(&mut Renderer, VarRef<'render>, VarRef<'render>)
+                -> fmt::Result
+{
+    let iter = match value.iterate_pairs() {
+        Ok(iter) => iter,
+        Err(e) => {
+            r.errors.push((pos, e));
+            // treating as empty loop
+            return Ok(());
+        }
+    };
+    for (var_a, var_b) in iter {
+        body(r, loop_var(&value, var_a), loop_var(&value, var_b))?;
+    }
+    Ok(())
+}
+
+/// Converts an item yielded by iterator over `value` into a reference
+pub(crate) fn loop_var<'x, 'render>(value: &VarRef<'render>,
+    var: Var<'x, 'render>)
+    -> VarRef<'render>
+{
+    match var {
+        Var(Val::Ref(r)) => {
+            value.clone()
+            // This transmute should be safe, because we only transmute
+            // lifetime and item has basically same lifetime as the
+            // `value` because it's borrowed from it even if rust
+            // doesn't think so
+            .map(|_| unsafe { transmute(r) })
+        }
+        Var(Val::Rc(r)) => r,
+    }
+}
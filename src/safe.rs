@@ -0,0 +1,54 @@
+use render_error::DataError;
+use vars::Variable;
+use {Output, Number, Comparable};
+
+
+/// A wrapper for the value that is already escaped
+///
+/// Output of the wrapped value is not escaped by `## filter`, but still
+/// checked by `## validate` statements. This is useful to put
+/// a sanitized piece of HTML into the template that escapes everything
+/// else by default:
+///
+/// ```rust
+/// # use trimmer::{Parser, Context, Safe};
+/// let tpl = Parser::new().parse(
+///     "## filter default: builtin.html_entities\n\
+///      <p>{{ title }}</p>{{ body }}").unwrap();
+/// let title = "<&>";
+/// let body = Safe("<p>text</p>");
+/// let mut ctx = Context::new();
+/// ctx.set("title", &title);
+/// ctx.set("body", &body);
+/// assert_eq!(tpl.render(&ctx).unwrap(),
+///            "<p>&lt;&amp;&gt;</p><p>text</p>");
+/// ```
+///
+/// Only the value itself is marked, i.e. attributes and items of the
+/// wrapped value can't be accessed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Safe<T>(pub T);
+
+/// An owned piece of already escaped markup
+pub type Markup = Safe<String>;
+
+impl<'render, T: Variable<'render>> Variable<'render> for Safe<T> {
+    fn typename(&self) -> &'static str {
+        self.0.typename()
+    }
+    fn output(&self) -> Result<Output<'_>, DataError> {
+        self.0.output().map(Output::safe)
+    }
+    fn as_str_key(&self) -> Result<&str, DataError> {
+        self.0.as_str_key()
+    }
+    fn as_bool(&self) -> Result<bool, DataError> {
+        self.0.as_bool()
+    }
+    fn as_number(&self) -> Result<Number, DataError> {
+        self.0.as_number()
+    }
+    fn as_comparable(&self) -> Result<Comparable<'_>, DataError> {
+        self.0.as_comparable()
+    }
+}
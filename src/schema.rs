@@ -0,0 +1,499 @@
+use std::fmt;
+use std::str::FromStr;
+
+use grammar::{Body, Expr, AssignTarget, Template as Tpl};
+use parse_error::{ParseError, ParseErrorEnum};
+use validators::{Filter, BuiltinEscape};
+use {Pos, Options};
+
+
+/// Expected type of a context variable
+///
+/// This is used to declare the shape of the context either with
+/// `Options::require` or with the `## requires name: type` directive,
+/// so the template is checked against it at parse time.
+///
+/// In the directive the types are written as `str`, `number`, `bool`, `any`,
+/// `[T]` for a list, `{*: T}` for a map and `{name: T, other?: T}` for
+/// an object (the question mark denotes an optional attribute).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    /// Any value, nothing is checked
+    Any,
+    /// A string
+    Str,
+    /// An integer or floating point number
+    Number,
+    /// A boolean
+    Bool,
+    /// A sequence of the values of the same type (`## for x in list`)
+    List(Box<Type>),
+    /// A mapping with arbitrary string keys (`## for k, v in map`)
+    Map(Box<Type>),
+    /// An object with the fixed set of attributes
+    Object(Vec<Attr>),
+}
+
+/// An attribute of the `Type::Object`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attr {
+    /// Name of the attribute
+    pub name: String,
+    /// Type of the attribute value
+    pub typ: Type,
+    /// Whether attribute might be absent
+    ///
+    /// Absent attributes render as an empty string and are false in
+    /// conditions, but can't be used in arithmetic or comparisons.
+    pub optional: bool,
+}
+
+/// Type of an expression as inferred by checker
+#[derive(Clone)]
+struct Ty {
+    typ: Type,
+    maybe_undefined: bool,
+}
+
+struct Checker<'a> {
+    schema: &'a [(String, Type)],
+    options: &'a Options,
+    locals: Vec<(&'a str, Ty)>,
+    errors: Vec<(Pos, String)>,
+}
+
+impl Type {
+    /// A list of values of the specified type
+    pub fn list(item: Type) -> Type {
+        Type::List(Box::new(item))
+    }
+    /// A map with string keys and values of the specified type
+    pub fn map(value: Type) -> Type {
+        Type::Map(Box::new(value))
+    }
+    /// An object with no attributes, use `attr()` to add some
+    pub fn object() -> Type {
+        Type::Object(Vec::new())
+    }
+    /// Add a required attribute to the object
+    ///
+    /// # Panics
+    ///
+    /// When called on anything other than `Type::Object`
+    pub fn attr(self, name: &str, typ: Type) -> Type {
+        self.add_attr(name, typ, false)
+    }
+    /// Add an optional attribute to the object
+    ///
+    /// # Panics
+    ///
+    /// When called on anything other than `Type::Object`
+    pub fn optional_attr(self, name: &str, typ: Type) -> Type {
+        self.add_attr(name, typ, true)
+    }
+    fn add_attr(self, name: &str, typ: Type, optional: bool) -> Type {
+        match self {
+            Type::Object(mut attrs) => {
+                attrs.retain(|a| a.name != name);
+                attrs.push(Attr { name: name.to_string(), typ, optional });
+                Type::Object(attrs)
+            }
+            _ => panic!("attributes can only be added to an object"),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Type::Any => write!(f, "any"),
+            Type::Str => write!(f, "str"),
+            Type::Number => write!(f, "number"),
+            Type::Bool => write!(f, "bool"),
+            Type::List(ref item) => write!(f, "[{}]", item),
+            Type::Map(ref value) => write!(f, "{{*: {}}}", value),
+            Type::Object(ref attrs) => {
+                write!(f, "{{")?;
+                for (i, a) in attrs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}{}: {}", a.name,
+                        if a.optional { "?" } else { "" }, a.typ)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+struct TypeParser<'a> {
+    buf: &'a str,
+    off: usize,
+}
+
+impl<'a> TypeParser<'a> {
+    fn skip_ws(&mut self) {
+        let rest = &self.buf[self.off..];
+        self.off += rest.len() - rest.trim_left().len();
+    }
+    fn eat(&mut self, ch: char) -> bool {
+        self.skip_ws();
+        if self.buf[self.off..].starts_with(ch) {
+            self.off += ch.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, ch: char) -> Result<(), String> {
+        if self.eat(ch) {
+            Ok(())
+        } else {
+            Err(format!("expected {:?} at {:?}", ch, &self.buf[self.off..]))
+        }
+    }
+    fn ident(&mut self) -> Option<&'a str> {
+        self.skip_ws();
+        let rest = &self.buf[self.off..];
+        let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            None
+        } else {
+            self.off += len;
+            Some(&rest[..len])
+        }
+    }
+    fn parse_type(&mut self) -> Result<Type, String> {
+        if self.eat('[') {
+            let item = self.parse_type()?;
+            self.expect(']')?;
+            return Ok(Type::list(item));
+        }
+        if self.eat('{') {
+            if self.eat('*') {
+                self.expect(':')?;
+                let value = self.parse_type()?;
+                self.expect('}')?;
+                return Ok(Type::map(value));
+            }
+            let mut typ = Type::object();
+            while !self.eat('}') {
+                let name = self.ident()
+                    .ok_or_else(|| format!("expected attribute name at {:?}",
+                                           &self.buf[self.off..]))?;
+                let optional = self.eat('?');
+                self.expect(':')?;
+                let attr_type = self.parse_type()?;
+                typ = typ.add_attr(name, attr_type, optional);
+                if !self.eat(',') {
+                    self.expect('}')?;
+                    break;
+                }
+            }
+            return Ok(typ);
+        }
+        match self.ident() {
+            Some("any") => Ok(Type::Any),
+            Some("str") => Ok(Type::Str),
+            Some("number") => Ok(Type::Number),
+            Some("bool") => Ok(Type::Bool),
+            Some(x) => Err(format!("unknown type {:?}", x)),
+            None => Err(format!("expected type at {:?}",
+                                &self.buf[self.off..])),
+        }
+    }
+}
+
+impl FromStr for Type {
+    type Err = ParseError;
+    fn from_str(val: &str) -> Result<Type, ParseError> {
+        let mut p = TypeParser { buf: val, off: 0 };
+        let typ = p.parse_type()
+            .map_err(|e| ParseErrorEnum::BadType(val.to_string(), e))?;
+        p.skip_ws();
+        let rest = &p.buf[p.off..];
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(ParseErrorEnum::BadType(val.to_string(),
+                format!("unexpected {:?}", rest)).into());
+        }
+        Ok(typ)
+    }
+}
+
+impl Ty {
+    fn new(typ: Type) -> Ty {
+        Ty { typ, maybe_undefined: false }
+    }
+    fn undefined() -> Ty {
+        Ty { typ: Type::Any, maybe_undefined: true }
+    }
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, pos: Pos, msg: String) {
+        self.errors.push((pos, msg));
+    }
+    fn visit_body(&mut self, body: &'a Body) {
+        use grammar::StatementCode::*;
+
+        let scope = self.locals.len();
+        for st in &body.statements {
+            match st.code {
+                OutputRaw(..) | Joiner | WhitespaceControl { .. } => {}
+                Output { ref expr, ref validator, .. } => {
+                    let ty = self.expr(expr);
+                    let filter = validator.as_ref()
+                        .and_then(|name| self.options.filters.get(name))
+                        .unwrap_or(&self.options.default_filter);
+                    match ty.typ {
+                        // serializes any value
+                        _ if matches!(*filter, Filter::Escape(ref escapes)
+                            if escapes.first() == Some(&BuiltinEscape::Json))
+                        => {}
+                        Type::List(..) | Type::Map(..) | Type::Object(..) => {
+                            self.error(expr.position.0, format!(
+                                "value of type `{}` can't be printed",
+                                ty.typ));
+                        }
+                        _ => {}
+                    }
+                }
+                Cond { ref conditional, ref otherwise, .. } => {
+                    for (cond, body) in conditional {
+                        self.expr(cond);
+                        self.visit_body(body);
+                    }
+                    self.visit_body(otherwise);
+                }
+                Loop { ref target, ref iterator, ref filter, ref body, .. }
+                => {
+                    let ty = self.expr(iterator);
+                    let inner = self.locals.len();
+                    match *target {
+                        AssignTarget::Var(ref name) => {
+                            let item = self.iterate(iterator.position.0, ty);
+                            self.locals.push((name, item));
+                        }
+                        AssignTarget::Pair(ref a, ref b) => {
+                            let value = self.iterate_pairs(
+                                iterator.position.0, ty);
+                            self.locals.push((a, Ty::new(Type::Str)));
+                            self.locals.push((b, value));
+                        }
+                    }
+                    if let Some(ref filter) = *filter {
+                        self.expr(filter);
+                    }
+                    self.visit_body(body);
+                    self.locals.truncate(inner);
+                }
+                Alias { ref target, ref value } => {
+                    let ty = self.expr(value);
+                    match *target {
+                        AssignTarget::Var(ref name) => {
+                            self.locals.push((name, ty));
+                        }
+                        AssignTarget::Pair(..) => unreachable!(),
+                    }
+                }
+            }
+        }
+        self.locals.truncate(scope);
+    }
+    fn iterate(&mut self, pos: Pos, ty: Ty) -> Ty {
+        match ty.typ {
+            Type::Any => Ty::new(Type::Any),
+            Type::List(item) => Ty::new(*item),
+            typ => {
+                self.error(pos, format!(
+                    "value of type `{}` can't be iterated over{}", typ,
+                    match typ {
+                        Type::Map(..) | Type::Object(..)
+                        => ", use `for key, value in ...`",
+                        _ => "",
+                    }));
+                Ty::new(Type::Any)
+            }
+        }
+    }
+    fn iterate_pairs(&mut self, pos: Pos, ty: Ty) -> Ty {
+        match ty.typ {
+            Type::Any | Type::Object(..) => Ty::new(Type::Any),
+            Type::Map(value) => Ty::new(*value),
+            typ => {
+                self.error(pos, format!(
+                    "value of type `{}` can't be iterated over by pairs",
+                    typ));
+                Ty::new(Type::Any)
+            }
+        }
+    }
+    fn attr(&mut self, pos: Pos, ty: Ty, name: &str) -> Ty {
+        let undef = ty.maybe_undefined;
+        match ty.typ {
+            Type::Any => Ty { typ: Type::Any, maybe_undefined: undef },
+            Type::Map(value) => Ty { typ: *value, maybe_undefined: true },
+            Type::Object(attrs) => {
+                match attrs.into_iter().find(|a| a.name == name) {
+                    Some(a) => Ty {
+                        typ: a.typ,
+                        maybe_undefined: undef || a.optional,
+                    },
+                    None => {
+                        self.error(pos, format!(
+                            "attribute {:?} is not declared", name));
+                        Ty::undefined()
+                    }
+                }
+            }
+            typ => {
+                self.error(pos, format!(
+                    "value of type `{}` has no attributes", typ));
+                Ty::undefined()
+            }
+        }
+    }
+    fn number(&mut self, expr: &Expr, ty: &Ty) {
+        match ty.typ {
+            Type::Any | Type::Number if !ty.maybe_undefined => {}
+            Type::Any | Type::Number => {
+                self.error(expr.position.0, String::from(
+                    "value might be undefined and can't be used as number"));
+            }
+            ref typ => {
+                self.error(expr.position.0, format!(
+                    "value of type `{}` can't be used as number", typ));
+            }
+        }
+    }
+    fn comparable(&mut self, expr: &Expr, ty: &Ty) {
+        match ty.typ {
+            Type::List(..) | Type::Map(..) | Type::Object(..) => {
+                self.error(expr.position.0, format!(
+                    "value of type `{}` can't be compared", ty.typ));
+            }
+            _ if ty.maybe_undefined => {
+                self.error(expr.position.0, String::from(
+                    "value might be undefined and can't be compared"));
+            }
+            _ => {}
+        }
+    }
+    fn expr(&mut self, expr: &'a Expr) -> Ty {
+        use grammar::ExprCode::*;
+
+        match expr.code {
+            Str(..) => Ty::new(Type::Str),
+            Int(..) | Float(..) => Ty::new(Type::Number),
+            Var(ref name) => {
+                if let Some((_, ty)) = self.locals.iter().rev()
+                    .find(|&&(n, _)| n == name)
+                {
+                    return ty.clone();
+                }
+                if let Some((_, typ)) = self.schema.iter()
+                    .find(|(n, _)| n == name)
+                {
+                    return Ty::new(typ.clone());
+                }
+                self.error(expr.position.0, format!(
+                    "variable {:?} is not declared", name));
+                Ty::new(Type::Any)
+            }
+            Attr(ref e, ref name) => {
+                let ty = self.expr(e);
+                self.attr(expr.position.0, ty, name)
+            }
+            Item(ref e, ref key) => {
+                let ty = self.expr(e);
+                if let Str(ref name) = key.code {
+                    return self.attr(expr.position.0, ty, name);
+                }
+                let key_ty = self.expr(key);
+                match ty.typ {
+                    Type::Any | Type::Object(..) => Ty::undefined(),
+                    Type::Map(value) => Ty { typ: *value,
+                                             maybe_undefined: true },
+                    Type::List(item) => {
+                        if let Type::Str = key_ty.typ {
+                            self.error(key.position.0, String::from(
+                                "list can only be indexed by number"));
+                        }
+                        Ty { typ: *item, maybe_undefined: true }
+                    }
+                    typ => {
+                        self.error(expr.position.0, format!(
+                            "value of type `{}` can't be indexed", typ));
+                        Ty::undefined()
+                    }
+                }
+            }
+            And(ref a, ref b) | Or(ref a, ref b) => {
+                let a = self.expr(a);
+                let b = self.expr(b);
+                if a.typ == b.typ {
+                    Ty {
+                        typ: a.typ,
+                        maybe_undefined: a.maybe_undefined ||
+                                         b.maybe_undefined,
+                    }
+                } else {
+                    Ty::new(Type::Any)
+                }
+            }
+            Not(ref e) => {
+                self.expr(e);
+                Ty::new(Type::Bool)
+            }
+            Comparison(ref first, ref rest) => {
+                let ty = self.expr(first);
+                self.comparable(first, &ty);
+                for (_, e) in rest {
+                    let ty = self.expr(e);
+                    self.comparable(e, &ty);
+                }
+                Ty::new(Type::Bool)
+            }
+            Add(ref a, ref b) | Sub(ref a, ref b) |
+            Mul(ref a, ref b) | Div(ref a, ref b) | Mod(ref a, ref b) => {
+                let ta = self.expr(a);
+                self.number(a, &ta);
+                let tb = self.expr(b);
+                self.number(b, &tb);
+                Ty::new(Type::Number)
+            }
+            List(ref items) => {
+                for e in items {
+                    self.expr(e);
+                }
+                Ty::new(Type::list(Type::Any))
+            }
+            Dict(ref pairs) => {
+                for (k, v) in pairs {
+                    self.expr(k);
+                    self.expr(v);
+                }
+                Ty::new(Type::map(Type::Any))
+            }
+            // not produced by the parser yet
+            Filter(..) | Range(..) => Ty::new(Type::Any),
+        }
+    }
+}
+
+pub fn check(tpl: &Tpl) -> Result<(), ParseError> {
+    let mut checker = Checker {
+        schema: &tpl.options.schema,
+        options: &tpl.options,
+        locals: Vec::new(),
+        errors: Vec::new(),
+    };
+    checker.visit_body(&tpl.body);
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ParseErrorEnum::SchemaMismatch(checker.errors).into())
+    }
+}
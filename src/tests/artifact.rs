@@ -0,0 +1,126 @@
+use render::extract;
+use {Parser, Template, Context, ArtifactError};
+
+
+const TEMPLATES: &[&str] = &[
+    "hello",
+    "## syntax: indent\n\
+     ## for k, v in map\n    - {{ k }}: {{ v }}\n## endfor\n\
+     ## for x in list\n  ## skip if x == 'b'\n  {{- x +}}\n## endfor\n",
+    "## syntax: oneline\n\
+     ## validate default: [a-z]+\n\
+     ## validate sum: [a-z+]+\n\
+     ## filter h: builtin.html_entities\n\
+     {{ x | sum }} {{ x | h }}  ##\n  {{ x }}",
+    "## filter default: builtin.quoted_shell_argument\n\
+     ## requires x: {a: str, b?: [number], c: {*: bool}}\n\
+     ## requires y: any\n\
+     {{ x.a }}",
+    "## let a = {'x': [1, 2.5, 3], 'y': not z}\n\
+     ## if a.x[0] + 1 * 2 - 3 / 4 % 5 > 0 and (b or c) != 1 <= 2\n\
+     {{ a['y'] }}\n\
+     ## elif 1 < 2 >= 3 == 3\n\
+     x {# comment #}\n\
+     ## else\n\
+     y\n\
+     ## endif\n",
+    "## delimiters: <% %> %% <# #>\n\
+     %% syntax: oneline\n\
+     <% x %> {{ y }}",
+    "a {#- c +#} b\n##- for x in y\n  {{ x }}\n##+ endfor\n",
+    "## newline_at_eof: no\na\n",
+    "## filter default: builtin.url_form | builtin.html_entities\n{{ x }}",
+    "## validate default max_length: 10\n\
+     ## validate default: [a-z]*\n\
+     ## validate t categories: L N\n\
+     ## validate t forbid: --\n\
+     ## validate t escape: builtin.url_form | builtin.html_entities\n\
+     {{ x }} {{ x | t }}",
+    "## filter default: builtin.html_auto\n\
+     <a href=\"{{ x }}\" onclick=\"f({{ x }})\">{{ x }}</a>",
+];
+
+fn roundtrip(template: &str) -> (Template, Template) {
+    let tpl = Parser::new().parse(template).unwrap();
+    let bytes = tpl.to_bytes();
+    let loaded = Template::from_bytes(&bytes).unwrap();
+    assert_eq!(loaded.to_bytes(), bytes);
+    (tpl, loaded)
+}
+
+#[test]
+fn same_template() {
+    for template in TEMPLATES {
+        let (a, b) = roundtrip(template);
+        let a = extract(a);
+        let b = extract(b);
+        assert_eq!(a.body, b.body);
+        assert_eq!(a.options.syntax, b.options.syntax);
+        assert_eq!(a.options.delimiters, b.options.delimiters);
+        assert_eq!(a.options.new_line_at_eof, b.options.new_line_at_eof);
+        assert_eq!(a.options.default_filter, b.options.default_filter);
+        assert_eq!(a.options.filters, b.options.filters);
+        assert_eq!(a.options.schema, b.options.schema);
+    }
+}
+
+#[test]
+fn renders() {
+    let (_, tpl) = roundtrip(TEMPLATES[2]);
+    let good = "ab";
+    let bad = "<A>";
+    let mut ctx = Context::new();
+    ctx.set("x", &good);
+    assert_eq!(tpl.render(&ctx).unwrap(), "ab ab ab");
+    ctx.set("x", &bad);
+    assert_eq!(tpl.render(&ctx).unwrap_err().to_string(),
+        "data error: \
+         5:1: output \"<A>\" should match regex \"^[a-z+]+$\"\n  \
+         6:3: output \"<A>\" should match regex \"^[a-z]+$\"");
+}
+
+#[test]
+fn chained_escape() {
+    let (_, tpl) = roundtrip(TEMPLATES[8]);
+    let good = "a&b c";
+    let bad = "<\"";
+    let mut ctx = Context::new();
+    ctx.set("x", &good);
+    assert_eq!(tpl.render(&ctx).unwrap(), "a%26b+c");
+    ctx.set("x", &bad);
+    assert_eq!(tpl.render(&ctx).unwrap(), "%3C%22");
+}
+
+#[test]
+fn bad_magic() {
+    assert!(matches!(Template::from_bytes(b"hello"),
+                     Err(ArtifactError::BadMagic)));
+}
+
+#[test]
+fn other_version() {
+    let mut bytes = Parser::new().parse("hello").unwrap().to_bytes();
+    bytes[8] = 0xFF;
+    let err = Template::from_bytes(&bytes).unwrap_err();
+    assert!(matches!(err, ArtifactError::UnsupportedVersion(..)));
+    assert!(err.to_string().starts_with(
+        concat!("template is precompiled by trimmer ",
+                env!("CARGO_PKG_VERSION"))));
+}
+
+#[test]
+fn corrupted() {
+    let bytes = Parser::new().parse(TEMPLATES[4]).unwrap().to_bytes();
+    for len in 0..bytes.len() {
+        assert!(Template::from_bytes(&bytes[..len]).is_err());
+    }
+    let mut extra = bytes.clone();
+    extra.push(0);
+    assert!(Template::from_bytes(&extra).is_err());
+    // errors, not panics
+    for idx in 8..bytes.len() {
+        let mut bytes = bytes.clone();
+        bytes[idx] ^= 0x55;
+        let _ = Template::from_bytes(&bytes);
+    }
+}
@@ -0,0 +1,129 @@
+use ast::{self, Visitor, StatementKind, ExprKind, Target, Whitespace, MathOp};
+use {Parser, Template, Pos};
+
+
+fn parse(data: &str) -> Template {
+    Parser::new().parse(data).unwrap()
+}
+
+struct Strings<'a>(Vec<(Pos, &'a str)>);
+
+impl<'a> Visitor<'a> for Strings<'a> {
+    fn visit_expr(&mut self, expr: ast::Expr<'a>) {
+        if let ExprKind::Str(value) = expr.kind() {
+            self.0.push((expr.position().0, value));
+        }
+        ast::walk_expr(self, expr);
+    }
+}
+
+struct Loops(usize);
+
+impl<'a> Visitor<'a> for Loops {
+    fn visit_statement(&mut self, statement: ast::Statement<'a>) {
+        if let StatementKind::For { .. } = statement.kind() {
+            self.0 += 1;
+        }
+        ast::walk_statement(self, statement);
+    }
+}
+
+#[test]
+fn extract_strings() {
+    let tpl = parse("\
+        {{ \"hello\" }}\n\
+        ## for x in items\n\
+        ## if x == 'a'\n\
+        {{ {'key': \"value\"}[x] }}\n\
+        ## endif\n\
+        ## endfor\n");
+    let mut strings = Strings(Vec::new());
+    tpl.ast().visit(&mut strings);
+    assert_eq!(strings.0, vec![
+        (Pos { line: 1, column: 4 }, "hello"),
+        (Pos { line: 3, column: 12 }, "a"),
+        (Pos { line: 4, column: 5 }, "key"),
+        (Pos { line: 4, column: 12 }, "value"),
+    ]);
+}
+
+#[test]
+fn nested_loops() {
+    let tpl = parse("\
+        ## for x in items\n\
+        ## for k, v in x\n\
+        ## endfor\n\
+        ## endfor\n\
+        ## if y\n\
+        ## for z in y\n\
+        ## endfor\n\
+        ## endif\n");
+    let mut loops = Loops(0);
+    tpl.ast().visit(&mut loops);
+    assert_eq!(loops.0, 3);
+}
+
+#[test]
+fn statement_kinds() {
+    let tpl = parse("a {{- x + 1 | v }}\n## let y = x\n## for k, v in y\n\
+                     ## skip if k\n## endfor\n");
+    let statements = tpl.ast().statements().collect::<Vec<_>>();
+    assert_eq!(statements.len(), 5);
+    match statements[0].kind() {
+        StatementKind::Raw(text) => assert_eq!(text, "a "),
+        kind => panic!("unexpected {:?}", kind),
+    }
+    match statements[1].kind() {
+        StatementKind::Output { expr, filter, left_ws, right_ws } => {
+            assert_eq!(filter, Some("v"));
+            assert_eq!(left_ws, Whitespace::Strip);
+            assert_eq!(right_ws, Whitespace::Preserve);
+            assert_eq!(expr.position(),
+                (Pos { line: 1, column: 7 }, Pos { line: 1, column: 13 }));
+            match expr.kind() {
+                ExprKind::Math(MathOp::Add, a, b) => {
+                    assert!(matches!(a.kind(), ExprKind::Var("x")));
+                    assert!(matches!(b.kind(), ExprKind::Int(1)));
+                }
+                kind => panic!("unexpected {:?}", kind),
+            }
+        }
+        kind => panic!("unexpected {:?}", kind),
+    }
+    match statements[3].kind() {
+        StatementKind::Let { target, value } => {
+            assert_eq!(target, "y");
+            assert!(matches!(value.kind(), ExprKind::Var("x")));
+        }
+        kind => panic!("unexpected {:?}", kind),
+    }
+    match statements[4].kind() {
+        StatementKind::For { target, skip_if, body, .. } => {
+            assert_eq!(target, Target::Pair("k", "v"));
+            assert!(skip_if.is_some());
+            assert!(body.is_empty());
+        }
+        kind => panic!("unexpected {:?}", kind),
+    }
+}
+
+#[test]
+fn whitespace_control() {
+    let tpl = parse("a {#- c +#}\n##- let y = x\nb");
+    let kinds = tpl.ast().statements()
+        .map(|s| match s.kind() {
+            StatementKind::WhitespaceControl { left_ws, right_ws }
+            => Some((left_ws, right_ws)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(kinds, vec![
+        None,
+        Some((Whitespace::Strip, Whitespace::Space)),
+        None,
+        Some((Whitespace::Strip, Whitespace::Preserve)),
+        None,
+        Some((Whitespace::Preserve, Whitespace::Strip)),
+        None,
+    ]);
+}
@@ -36,6 +36,14 @@ fn hello() {
     }]);
 }
 
+#[test]
+fn hello_indented() {
+    assert_eq(parse("hello\n  world"), vec![Statement {
+        position: lines(1, 1, 2, 8),
+        code: OutputRaw("hello\n  world".into()),
+    }]);
+}
+
 #[test]
 #[should_panic(expected="Statement must start at the beginning of the line")]
 fn invalid() {
@@ -0,0 +1,67 @@
+use {Parser, Context, Options};
+
+
+fn render_x(options: &Options, template: &str, x: &str)
+    -> Result<String, String>
+{
+    let tpl = Parser::new().parse_with_options(options, template).unwrap();
+    let mut vars: Context = Context::new();
+    vars.set("x", &x);
+    tpl.render(&vars).map_err(|e| e.to_string())
+}
+
+#[test]
+fn disabled() {
+    assert_eq!(render_x(&Options::new(), "a {{ x }}", "}{").unwrap(),
+               "a }{");
+}
+
+#[test]
+fn balanced() {
+    let opt = Options::new().balance_curly().balance_square()
+        .balance_round().clone();
+    assert_eq!(render_x(&opt, "{ a {{ x }} }", "{[()]}").unwrap(),
+               "{ a {[()]} }");
+}
+
+#[test]
+fn stray_close() {
+    assert_eq!(render_x(Options::new().balance_curly(),
+                        "server {\n  root {{ x }};\n}\n",
+                        "/x; } server { root /").unwrap_err(),
+               "data error: 2:8: output \"/x; } server { root /\" \
+                has unbalanced bracket '}'");
+}
+
+#[test]
+fn unclosed() {
+    assert_eq!(render_x(Options::new().balance_square(), "[{{ x }}]", "[a")
+               .unwrap_err(),
+               "data error: 1:2: output \"[a\" has unbalanced bracket '['");
+}
+
+#[test]
+fn mismatched() {
+    let opt = Options::new().balance_curly().balance_round().clone();
+    assert_eq!(render_x(&opt, "{{ x }}", "({)}").unwrap_err(),
+               "data error: 1:1: output \"({)}\" \
+                has unbalanced bracket ')'");
+}
+
+#[test]
+fn only_enabled() {
+    assert_eq!(render_x(Options::new().balance_round(), "{{ x }}", "{[(x)")
+               .unwrap(), "{[(x)");
+    assert_eq!(render_x(Options::new().balance_curly(), "{{ x }}", "(})")
+               .unwrap_err(),
+               "data error: 1:1: output \"(})\" has unbalanced bracket '}'");
+}
+
+#[test]
+fn after_escape() {
+    assert_eq!(render_x(Options::new().balance_round(),
+                        "## filter default: builtin.html_entities\n\
+                         {{ x }}", "<(>").unwrap_err(),
+               "data error: 2:1: output \"&lt;(&gt;\" \
+                has unbalanced bracket '('");
+}
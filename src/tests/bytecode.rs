@@ -0,0 +1,96 @@
+use tests::assert_eq;
+
+use bytecode::{compile, Instr};
+use bytecode::Instr::*;
+use grammar::OutputMode::*;
+use render::extract;
+use {Parser, Context, Pos};
+
+
+fn code(data: &str) -> Vec<Instr> {
+    compile(&extract(Parser::new().parse(data).unwrap()).body).unwrap()
+}
+
+fn render(data: &str) -> String {
+    let x = "x";
+    let mut ctx = Context::new();
+    ctx.set("x", &x);
+    Parser::new().parse(data).unwrap().render(&ctx).unwrap()
+}
+
+fn pos(line: usize, column: usize) -> Pos {
+    Pos { line, column }
+}
+
+#[test]
+fn output() {
+    assert_eq(code("a{{ x.y }}"), vec![
+        Raw("a".into()),
+        Var("x".into(), pos(1, 5)),
+        Attr("y".into(), pos(1, 5)),
+        Output {
+            left_ws: Preserve,
+            validator: None,
+            position: pos(1, 2),
+            right_ws: Preserve,
+        },
+    ]);
+}
+
+#[test]
+fn condition() {
+    assert_eq(code("## if x\n1\n## else\n2\n## endif\n"), vec![
+        Var("x".into(), pos(1, 7)),
+        Condition(pos(1, 7), 6),
+        PushScope,
+        Raw("1\n".into()),
+        PopScope,
+        Jump(9),
+        PushScope,
+        Raw("2\n".into()),
+        PopScope,
+    ]);
+}
+
+#[test]
+fn iteration() {
+    assert_eq(code("## for a in x\n## skip if a\n{{ a }}\n## endfor\n"), vec![
+        Var("x".into(), pos(1, 13)),
+        Iterate { position: pos(1, 13), pairs: false, end: 11 },
+        Next(11),
+        Set("a".into()),
+        Var("a".into(), pos(2, 12)),
+        Skip(pos(2, 12), 9),
+        Var("a".into(), pos(3, 4)),
+        Output {
+            left_ws: Preserve,
+            validator: None,
+            position: pos(3, 1),
+            right_ws: Preserve,
+        },
+        Raw("\n".into()),
+        PopScope,
+        Jump(2),
+    ]);
+}
+
+#[test]
+fn scopes() {
+    assert_eq!(render("## let y = x\n\
+                      ## for x in [1, 2]\n\
+                      ## let y = x\n\
+                      {{ y }}\n\
+                      ## endfor\n\
+                      {{ x }}{{ y }}\n"),
+              "1\n2\nxx\n");
+}
+
+#[test]
+fn nested_loops() {
+    assert_eq!(render("## for a in [1, 2]\n\
+                      ## for b in ['x', 'y']\n\
+                      {{ a }}{{ b }}{{ x }}\n\
+                      ## endfor\n\
+                      ## endfor\n"),
+              "1xx\n1yx\n2xx\n2yx\n");
+}
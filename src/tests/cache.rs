@@ -0,0 +1,185 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+#[cfg(unix)] use std::process::Command;
+use std::sync::Arc;
+use std::thread;
+
+use {Parser, Context, TemplateCache, CacheError};
+
+
+fn dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir()
+        .join(format!("trimmer-cache-{}-{}", name, ::std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write(path: &Path, data: &str) {
+    let mut file = File::create(path).unwrap();
+    file.write_all(data.as_bytes()).unwrap();
+}
+
+/// Sets mtime of the file far into the past, like `cp -p` does
+#[cfg(unix)]
+fn age(path: &Path) {
+    let status = Command::new("touch").arg("-t").arg("200001010000")
+        .arg(path).status().unwrap();
+    assert!(status.success());
+}
+
+fn render(cache: &TemplateCache, path: &Path) -> String {
+    cache.get(path).unwrap().render(&Context::new()).unwrap()
+}
+
+#[test]
+fn unchanged() {
+    let dir = dir("unchanged");
+    let path = dir.join("a.txt");
+    write(&path, "hello");
+    let cache = TemplateCache::new(Parser::new());
+    let tpl = cache.get(&path).unwrap();
+    assert!(Arc::ptr_eq(&tpl, &cache.get(&path).unwrap()));
+    // touching file doesn't make it reparsed
+    write(&path, "hello");
+    assert!(Arc::ptr_eq(&tpl, &cache.get(&path).unwrap()));
+}
+
+#[test]
+fn changed() {
+    let dir = dir("changed");
+    let path = dir.join("a.txt");
+    write(&path, "hello");
+    let cache = TemplateCache::new(Parser::new());
+    assert_eq!(render(&cache, &path), "hello");
+    write(&path, "world");
+    assert_eq!(render(&cache, &path), "world");
+}
+
+#[test]
+fn no_check() {
+    let dir = dir("no_check");
+    let path = dir.join("a.txt");
+    write(&path, "hello");
+    let mut cache = TemplateCache::new(Parser::new());
+    cache.check_changes(false);
+    assert_eq!(render(&cache, &path), "hello");
+    write(&path, "world");
+    assert_eq!(render(&cache, &path), "hello");
+    cache.invalidate(&path);
+    assert_eq!(render(&cache, &path), "world");
+}
+
+#[test]
+fn dependency() {
+    let dir = dir("dependency");
+    let path = dir.join("a.txt");
+    let dep = dir.join("b.txt");
+    write(&path, "hello");
+    write(&dep, "x");
+    let cache = TemplateCache::new(Parser::new());
+    cache.add_dependency(&path, &dep).unwrap();
+    let tpl = cache.get(&path).unwrap();
+    write(&dep, "x");
+    assert!(Arc::ptr_eq(&tpl, &cache.get(&path).unwrap()));
+    write(&dep, "y");
+    let new = cache.get(&path).unwrap();
+    assert!(!Arc::ptr_eq(&tpl, &new));
+    // dependency is dropped after reparse until it's added again
+    write(&dep, "z");
+    assert!(Arc::ptr_eq(&new, &cache.get(&path).unwrap()));
+    cache.add_dependency(&path, &dep).unwrap();
+    write(&dep, "w");
+    assert!(!Arc::ptr_eq(&new, &cache.get(&path).unwrap()));
+}
+
+#[test]
+fn removed_dependency() {
+    let dir = dir("removed_dependency");
+    let path = dir.join("a.txt");
+    let dep = dir.join("b.txt");
+    write(&path, "hello");
+    write(&dep, "x");
+    let cache = TemplateCache::new(Parser::new());
+    cache.add_dependency(&path, &dep).unwrap();
+    let tpl = cache.get(&path).unwrap();
+    fs::remove_file(&dep).unwrap();
+    let new = cache.get(&path).unwrap();
+    assert!(!Arc::ptr_eq(&tpl, &new));
+    assert!(Arc::ptr_eq(&new, &cache.get(&path).unwrap()));
+}
+
+#[test]
+fn rewritten_immediately() {
+    let dir = dir("rewritten_immediately");
+    let path = dir.join("a.txt");
+    write(&path, "hello");
+    let cache = TemplateCache::new(Parser::new());
+    assert_eq!(render(&cache, &path), "hello");
+    // might have the same mtime on file systems with coarse timestamps
+    write(&path, "world");
+    assert_eq!(render(&cache, &path), "world");
+}
+
+#[test]
+#[cfg(unix)]
+fn preserved_mtime() {
+    let dir = dir("preserved_mtime");
+    let path = dir.join("a.txt");
+    write(&path, "hello");
+    age(&path);
+    let cache = TemplateCache::new(Parser::new());
+    assert_eq!(render(&cache, &path), "hello");
+    write(&path, "hello world");
+    age(&path);
+    assert_eq!(render(&cache, &path), "hello world");
+}
+
+#[test]
+fn errors() {
+    let dir = dir("errors");
+    let path = dir.join("a.txt");
+    let cache = TemplateCache::new(Parser::new());
+    assert!(matches!(cache.get(&path), Err(CacheError::Io(..))));
+    write(&path, "{{ x");
+    assert!(matches!(cache.get(&path), Err(CacheError::Parse(..))));
+    write(&path, "{{ 'x' }}");
+    assert_eq!(render(&cache, &path), "x");
+}
+
+#[test]
+fn threads() {
+    let dir = dir("threads");
+    let path = dir.join("a.txt");
+    write(&path, "hello");
+    let cache = Arc::new(TemplateCache::new(Parser::new()));
+    let threads = (0..4).map(|_| {
+        let cache = cache.clone();
+        let path = path.clone();
+        thread::spawn(move || render(&cache, &path))
+    }).collect::<Vec<_>>();
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), "hello");
+    }
+}
+
+#[test]
+fn error_keeps_dependency() {
+    let dir = dir("error_keeps_dependency");
+    let path = dir.join("a.txt");
+    let dep = dir.join("b.txt");
+    write(&path, "hello");
+    write(&dep, "x");
+    let cache = TemplateCache::new(Parser::new());
+    cache.add_dependency(&path, &dep).unwrap();
+    write(&path, "{{ x");
+    assert!(matches!(cache.get(&path), Err(CacheError::Parse(..))));
+    // stays broken until fixed
+    assert!(matches!(cache.get(&path), Err(CacheError::Parse(..))));
+    write(&dep, "y");
+    assert!(matches!(cache.get(&path), Err(CacheError::Parse(..))));
+    write(&path, "world");
+    assert_eq!(render(&cache, &path), "world");
+}
@@ -0,0 +1,25 @@
+use std::mem;
+
+use codegen::generate;
+use grammar::{Expr, ExprCode, StatementCode};
+use render::extract;
+use Parser;
+
+
+#[test]
+fn unsupported() {
+    // parser doesn't produce range expressions yet
+    let mut tpl = extract(Parser::new().parse("a\n {{ x }}").unwrap());
+    match tpl.body.statements[1].code {
+        StatementCode::Output { ref mut expr, .. } => {
+            let code = mem::replace(&mut expr.code, ExprCode::Int(0));
+            let x = Expr { position: expr.position, code: code };
+            expr.code = ExprCode::Range(Some(Box::new(x)), None);
+        }
+        ref code => panic!("unexpected statement {:?}", code),
+    }
+    let err = generate("x", &tpl).unwrap_err();
+    assert_eq!(err.to_string(),
+        "2:5: range expression is not supported yet");
+    assert_eq!(err.position().map(|p| (p.line, p.column)), Some((2, 5)));
+}
@@ -0,0 +1,123 @@
+use {Parser, Context, Options};
+
+
+fn render(template: &str) -> String {
+    render_with(&Options::new(), template)
+}
+
+fn render_with(options: &Options, template: &str) -> String {
+    let tpl = Parser::new().parse_with_options(options, template).unwrap();
+    let x = "hello";
+    let items = vec!["a", "b"];
+    let mut vars: Context = Context::new();
+    vars.set("x", &x);
+    vars.set("items", &items);
+    tpl.render(&vars).unwrap()
+}
+
+fn parse_err(options: &Options, template: &str) -> String {
+    Parser::new().parse_with_options(options, template)
+        .err().unwrap().to_string()
+}
+
+#[test]
+fn directive() {
+    assert_eq!(render("## delimiters: <% %>\n\
+                       {{ x }} <% x %>!\n"),
+               "{{ x }} hello!\n");
+}
+
+#[test]
+fn statement_prefix() {
+    assert_eq!(render("## delimiters: <% %> %%\n\
+                       %% syntax: oneline\n\
+                       ## not a statement\n\
+                       %% for i in items\n\
+                         <% i %>\n\
+                       %% endfor\n"),
+               "## not a statement a b");
+}
+
+#[test]
+fn comments() {
+    assert_eq!(render("## delimiters: <% %> %% <# #>\n\
+                       %%# line comment\n\
+                       a{# x #}<# comment #>b %%\n\
+                       c\n"),
+               "a{# x #}b c\n");
+}
+
+#[test]
+fn whitespace_markers() {
+    assert_eq!(render("## delimiters: <% %>\n\
+                       a   <%- x -%>   b\n\
+                       a   <%+ x +%>   b\n"),
+               "ahellob\na hello b\n");
+}
+
+#[test]
+fn options() {
+    let mut options = Options::new();
+    options.expression_delimiters("${", "}")
+        .statement_prefix("#%")
+        .comment_delimiters("/*", "*/");
+    assert_eq!(render_with(&options,
+        "#% syntax: oneline\n\
+         # comment /* {{ x }} */\n\
+         #% if x == \"hello\"\n\
+             ${- x -}!\n\
+         #% endif\n"),
+        "# commenthello!");
+}
+
+#[test]
+fn directive_overrides_options() {
+    let mut options = Options::new();
+    options.statement_prefix("%%");
+    assert_eq!(render_with(&options,
+        "%% delimiters: [[ ]] @@\n\
+         @@ if x\n\
+         [[ x ]]\n\
+         @@ endif\n"),
+        "hello\n");
+}
+
+#[test]
+fn invalid() {
+    assert_eq!(parse_err(&Options::new(), "## delimiters: <%\n"),
+        "Delimiters \"<%\" are invalid: \
+         expected 2, 3 or 5 markers separated by whitespace");
+    assert_eq!(parse_err(&Options::new(), "## delimiters: <% %> <%%\n"),
+        "Delimiters \"<% %> <%% {# #}\" are invalid: \
+         expression, statement and comment markers must start differently");
+    assert_eq!(parse_err(&Options::new(), "## delimiters: <%- %>\n"),
+        "Delimiters \"<%- %> ## {# #}\" are invalid: \
+         whitespace control signs `+` and `-` can't be used at \
+         the inner side of markers");
+    let mut options = Options::new();
+    options.statement_prefix("");
+    assert_eq!(parse_err(&options, "text"),
+        "Delimiters \"{{ }}  {# #}\" are invalid: \
+         markers must not be empty");
+}
+
+#[test]
+fn duplicate() {
+    assert_eq!(parse_err(&Options::new(),
+        "## delimiters: <% %>\n## delimiters: [[ ]]\n"),
+        "duplicate delimiters directive");
+}
+
+#[test]
+fn format() {
+    let parser = Parser::new();
+    assert_eq!(parser.format(
+        "## delimiters: <% %> %%\n\
+         %%   if  x   # check\n\
+         <%x|y-%>%%\n\
+         %%  endif\n").unwrap(),
+        "## delimiters: <% %> %%\n\
+         %% if x  # check\n\
+         <% x | y -%>%%\n\
+         %% endif\n");
+}
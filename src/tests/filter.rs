@@ -34,3 +34,141 @@ fn filter_shell_argument() {
                    echo {{ x | arg }}", "don't crash"),
         r#"echo 'don'"'"'t crash'"#, "\n", 0);
 }
+
+#[test]
+fn filter_indent() {
+    assert_diff!(
+        &render_x("## filter i: builtin.indent\n\
+                   key:\n  script: {{ x | i }}\n  other: 1\n",
+                  "- a\n- b\n\n- c"),
+        "key:\n  script: - a\n          - b\n\n          - c\n  other: 1\n",
+        "\n", 0);
+}
+
+#[test]
+fn filter_indent_block() {
+    assert_diff!(
+        &render_x("## filter i: builtin.indent\n\
+                   def f():\n\t  {{ x | i }}\n",
+                  "a = 1\nreturn a\n"),
+        "def f():\n\t  a = 1\n\t  return a\n\n",
+        "\n", 0);
+}
+
+#[test]
+fn filter_indent_stripped() {
+    assert_diff!(
+        &render_x("## filter i: builtin.indent\n\
+                   - {{ 'x' }}    {{- x | i }}\n",
+                  "a\nb"),
+        "- xa\n   b\n",
+        "\n", 0);
+}
+
+#[test]
+fn filter_url_path_segment() {
+    assert_diff!(
+        &render_x("## filter p: builtin.url_path_segment\n\
+                   /files/{{ x | p }}", "../a b?c=d/ü~"),
+        "/files/..%2Fa%20b%3Fc%3Dd%2F%C3%BC~", "\n", 0);
+}
+
+#[test]
+fn filter_url_query_component() {
+    assert_diff!(
+        &render_x("## filter q: builtin.url_query_component\n\
+                   /?q={{ x | q }}", "a b&c=d+e#f;g/h?i:j@k'l"),
+        "/?q=a%20b%26c%3Dd%2Be%23f%3Bg/h?i:j@k%27l", "\n", 0);
+}
+
+#[test]
+fn filter_url_form() {
+    assert_diff!(
+        &render_x("## filter f: builtin.url_form\n\
+                   /?q={{ x | f }}", "a b&c=d+e*~"),
+        "/?q=a+b%26c%3Dd%2Be*%7E", "\n", 0);
+}
+
+#[test]
+fn filter_chain() {
+    assert_diff!(
+        &render_x("## filter href: builtin.url_form | builtin.html_entities\n\
+                   <a href=\"/?a=1&b={{ x | href }}\">", "x&y'"),
+        "<a href=\"/?a=1&b=x%26y%27\">", "\n", 0);
+    assert_diff!(
+        &render_x("## filter j: builtin.json | builtin.html_entities\n\
+                   <div data-x=\"{{ x | j }}\">", "<\"a\">"),
+        "<div data-x=\"&quot;&lt;\\&quot;a\\&quot;&gt;&quot;\">", "\n", 0);
+}
+
+#[test]
+fn filter_chain_unknown() {
+    let err = Parser::new()
+        .parse("## filter x: builtin.url_form | html\n").unwrap_err();
+    assert_eq!(err.to_string(), "Filter \"html\" is unknown");
+}
+
+#[test]
+fn filter_chain_indent() {
+    assert_diff!(
+        &render_x("## filter i: builtin.html_entities | builtin.indent\n\
+                   <pre>{{ x | i }}", "<a>\n<b>"),
+        "<pre>&lt;a&gt;\n     &lt;b&gt;", "\n", 0);
+    let err = Parser::new()
+        .parse("## filter i: builtin.indent | builtin.html_entities\n")
+        .unwrap_err();
+    assert_eq!(err.to_string(),
+        "Escape chain \"builtin.indent | builtin.html_entities\" is \
+         invalid: builtin.indent must be the last in the chain");
+}
+
+#[test]
+fn filter_yaml_string() {
+    assert_diff!(
+        &render_x("## filter y: builtin.yaml_string\n\
+                   key: {{ x | y }}", "a: \"b\"\n- c\\\u{1}\u{85}"),
+        "key: \"a: \\\"b\\\"\\n- c\\\\\\x01\\N\"", "\n", 0);
+}
+
+#[test]
+fn filter_toml_string() {
+    assert_diff!(
+        &render_x("## filter t: builtin.toml_string\n\
+                   key = {{ x | t }}", "a\"\nb = \\c\u{7f}"),
+        "key = \"a\\\"\\nb = \\\\c\\u007f\"", "\n", 0);
+}
+
+#[test]
+fn filter_nginx_string() {
+    assert_diff!(
+        &render_x("## filter n: builtin.nginx_string\n\
+                   root {{ x | n }};", "/a b\"; } server {\n"),
+        "root \"/a b\\\"; } server {\\n\";", "\n", 0);
+}
+
+#[test]
+fn filter_nginx_string_dollar() {
+    let tpl = Parser::new().parse("## filter n: builtin.nginx_string\n\
+                                   root {{ x | n }};").unwrap();
+    let mut vars: Context = Context::new();
+    vars.set("x", &"/$document_root");
+    assert_eq!(tpl.render(&vars).unwrap_err().to_string(),
+        "data error: 2:6: output \"/$document_root\" \
+         contains `$` which can't be escaped in nginx");
+}
+
+#[test]
+fn filter_systemd_string() {
+    assert_diff!(
+        &render_x("## filter s: builtin.systemd_string\n\
+                   Environment={{ x | s }}", "A=100% \"b\"\n[Service]"),
+        "Environment=\"A=100%% \\\"b\\\"\\n[Service]\"", "\n", 0);
+}
+
+#[test]
+fn filter_csv_field() {
+    assert_diff!(
+        &render_x("## filter c: builtin.csv_field\n\
+                   1,{{ x | c }},2", "a,\"b\"\nc"),
+        "1,\"a,\"\"b\"\"\nc\",2", "\n", 0);
+}
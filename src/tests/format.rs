@@ -0,0 +1,220 @@
+use grammar::{Body, Expr};
+use preparser::Syntax;
+use {Parser, Options, Pos};
+
+
+const NOWHERE: Pos = Pos { line: 0, column: 0 };
+
+fn clear_expr(expr: &mut Expr) {
+    use grammar::ExprCode::*;
+    expr.position = (NOWHERE, NOWHERE);
+    match expr.code {
+        Str(..) | Int(..) | Float(..) | Var(..) => {}
+        Attr(ref mut e, _) | Not(ref mut e) => clear_expr(e),
+        Item(ref mut a, ref mut b) | Filter(ref mut a, ref mut b) |
+        And(ref mut a, ref mut b) | Or(ref mut a, ref mut b) |
+        Add(ref mut a, ref mut b) | Sub(ref mut a, ref mut b) |
+        Mul(ref mut a, ref mut b) | Div(ref mut a, ref mut b) |
+        Mod(ref mut a, ref mut b) => {
+            clear_expr(a);
+            clear_expr(b);
+        }
+        Comparison(ref mut first, ref mut rest) => {
+            clear_expr(first);
+            for (_, e) in rest {
+                clear_expr(e);
+            }
+        }
+        List(ref mut items) => items.iter_mut().for_each(clear_expr),
+        Dict(ref mut pairs) => {
+            for (k, v) in pairs {
+                clear_expr(k);
+                clear_expr(v);
+            }
+        }
+        Range(ref mut a, ref mut b) => {
+            a.as_mut().map(|x| clear_expr(x));
+            b.as_mut().map(|x| clear_expr(x));
+        }
+    }
+}
+
+/// Clears everything that formatter is allowed to change
+fn clear_body(body: &mut Body, keep_indent: bool) {
+    use grammar::StatementCode::*;
+    for st in &mut body.statements {
+        st.position = (NOWHERE, NOWHERE);
+        match st.code {
+            OutputRaw(..) | Joiner | WhitespaceControl { .. } => {}
+            Output { ref mut expr, .. } => clear_expr(expr),
+            Alias { ref mut value, .. } => clear_expr(value),
+            Cond { ref mut indent, ref mut conditional, ref mut otherwise }
+            => {
+                if !keep_indent {
+                    *indent = 0;
+                }
+                for (cond, body) in conditional {
+                    clear_expr(cond);
+                    clear_body(body, keep_indent);
+                }
+                clear_body(otherwise, keep_indent);
+            }
+            Loop { ref mut indent, ref mut iterator, ref mut filter,
+                   ref mut body, .. }
+            => {
+                if !keep_indent {
+                    *indent = 0;
+                }
+                clear_expr(iterator);
+                filter.as_mut().map(clear_expr);
+                clear_body(body, keep_indent);
+            }
+        }
+    }
+}
+
+fn parse_body(parser: &Parser, data: &str) -> Body {
+    let (options, mut body) = parser.parse_body(&Options::new(), data)
+        .unwrap();
+    clear_body(&mut body, options.syntax == Syntax::Indent);
+    body
+}
+
+fn format(data: &str) -> String {
+    let parser = Parser::new();
+    let result = parser.format(data).unwrap();
+    assert_eq!(parse_body(&parser, &result), parse_body(&parser, data));
+    assert_eq!(parser.format(&result).unwrap(), result);
+    // template must be still compilable, not just parseable
+    let tpl = parser.parse(data).unwrap();
+    assert_eq!(parser.parse(&result).unwrap().usage().variable_names(),
+               tpl.usage().variable_names());
+    result
+}
+
+#[test]
+fn expression_spacing() {
+    assert_eq!(format("{{x}} {{-  a+b*c.d  -}}{{+y|v+}}\n"),
+        "{{ x }} {{- a + b * c.d -}}{{+ y | v +}}\n");
+}
+
+#[test]
+fn parenthesis() {
+    assert_eq!(format("{{ (a + b) * c }} {{ (a * b) + (c) }}"),
+        "{{ (a + b) * c }} {{ a * b + c }}");
+    assert_eq!(format("{{ a - (b - c) }} {{ (a - b) - c }}"),
+        "{{ a - (b - c) }} {{ a - b - c }}");
+    assert_eq!(format("## if not (a.b) and (not c or d)\n## endif\n"),
+        "## if not a.b and (not c or d)\n## endif\n");
+    assert_eq!(format("## if not (a == b)\n## endif\n"),
+        "## if not (a == b)\n## endif\n");
+    assert_eq!(format("{{ (a + b).c }}{{ (x)[ 1 ] }}"),
+        "{{ (a + b).c }}{{ x[1] }}");
+}
+
+#[test]
+fn literals() {
+    assert_eq!(format("{{ 'a' }}{{ \"b\\nc\" }}{{ 'say \"hi\"' }}"),
+        "{{ \"a\" }}{{ \"b\\nc\" }}{{ 'say \"hi\"' }}");
+    assert_eq!(format("{{ 1 }}{{ 2.50 }}{{ 3.0 }}"),
+        "{{ 1 }}{{ 2.5 }}{{ 3.0 }}");
+    assert_eq!(format("{{ [ 1,2, ] }}{{ {'a':1,  'b' : x} }}{{ [] }}"),
+        "{{ [1, 2] }}{{ {\"a\": 1, \"b\": x} }}{{ [] }}");
+}
+
+#[test]
+fn nested_blocks() {
+    assert_eq!(format("\
+        ## for item in items\n\
+        ## if item.a>1\n\
+          a\n\
+        ##   elif   item.b\n\
+        \x20 b\n\
+        ## else\n\
+        ## endif\n\
+        ##  let x=item.c\n\
+        {{x}}\n\
+        ##   endfor\n"), "\
+        ## for item in items\n\
+        \x20   ## if item.a > 1\n\
+          a\n\
+        \x20   ## elif item.b\n\
+        \x20 b\n\
+        \x20   ## else\n\
+        \x20   ## endif\n\
+        \x20   ## let x = item.c\n\
+        {{ x }}\n\
+        ## endfor\n");
+}
+
+#[test]
+fn skip_if() {
+    assert_eq!(format("\
+        ## for k,v in x\n\
+        ## skip if k==1 or k==2\n\
+        ## skip if v\n\
+        {{ k }}\n\
+        ## endfor\n"), "\
+        ## for k, v in x\n\
+        \x20   ## skip if k == 1 or k == 2\n\
+        \x20   ## skip if v\n\
+        {{ k }}\n\
+        ## endfor\n");
+}
+
+#[test]
+fn comments() {
+    assert_eq!(format("\
+        ## syntax:  oneline\n\
+        ### header comment\n\
+        ## if x   # trailing comment\n\
+        a {# inline #} {{b}} ### line comment\n\
+          ### block comment\n\
+        ## endif\n\
+        {# final #}"), "\
+        ## syntax:  oneline\n\
+        ### header comment\n\
+        ## if x  # trailing comment\n\
+        a {# inline #} {{ b }} ### line comment\n\
+          ### block comment\n\
+        ## endif\n\
+        {# final #}");
+}
+
+#[test]
+fn line_joiner() {
+    assert_eq!(format("f(  ##\n  {{a}},   ##\n  b)\n"),
+        "f(  ##\n  {{ a }},   ##\n  b)\n");
+}
+
+#[test]
+fn indent_syntax() {
+    assert_eq!(format("\
+        ## syntax: indent\n\
+        items:\n\
+        \x20 ## for x in items\n\
+        \x20   - {{x}}\n\
+        ## endfor\n\
+        \x20 ##   if y\n\
+        \x20 y: 1\n\
+        \x20       ## endif\n"), "\
+        ## syntax: indent\n\
+        items:\n\
+        \x20 ## for x in items\n\
+        \x20   - {{ x }}\n\
+        \x20 ## endfor\n\
+        \x20 ## if y\n\
+        \x20 y: 1\n\
+        \x20 ## endif\n");
+}
+
+#[test]
+fn format_error() {
+    assert!(Parser::new().format("## if x\n").is_err());
+}
+
+#[test]
+fn multiline_statement() {
+    assert_eq!(format("## let x = [1,\n   2]\n{{ x }}"),
+        "## let x = [1, 2]\n{{ x }}");
+}
@@ -0,0 +1,146 @@
+use {Parser, Context};
+
+
+fn render_x(template: &str, x: &str) -> String {
+    let tpl = Parser::new()
+        .parse(&format!("## filter default: builtin.html_auto\n{}", template))
+        .unwrap();
+    let mut vars: Context = Context::new();
+    vars.set("x", &x);
+    tpl.render(&vars).unwrap()
+}
+
+fn error(template: &str) -> String {
+    Parser::new()
+        .parse(&format!("## filter default: builtin.html_auto\n{}", template))
+        .unwrap_err().to_string()
+}
+
+#[test]
+fn text() {
+    assert_eq!(render_x("<p>{{ x }}</p>", "<b>'&'</b>"),
+               "<p>&lt;b&gt;&#x27;&amp;&#x27;&lt;&#x2f;b&gt;</p>");
+    assert_eq!(render_x("<title>{{ x }}</title>", "</title>"),
+               "<title>&lt;&#x2f;title&gt;</title>");
+}
+
+#[test]
+fn attribute() {
+    assert_eq!(render_x("<p class=\"a {{ x }}\" title='{{ x }}'>", "\"'"),
+               "<p class=\"a &quot;&#x27;\" title='&quot;&#x27;'>");
+}
+
+#[test]
+fn url() {
+    assert_eq!(render_x("<a href=\"{{ x }}\">", "https://a.b/c d?e=\"f\""),
+               "<a href=\"https:&#x2f;&#x2f;a.b&#x2f;c%20d?e=%22f%22\">");
+    assert_eq!(render_x("<a href=\"{{ x }}\">", "JavaScript:alert(1)"),
+               "<a href=\"about:invalid#unsafe-url\">");
+    assert_eq!(render_x("<a href=\"/user/{{ x }}\">", "javascript:a b"),
+               "<a href=\"/user/javascript:a%20b\">");
+    assert_eq!(render_x("<a href=\"{{ x }}\">", "/a:b"),
+               "<a href=\"&#x2f;a:b\">");
+    assert_eq!(render_x("<img src='/s?q={{ x }}&a=1'>", "a&b=c d"),
+               "<img src='/s?q=a%26b%3Dc%20d&a=1'>");
+}
+
+#[test]
+fn script() {
+    assert_eq!(render_x("<script>var x = {{ x }};</script>", "</script>\""),
+               "<script>var x = \"<\\/script>\\\"\";</script>");
+    assert_eq!(render_x("<button onclick=\"go({{ x }})\">", "a\"b"),
+               "<button onclick=\"go(&quot;a\\&quot;b&quot;)\">");
+    assert_eq!(render_x("<script>// '\n</script><p>{{ x }}", "<"),
+               "<script>// '\n</script><p>&lt;");
+}
+
+#[test]
+fn style() {
+    assert_eq!(render_x("<style>p { color: {{ x }} }</style>", "red}</style>"),
+               "<style>p { color: red\\7d \\3c \\2f style\\3e  }</style>");
+    assert_eq!(render_x("<p style=\"width: {{ x }}\">", "50%;\""),
+               "<p style=\"width: 50%\\3b \\22 \">");
+}
+
+#[test]
+fn explicit_filter() {
+    let tpl = Parser::new().parse("## filter default: builtin.html_auto\n\
+                                   ## validate raw: .*\n\
+                                   <p>{{ x | raw }}</p>").unwrap();
+    let mut vars: Context = Context::new();
+    vars.set("x", &"<b>");
+    assert_eq!(tpl.render(&vars).unwrap(), "<p><b></p>");
+}
+
+#[test]
+fn named_filter() {
+    let tpl = Parser::new().parse("## filter h: builtin.html_auto\n\
+                                   <a href=\"{{ x | h }}\">{{ x }}</a>")
+        .unwrap();
+    let mut vars: Context = Context::new();
+    vars.set("x", &"javascript:<b>");
+    assert_eq!(tpl.render(&vars).unwrap(),
+               "<a href=\"about:invalid#unsafe-url\">javascript:<b></a>");
+}
+
+#[test]
+fn conditions() {
+    assert_eq!(render_x("<a href=\"\n## if 1\n/a/\n## else\n/b/\n## endif\n\
+                         {{ x }}\">", "c d"),
+               "<a href=\"\n/a/\nc%20d\">");
+    assert_eq!(error("## if 1\n<p class=\"\n## endif\n{{ x }}\">"),
+               "2:1: branches of the condition end in different HTML context");
+    assert_eq!(error("## for i in x\n<p class='\n## endfor\n{{ x }}'>"),
+               "2:1: loop body ends in different HTML context \
+                than it starts");
+    assert_eq!(render_x("<ul>\n## for i in [x, 'b']\n<li>{{ i }}</li>\n\
+                         ## endfor\n</ul>", "<"),
+               "<ul>\n<li>&lt;</li>\n<li>b</li>\n</ul>");
+}
+
+#[test]
+fn errors() {
+    assert_eq!(error("<p {{ x }}>"),
+               "2:4: expression inside of HTML tag, \
+                only attribute values can be printed");
+    assert_eq!(error("<p class={{ x }}>"),
+               "2:10: value of the attribute must be quoted");
+    assert_eq!(error("<!-- {{ x }} -->"),
+               "2:6: expression inside of HTML comment");
+    assert_eq!(error("<script>var x = '{{ x }}';</script>"),
+               "2:18: expression inside of a javascript string or comment, \
+                put it outside of the quotes");
+}
+
+#[test]
+fn url_start_expressions() {
+    let tpl = Parser::new()
+        .parse("## filter default: builtin.html_auto\n\
+                <a href=\"{{ a }}{{ b }}\"><a href=\"java{{ b }}\">")
+        .unwrap();
+    let a = "";
+    let b = "javascript:alert(1)";
+    let mut vars: Context = Context::new();
+    vars.set("a", &a);
+    vars.set("b", &b);
+    assert_eq!(tpl.render(&vars).unwrap(),
+               "<a href=\"about:invalid#unsafe-url\">\
+                <a href=\"javaabout:invalid#unsafe-url\">");
+    let a = "javascript";
+    let b = ":alert(1)";
+    let mut vars: Context = Context::new();
+    vars.set("a", &a);
+    vars.set("b", &b);
+    assert_eq!(tpl.render(&vars).unwrap(),
+               "<a href=\"javascriptabout:invalid#unsafe-url\">\
+                <a href=\"javaabout:invalid#unsafe-url\">");
+}
+
+#[test]
+fn url_scheme_after_expression() {
+    assert_eq!(error("<a href=\"{{ x }}:alert(1)\">"),
+        "2:17: scheme of the URL depends on the expression, \
+         put the whole scheme into the expression");
+    assert_eq!(render_x("<a href=\"{{ x }}/a:b\">", "javascript"),
+               "<a href=\"javascript/a:b\">");
+}
@@ -1,13 +1,7 @@
 use tests::assert_eq;
 
-use render::extract;
-use grammar::StatementCode::*;
-use grammar::{Parser, Statement};
-use {Pos, Context};
-
-fn parse(data: &'static str) -> Vec<Statement> {
-    extract(Parser::new().parse(data).unwrap()).body.statements
-}
+use grammar::Parser;
+use {Context};
 
 #[cfg(feature="json")]
 fn render_json(template: &str, value: &str) -> String {
@@ -31,26 +25,6 @@ fn render_x(template: &str) -> String {
     tpl.render(&vars).unwrap()
 }
 
-fn lines(line_st: usize, start: usize, line_end: usize, end: usize)
-    -> (Pos, Pos)
-{
-    (Pos { line: line_st, column: start },
-     Pos { line: line_end, column: end })
-}
-
-#[test]
-fn empty() {
-    assert_eq(parse(""), vec![]);
-}
-
-#[test]
-fn hello() {
-    assert_eq(parse("hello\n  world"), vec![Statement {
-        position: lines(1, 1, 2, 8),
-        code: OutputRaw("hello\n  world".into()),
-    }]);
-}
-
 #[test]
 #[cfg(feature="json")]
 fn indented_if() {
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+
+use {Parser, Context, Options, Variable};
+use schema::Type;
+
+
+fn render_x<'x, V: Variable<'x> + 'x>(template: &str, x: &'x V) -> String {
+    let tpl = Parser::new().parse(template).unwrap();
+    let mut vars: Context = Context::new();
+    vars.set("x", x);
+    tpl.render(&vars).unwrap()
+}
+
+#[test]
+fn json_string() {
+    assert_eq!(render_x("## filter j: builtin.json_string\n\
+                         {\"x\": {{ x | j }}}",
+                        &"a\"b\\c\nd\te\u{1}f\u{2028}"),
+               r#"{"x": "a\"b\\c\nd\te\u0001f\u2028"}"#);
+}
+
+#[test]
+fn json_string_number() {
+    assert_eq!(render_x("## filter j: builtin.json_string\n{{ x | j }}", &12),
+               r#""12""#);
+}
+
+#[test]
+fn json_string_script() {
+    assert_eq!(render_x("## filter j: builtin.json_string\n{{ x | j }}",
+                        &"</script><!-- ünïcode"),
+               r#""<\/script><!-- ünïcode""#);
+}
+
+#[test]
+fn json_scalars() {
+    let tpl = "## filter default: builtin.json\n{{ x }}";
+    assert_eq!(render_x(tpl, &"x\"y"), r#""x\"y""#);
+    assert_eq!(render_x(tpl, &-12), "-12");
+    assert_eq!(render_x(tpl, &1.5), "1.5");
+    assert_eq!(render_x(tpl, &true), "true");
+    assert_eq!(render_x("## filter default: builtin.json\n\
+                         {{ x.missing }}", &BTreeMap::<String, u8>::new()),
+               "null");
+}
+
+#[test]
+fn json_list() {
+    assert_eq!(render_x("## filter j: builtin.json\n{{ x | j }}",
+                        &vec!["a", "b"]),
+               r#"["a","b"]"#);
+    assert_eq!(render_x("## filter j: builtin.json\n{{ x | j }}",
+                        &Vec::<u32>::new()),
+               "[]");
+}
+
+#[test]
+fn json_map() {
+    let mut map = BTreeMap::new();
+    map.insert("a".to_string(), vec![1, 2]);
+    map.insert("b\"".to_string(), vec![]);
+    assert_eq!(render_x("## filter j: builtin.json\n{{ x | j }}", &map),
+               r#"{"a":[1,2],"b\"":[]}"#);
+}
+
+#[test]
+fn json_infinity() {
+    let tpl = Parser::new()
+        .parse("## filter j: builtin.json\n[{{ x | j }}]").unwrap();
+    let x = ::std::f64::INFINITY;
+    let mut vars: Context = Context::new();
+    vars.set("x", &x);
+    assert_eq!(tpl.render(&vars).unwrap_err().to_string(),
+               "data error: 2:2: error when formatting value of type f64");
+}
+
+#[test]
+fn json_schema() {
+    let mut options = Options::new();
+    options.require("x", Type::list(Type::Str));
+    Parser::new().parse_with_options(&options,
+        "## filter j: builtin.json\n{{ x | j }}").unwrap();
+    let err = Parser::new().parse_with_options(&options,
+        "## filter j: builtin.json_string\n{{ x | j }}").unwrap_err();
+    assert!(err.to_string().contains("can't be printed"));
+}
+
+#[cfg(feature="json")]
+#[test]
+fn json_value() {
+    use serde_json;
+    let value = serde_json::from_str::<serde_json::Value>(
+        r#"{"a": [1, -2, 2.5, null, true], "b": {"c": "d"}}"#).unwrap();
+    assert_eq!(render_x("## filter j: builtin.json\n{{ x | j }}", &value),
+               r#"{"a":[1,-2,2.5,null,true],"b":{"c":"d"}}"#);
+}
@@ -0,0 +1,109 @@
+use {Parser, Pos, Warning};
+
+fn lint(template: &str) -> Vec<(Pos, Warning)> {
+    Parser::new().lint(template).unwrap()
+}
+
+fn pos(line: usize, column: usize) -> Pos {
+    Pos { line, column }
+}
+
+#[test]
+fn clean() {
+    assert_eq!(lint("\
+        ## let y = x.y\n\
+        ## for item in y\n\
+        ## if item.visible\n\
+        {{ item.name }}\n\
+        ## endif\n\
+        ## endfor\n"), vec![]);
+}
+
+#[test]
+fn unused_let() {
+    assert_eq!(lint("## let y = x\n{{ x }}\n"),
+        vec![(pos(1, 1), Warning::UnusedLet("y".into()))]);
+}
+
+#[test]
+fn shadowed_variable() {
+    assert_eq!(lint("\
+        ## for x in items\n\
+        ## for x in x.children\n\
+        {{ x }}\n\
+        ## endfor\n\
+        ## endfor\n"),
+        vec![(pos(2, 1), Warning::ShadowedVariable("x".into()))]);
+}
+
+#[test]
+fn constant_condition() {
+    assert_eq!(lint("## if 1 > 2\nx\n## endif\n"),
+        vec![(pos(1, 7), Warning::ConstantCondition)]);
+}
+
+#[test]
+fn unreachable_branch() {
+    assert_eq!(lint("\
+        ## if x\n\
+        a\n\
+        ## elif y\n\
+        b\n\
+        ## elif x\n\
+        c\n\
+        ## endif\n"),
+        vec![(pos(5, 9), Warning::UnreachableBranch)]);
+    assert_eq!(lint("\
+        ## if 1\n\
+        a\n\
+        ## elif y\n\
+        b\n\
+        ## endif\n"),
+        vec![(pos(1, 7), Warning::ConstantCondition),
+             (pos(3, 9), Warning::UnreachableBranch)]);
+}
+
+#[test]
+fn unvalidated_output() {
+    assert_eq!(lint("\
+        ## validate alnum: [a-z0-9]+\n\
+        {{ x | alnum }} {{ y }}\n"),
+        vec![(pos(2, 17), Warning::UnvalidatedOutput)]);
+    assert_eq!(lint("\
+        ## validate default: [a-z0-9]+\n\
+        ## validate alnum: [a-z0-9]+\n\
+        ## filter h: builtin.html_entities\n\
+        {{ x | alnum }} {{ y }} {{ z | h }}\n"),
+        vec![]);
+    // named validator which accepts anything
+    assert_eq!(lint("\
+        ## validate default: [a-z0-9]+\n\
+        ## validate raw: .*\n\
+        {{ x | raw }} {{ y }}\n"),
+        vec![(pos(3, 1), Warning::UnvalidatedOutput)]);
+    assert_eq!(lint("\
+        ## validate default: [^\\n]*\n\
+        ## validate default max_length: 100\n\
+        {{ x }}\n"),
+        vec![(pos(3, 1), Warning::UnvalidatedOutput)]);
+}
+
+#[test]
+fn mixed_indentation() {
+    assert_eq!(lint("\
+        ## syntax: indent\n\
+        ## if x\n\
+        \x20 a\n\
+        \t\x20b\n\
+        \tc\n\
+        ## endif\n"),
+        vec![(pos(4, 1), Warning::MixedIndentation),
+             (pos(5, 1), Warning::MixedIndentation)]);
+    // only checked for indent syntax
+    assert_eq!(lint("\x20 a\n\tb\n"), vec![]);
+}
+
+#[test]
+fn lint_syntax_error() {
+    assert!(Parser::new().lint("## if x\n").is_err());
+}
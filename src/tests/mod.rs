@@ -8,6 +8,25 @@ mod vars;
 mod boolean;
 mod math;
 mod validate;
+mod whitespace;
 mod filter;
+mod usage;
+mod schema;
+mod lint;
+mod format;
+mod artifact;
+mod ast;
+mod cache;
+mod delimiters;
+mod newline;
+mod brackets;
+mod json;
+mod shell;
+mod html;
+mod safe;
+mod options;
+mod tokenizer;
+mod bytecode;
+mod codegen;
 
 pub use self::diff::assert_eq;
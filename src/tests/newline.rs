@@ -0,0 +1,65 @@
+use {Parser, Context, Options};
+
+
+fn render(template: &str) -> String {
+    let tpl = Parser::new().parse(template).unwrap();
+    tpl.render(&Context::new()).unwrap()
+}
+
+fn render_opt(options: &Options, template: &str) -> String {
+    let tpl = Parser::new().parse_with_options(options, template).unwrap();
+    tpl.render(&Context::new()).unwrap()
+}
+
+#[test]
+fn untouched() {
+    assert_eq!(render("a\n\n"), "a\n\n");
+    assert_eq!(render("a"), "a");
+}
+
+#[test]
+fn force_newline() {
+    assert_eq!(render("## newline_at_eof: yes\na"), "a\n");
+    assert_eq!(render("## newline_at_eof: yes\na\n\n\n"), "a\n");
+    assert_eq!(render("## newline_at_eof: yes\na\n"), "a\n");
+}
+
+#[test]
+fn no_newline() {
+    assert_eq!(render("## newline_at_eof: no\na\n\n"), "a");
+    assert_eq!(render("## newline_at_eof: no\na {{ '\n' }}"), "a ");
+}
+
+#[test]
+fn empty() {
+    assert_eq!(render("## newline_at_eof: yes\n## if 0\na\n## endif\n"), "");
+}
+
+#[test]
+fn indent() {
+    assert_eq!(render("## syntax: indent\n## newline_at_eof: no\n\
+                       ## if 1\n  a\n## endif\n"), "a");
+}
+
+#[test]
+fn oneline() {
+    assert_eq!(render("## syntax: oneline\n## newline_at_eof: yes\n\
+                       a\n  b\n"), "a b\n");
+}
+
+#[test]
+fn options() {
+    assert_eq!(render_opt(Options::new().new_line_at_eof(true), "a"), "a\n");
+    assert_eq!(render_opt(Options::new().new_line_at_eof(true),
+                          "## newline_at_eof: no\na\n"), "a");
+}
+
+#[test]
+fn invalid() {
+    let err = Parser::new().parse("## newline_at_eof: maybe\na").unwrap_err();
+    assert_eq!(err.to_string(),
+               "newline_at_eof must be `yes` or `no`, not \"maybe\"");
+    let err = Parser::new().parse("## newline_at_eof: no\n\
+                                   ## newline_at_eof: no\na").unwrap_err();
+    assert_eq!(err.to_string(), "duplicate newline_at_eof directive");
+}
@@ -0,0 +1,194 @@
+use {Parser, Context, Options, Template, Safe, ArtifactError};
+
+
+fn parse(options: &Options, template: &str) -> Template {
+    Parser::new().parse_with_options(options, template).unwrap()
+}
+
+fn render(tpl: &Template, x: &str) -> Result<String, String> {
+    let mut ctx = Context::new();
+    ctx.set("x", &x);
+    tpl.render(&ctx).map_err(|e| e.to_string())
+}
+
+fn upper(dest: &mut String, src: &str) {
+    dest.push_str(&src.to_uppercase());
+}
+
+#[test]
+fn validator() {
+    let mut options = Options::new();
+    options.validator("default", "[a-z]+").unwrap()
+        .validator("num", "[0-9]+").unwrap();
+    let tpl = parse(&options, "{{ x }} {{ '12' | num }}");
+    assert_eq!(render(&tpl, "abc").unwrap(), "abc 12");
+    assert_eq!(render(&tpl, "a-b").unwrap_err(),
+        "data error: 1:1: output \"a-b\" should match regex \"^[a-z]+$\"");
+}
+
+#[test]
+fn bad_validator() {
+    assert_eq!(Options::new().validator("x", "(").unwrap_err().to_string()
+        .lines().next().unwrap(),
+        "Validator regex \"^($\" is invalid: regex parse error:");
+}
+
+#[test]
+fn escape() {
+    let mut options = Options::new();
+    options.escape("default", "builtin.html_entities").unwrap()
+        .escape("q", "builtin.url_form | builtin.html_entities").unwrap();
+    let tpl = parse(&options, "{{ x }} {{ x | q }}");
+    assert_eq!(render(&tpl, "a <b>").unwrap(),
+               "a &lt;b&gt; a+%3Cb%3E");
+    assert_eq!(Options::new().escape("x", "builtin.nope").unwrap_err()
+        .to_string(), "Filter \"builtin.nope\" is unknown");
+}
+
+#[test]
+fn escaper() {
+    let mut options = Options::new();
+    options.escaper("up", upper);
+    options.escaper("default", |dest: &mut String, src: &str| {
+        dest.push_str(&src.replace('a', "[a]"));
+    });
+    let tpl = parse(&options, "{{ x }} {{ x | up }}");
+    assert_eq!(render(&tpl, "abc").unwrap(), "[a]bc ABC");
+    let safe = Safe("abc");
+    let mut ctx = Context::new();
+    ctx.set("x", &safe);
+    assert_eq!(tpl.render(&ctx).unwrap(), "abc abc");
+}
+
+#[test]
+fn default_filter() {
+    let mut options = Options::new();
+    options.escaper("up", upper).default_filter("up").unwrap();
+    let tpl = parse(&options, "{{ x }} {{ x | up }}");
+    assert_eq!(render(&tpl, "abc").unwrap(), "ABC ABC");
+    assert_eq!(Options::new().default_filter("up").unwrap_err().to_string(),
+        "Filter \"up\" is unknown");
+}
+
+#[test]
+fn template_overrides() {
+    let mut options = Options::new();
+    options.validator("default", "[a-z]+").unwrap()
+        .escaper("up", upper);
+    let tpl = parse(&options, "## validate default: .*\n\
+                               ## filter up: builtin.html_entities\n\
+                               {{ x }} {{ x | up }}");
+    assert_eq!(render(&tpl, "<a>").unwrap(), "<a> &lt;a&gt;");
+}
+
+#[test]
+fn template_adds_constraints() {
+    let mut options = Options::new();
+    options.validator("default", "[a-z]+").unwrap();
+    let tpl = parse(&options, "## validate default max_length: 3\n{{ x }}");
+    assert_eq!(render(&tpl, "abc").unwrap(), "abc");
+    assert!(render(&tpl, "abcd").is_err());
+    assert!(render(&tpl, "a1").is_err());
+}
+
+#[test]
+fn codegen() {
+    let mut options = Options::new();
+    options.escaper("up", upper);
+    assert_eq!(Parser::new().codegen_with_options(&options, "x", "{{ x }}")
+        .unwrap_err().to_string(),
+        "custom escaper \"up\" can't be compiled into rust code");
+}
+
+#[test]
+fn artifact() {
+    let mut options = Options::new();
+    options.escaper("up", upper);
+    let bytes = parse(&options, "{{ x }} {{ x | up }}").to_bytes();
+    match Template::from_bytes(&bytes) {
+        Err(ArtifactError::CustomEscaper(ref name)) if name == "up" => {}
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+    let tpl = Template::from_bytes_with_options(&bytes, &options).unwrap();
+    assert_eq!(tpl.to_bytes(), bytes);
+    assert_eq!(render(&tpl, "abc").unwrap(), "abc ABC");
+    let other = Options::new();
+    assert!(matches!(Template::from_bytes_with_options(&bytes, &other),
+                     Err(ArtifactError::CustomEscaper(..))));
+}
+
+fn parse_err(options: &Options, template: &str) -> String {
+    Parser::new().parse_with_options(options, template)
+        .unwrap_err().to_string()
+}
+
+#[test]
+fn lock_syntax() {
+    let mut options = Options::new();
+    options.syntax_oneline().lock_syntax();
+    assert_eq!(parse_err(&options, "## syntax: indent\n"),
+        "syntax is locked by the application and can't be overridden \
+         in template");
+    let tpl = parse(&options, "## syntax: oneline\n{{ x }}\n  {{ x }}\n");
+    assert_eq!(render(&tpl, "a").unwrap(), "a a");
+}
+
+#[test]
+fn lock_default_filter() {
+    let mut options = Options::new();
+    options.escape("default", "builtin.html_entities").unwrap()
+        .lock_default_filter();
+    assert_eq!(parse_err(&options, "## filter default: builtin.url\n"),
+        "filter \"default\" is locked by the application and can't be \
+         overridden in template");
+    assert_eq!(parse_err(&options, "## validate default: .*\n"),
+        "filter \"default\" is locked by the application and can't be \
+         overridden in template");
+    assert!(parse_err(&options, "## validate default max_length: 1\n")
+        .starts_with("filter \"default\" is locked"));
+    // can't bypass the default by a new filter
+    assert_eq!(parse_err(&options, "## validate raw: .*\n{{ x | raw }}"),
+        "filter \"raw\" is locked by the application and can't be \
+         overridden in template");
+    assert!(parse_err(&options, "## filter u: builtin.url\n")
+        .starts_with("filter \"u\" is locked"));
+    let tpl = parse(&options, "## filter default: builtin.html_entities\n\
+                               {{ x }}");
+    assert_eq!(render(&tpl, "<a>").unwrap(), "&lt;a&gt;");
+}
+
+#[test]
+fn lock_registered() {
+    let mut options = Options::new();
+    options.escape("default", "builtin.html_entities").unwrap()
+        .escape("h", "builtin.html_entities").unwrap()
+        .validator("id", "[a-z]+").unwrap()
+        .lock_default_filter();
+    assert_eq!(parse_err(&options, "## validate h: .*\n{{ x | h }}"),
+        "filter \"h\" is locked by the application and can't be \
+         overridden in template");
+    assert!(parse_err(&options, "## validate id: [a-z0-9]+\n")
+        .starts_with("filter \"id\" is locked"));
+    let tpl = parse(&options, "## filter h: builtin.html_entities\n\
+                               ## validate id: [a-z]+\n\
+                               {{ x | h }}");
+    assert_eq!(render(&tpl, "<a>").unwrap(), "&lt;a&gt;");
+}
+
+#[test]
+fn lock_filter() {
+    let mut options = Options::new();
+    options.validator("id", "[a-z]+").unwrap()
+        .lock_filter("id")
+        .lock_filter("raw");
+    assert!(parse_err(&options, "## validate id: .*\n")
+        .starts_with("filter \"id\" is locked"));
+    assert!(parse_err(&options, "## filter id: builtin.url\n")
+        .starts_with("filter \"id\" is locked"));
+    assert!(parse_err(&options, "## validate raw: .*\n")
+        .starts_with("filter \"raw\" is locked"));
+    assert!(parse_err(&options, "## validate default: .*\n")
+        .starts_with("filter \"default\" is locked"));
+    let tpl = parse(&options, "## validate id: [a-z]+\n{{ x | id }}");
+    assert_eq!(render(&tpl, "abc").unwrap(), "abc");
+}
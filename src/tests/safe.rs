@@ -0,0 +1,64 @@
+use {Parser, Context, Variable, Safe, Markup, RenderError};
+
+
+fn render_x<'x, V: Variable<'x> + 'x>(template: &str, x: &'x V)
+    -> Result<String, RenderError>
+{
+    let tpl = Parser::new().parse(template).unwrap();
+    let mut vars: Context = Context::new();
+    vars.set("x", x);
+    tpl.render(&vars)
+}
+
+#[test]
+fn escape_bypassed() {
+    let tpl = "## filter default: builtin.html_entities\n<p>{{ x }}</p>";
+    assert_eq!(render_x(tpl, &"<b>").unwrap(), "<p>&lt;b&gt;</p>");
+    assert_eq!(render_x(tpl, &Safe("<b>")).unwrap(), "<p><b></p>");
+    let markup: Markup = Safe(String::from("<i>x</i>"));
+    assert_eq!(render_x(tpl, &markup).unwrap(), "<p><i>x</i></p>");
+}
+
+#[test]
+fn escape_chain_bypassed() {
+    let tpl = "## filter e: builtin.html_entities | builtin.url\n{{ x | e }}";
+    assert_eq!(render_x(tpl, &Safe("a&b c")).unwrap(), "a&b c");
+}
+
+#[test]
+fn html_auto() {
+    let tpl = "## filter default: builtin.html_auto\n\
+               <a title=\"{{ x }}\">{{ x }}</a>";
+    assert_eq!(render_x(tpl, &Safe("&amp;")).unwrap(),
+               "<a title=\"&amp;\">&amp;</a>");
+}
+
+#[test]
+fn validated() {
+    let tpl = "## validate default: [a-z<>/]*\n{{ x }}";
+    assert_eq!(render_x(tpl, &Safe("<b>")).unwrap(), "<b>");
+    assert!(render_x(tpl, &Safe("<b class=x>")).is_err());
+}
+
+#[test]
+fn json() {
+    let tpl = "## filter default: builtin.json\n{{ x }}";
+    assert_eq!(render_x(tpl, &Safe("<b>\"")).unwrap(), r#""<b>\"""#);
+}
+
+#[test]
+fn condition() {
+    let tpl = "## if x\nyes\n## else\nno\n## endif\n";
+    assert_eq!(render_x(tpl, &Safe("")).unwrap(), "no\n");
+    assert_eq!(render_x(tpl, &Safe("a")).unwrap(), "yes\n");
+}
+
+#[test]
+fn constraints() {
+    let tpl = "## validate default max_length: 3\n\
+               ## validate default escape: builtin.html_entities\n\
+               {{ x }}";
+    assert_eq!(render_x(tpl, &"<b>").unwrap(), "&lt;b&gt;");
+    assert_eq!(render_x(tpl, &Safe("<b>")).unwrap(), "<b>");
+    assert!(render_x(tpl, &Safe("<br>")).is_err());
+}
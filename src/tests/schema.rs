@@ -0,0 +1,138 @@
+use {Parser, Options, Type, Template, ParseError};
+
+fn parse(template: &str) -> Result<Template, ParseError> {
+    Parser::new().parse(template)
+}
+
+fn error(template: &str) -> String {
+    parse(template).unwrap_err().to_string()
+}
+
+#[test]
+fn parse_types() {
+    assert_eq!("str".parse::<Type>().unwrap(), Type::Str);
+    assert_eq!("[number]".parse::<Type>().unwrap(),
+               Type::list(Type::Number));
+    assert_eq!("{*: [bool]}".parse::<Type>().unwrap(),
+               Type::map(Type::list(Type::Bool)));
+    assert_eq!("{name: str, is_dir?: bool,}".parse::<Type>().unwrap(),
+               Type::object()
+                   .attr("name", Type::Str)
+                   .optional_attr("is_dir", Type::Bool));
+    assert_eq!("any  # comment".parse::<Type>().unwrap(), Type::Any);
+}
+
+#[test]
+fn display_types() {
+    let t = Type::list(Type::object()
+        .attr("name", Type::Str)
+        .optional_attr("size", Type::map(Type::Number)));
+    assert_eq!(t.to_string(), "[{name: str, size?: {*: number}}]");
+    assert_eq!(t.to_string().parse::<Type>().unwrap(), t);
+}
+
+#[test]
+#[should_panic(expected="BadType")]
+fn bad_type() {
+    parse("## requires x: [str\n").unwrap();
+}
+
+#[test]
+#[should_panic(expected="BadType")]
+fn unknown_type() {
+    parse("## requires x: string\n").unwrap();
+}
+
+#[test]
+fn no_schema() {
+    parse("{{ x.y.z }}").unwrap();
+}
+
+#[test]
+fn directory_listing() {
+    parse("\
+        ## requires path: str\n\
+        ## requires entries: [{name: str, is_dir: bool}]\n\
+        ## for entry in entries\n\
+        ## if entry.is_dir\n\
+        {{ path }}/{{ entry.name }}/\n\
+        ## endif\n\
+        ## endfor\n").unwrap();
+}
+
+#[test]
+fn undeclared_variable() {
+    assert_eq!(error("## requires x: str\n{{ x }}{{ y }}"),
+        "2:11: variable \"y\" is not declared");
+}
+
+#[test]
+fn undeclared_attribute() {
+    assert_eq!(error("\
+        ## requires entries: [{name: str}]\n\
+        ## for entry in entries\n\
+        {{ entry.size }}\n\
+        ## endfor\n"),
+        "3:4: attribute \"size\" is not declared");
+}
+
+#[test]
+fn print_list() {
+    assert_eq!(error("## requires x: [str]\n{{ x }}"),
+        "2:4: value of type `[str]` can't be printed");
+}
+
+#[test]
+fn iterate_map() {
+    assert_eq!(error("\
+        ## requires x: {*: str}\n\
+        ## for item in x\n\
+        ## endfor\n"),
+        "2:16: value of type `{*: str}` can't be iterated over, \
+         use `for key, value in ...`");
+    parse("\
+        ## requires x: {*: str}\n\
+        ## for key, value in x\n\
+        {{ key }}={{ value }}\n\
+        ## endfor\n").unwrap();
+}
+
+#[test]
+fn optional_arithmetic() {
+    assert_eq!(error("\
+        ## requires x: {a?: number, b: number}\n\
+        {{ x.b + 1 }}{{ x.a + 1 }}\n"),
+        "2:17: value might be undefined and can't be used as number");
+}
+
+#[test]
+fn string_arithmetic() {
+    assert_eq!(error("## requires x: str\n{{ x * 2 }}"),
+        "2:4: value of type `str` can't be used as number");
+}
+
+#[test]
+fn let_type() {
+    assert_eq!(error("\
+        ## requires x: {a: [str]}\n\
+        ## let items = x.a\n\
+        {{ items.len }}\n"),
+        "3:4: value of type `[str]` has no attributes");
+}
+
+#[test]
+fn schema_in_options() {
+    let mut options = Options::new();
+    options.require("user", Type::object().attr("name", Type::Str));
+    Parser::new().parse_with_options(&options, "{{ user.name }}").unwrap();
+    let err = Parser::new()
+        .parse_with_options(&options, "{{ user.login }}").unwrap_err();
+    assert_eq!(err.to_string(), "1:4: attribute \"login\" is not declared");
+}
+
+#[test]
+fn multiple_errors() {
+    assert_eq!(error("## requires x: str\n{{ y }}\n{{ x.z }}\n"),
+        "2:4: variable \"y\" is not declared\n\
+         3:4: value of type `str` has no attributes");
+}
@@ -0,0 +1,100 @@
+#[cfg(unix)] use std::process::Command;
+
+use validators::BuiltinEscape;
+use validators::BuiltinEscape::*;
+
+
+/// Strings that are tricky to quote, no NUL as it can't be an argument
+#[cfg(unix)]
+fn samples() -> Vec<String> {
+    let alphabet: Vec<char> = (1..128u8).map(|x| x as char)
+        .chain("üж\u{2028}€".chars())
+        .collect();
+    let mut result = vec![
+        "".to_string(), "word".to_string(), "-n".to_string(),
+        "'".to_string(), "\\\\".to_string(), "a b".to_string(),
+        "$HOME".to_string(), "`id`".to_string(), "$(id)".to_string(),
+        "~".to_string(), "*".to_string(), "!".to_string(),
+        "\n".to_string(), "x\\".to_string(),
+    ];
+    let mut state = 0x2545_F491_4F6C_DD1Du64;
+    for _ in 0..500 {
+        let mut item = String::new();
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        for _ in 0..(state % 12) {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            item.push(alphabet[(state % alphabet.len() as u64) as usize]);
+        }
+        result.push(item);
+    }
+    result
+}
+
+#[cfg(unix)]
+fn roundtrip(shell: &str, escape: BuiltinEscape, quote: &str) {
+    let samples = samples();
+    let mut script = String::from("printf '%s\\0'");
+    for item in &samples {
+        script.push(' ');
+        script.push_str(quote);
+        escape.escape(&mut script, item).unwrap();
+        script.push_str(quote);
+    }
+    let output = match Command::new(shell).arg("-c").arg(&script).output() {
+        Ok(output) => output,
+        Err(e) => panic!("can't run {}: {}", shell, e),
+    };
+    assert!(output.status.success(), "{}",
+        String::from_utf8_lossy(&output.stderr));
+    let text = String::from_utf8(output.stdout).unwrap();
+    let result = text.split('\0').collect::<Vec<_>>();
+    assert_eq!(result.len(), samples.len() + 1);
+    for (orig, res) in samples.iter().zip(result) {
+        assert_eq!(orig, res);
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn quoted_shell_argument() {
+    roundtrip("sh", QuotedShellArgument, "");
+}
+
+#[test]
+#[cfg(unix)]
+fn shell_word() {
+    roundtrip("sh", ShellWord, "");
+}
+
+#[test]
+fn shell_word_unquoted() {
+    let mut buf = String::new();
+    ShellWord.escape(&mut buf, "some-file_1.txt").unwrap();
+    ShellWord.escape(&mut buf, " ").unwrap();
+    ShellWord.escape(&mut buf, "").unwrap();
+    assert_eq!(buf, "some-file_1.txt' '''");
+}
+
+#[test]
+#[cfg(unix)]
+fn shell_double_quoted() {
+    roundtrip("sh", ShellDoubleQuoted, "\"");
+}
+
+#[test]
+#[cfg(unix)]
+#[ignore]  // needs bash, run with `cargo test -- --ignored`
+fn ansi_c_shell_argument() {
+    roundtrip("bash", AnsiCShellArgument, "");
+}
+
+#[test]
+fn ansi_c_visible() {
+    let mut buf = String::new();
+    AnsiCShellArgument.escape(&mut buf, "it's\n\x1b[0m\\").unwrap();
+    assert_eq!(buf, r"$'it\'s\n\x1b[0m\\'");
+}
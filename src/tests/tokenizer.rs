@@ -0,0 +1,112 @@
+use combine::StreamOnce;
+
+use tokenizer::Kind::*;
+use tokenizer::{Tokenizer, Kind, Delimiters};
+
+
+fn tokens(data: &str) -> Vec<(Kind, &str)> {
+    // tokens borrow delimiters, so they must outlive the result
+    tokens_with(data, Box::leak(Box::new(Delimiters::default())))
+}
+
+fn tokens_with<'a>(data: &'a str, delimiters: &'a Delimiters)
+    -> Vec<(Kind, &'a str)>
+{
+    let tok = Tokenizer::new();
+    let mut stream = tok.scan(data, delimiters, delimiters);
+    let mut result = Vec::new();
+    loop {
+        let token = stream.uncons().unwrap();
+        if token.kind == Eof {
+            return result;
+        }
+        result.push((token.kind, token.value));
+    }
+}
+
+#[test]
+fn top_level() {
+    assert_eq!(tokens("a b{{- x +}}\n"), vec![
+        (Raw, "a"), (Whitespace, " "), (Raw, "b"),
+        (ExprStart, "{{-"), (Whitespace, " "), (Ident, "x"),
+        (Whitespace, " "), (ExprEnd, "+}}"), (Newline, "\n"),
+    ]);
+    assert_eq!(tokens("x {# y #}\n  ### z\n"), vec![
+        (Raw, "x"), (Whitespace, " "), (Comment, "{# y #}"),
+        (Newline, "\n"), (Comment, " z\n"),
+    ]);
+    assert_eq!(tokens("a  ##\nb"), vec![
+        (Raw, "a"), (LineJoiner, "  ##\n"), (Raw, "b"),
+    ]);
+}
+
+#[test]
+fn statement() {
+    assert_eq!(tokens("## if x>=1 and y # c\n"), vec![
+        (StStart, "## if"), (Whitespace, " "), (Ident, "x"),
+        (Operator, ">="), (Number, "1"), (Whitespace, " "),
+        (Operator, "and"), (Whitespace, " "), (Ident, "y"),
+        (Whitespace, " "), (Comment, " c"), (Newline, "\n"),
+    ]);
+    assert_eq!(tokens("## let x = [\n1]\n"), vec![
+        (StStart, "## let"), (Whitespace, " "), (Ident, "x"),
+        (Whitespace, " "), (Operator, "="), (Whitespace, " "),
+        (Paren, "["), (Whitespace, "\n"), (Number, "1"), (Paren, "]"),
+        (Newline, "\n"),
+    ]);
+}
+
+#[test]
+fn words() {
+    assert_eq!(tokens("{{ android in int iffy if }}"), vec![
+        (ExprStart, "{{"), (Whitespace, " "), (Ident, "android"),
+        (Whitespace, " "), (Keyword, "in"), (Whitespace, " "),
+        (Ident, "int"), (Whitespace, " "), (Ident, "iffy"),
+        (Whitespace, " "), (Keyword, "if"), (Whitespace, " "),
+        (ExprEnd, "}}"),
+    ]);
+}
+
+#[test]
+fn literals() {
+    assert_eq!(tokens("{{ 0x1_0 1.5 1.x 'a\"b' \"c\" }}"), vec![
+        (ExprStart, "{{"), (Whitespace, " "), (Number, "0x1_0"),
+        (Whitespace, " "), (Number, "1.5"), (Whitespace, " "),
+        (Number, "1"), (Operator, "."), (Ident, "x"), (Whitespace, " "),
+        (String, "'a\"b'"), (Whitespace, " "), (String, "\"c\""),
+        (Whitespace, " "), (ExprEnd, "}}"),
+    ]);
+}
+
+#[test]
+fn unbalanced() {
+    let tok = Tokenizer::new();
+    let delimiters = Delimiters::default();
+    let mut stream = tok.scan("## if (x]\n", &delimiters, &delimiters);
+    let mut error = None;
+    for _ in 0..10 {
+        if let Err(e) = stream.uncons() {
+            error = Some(format!("{:?}", e));
+            break;
+        }
+    }
+    assert_eq!(error.unwrap(),
+        r#"Unexpected(Owned("Bracket ']' expecting ')' started at 1:7"))"#);
+}
+
+#[test]
+fn custom_delimiters() {
+    let delimiters = Delimiters {
+        expr_start: "<%".into(),
+        expr_end: "%>".into(),
+        statement: "%%".into(),
+        comment_start: "<#".into(),
+        comment_end: "#>".into(),
+    };
+    assert_eq!(tokens_with("a<%-x%>{{<# c #>\n%% if x\n", &delimiters), vec![
+        (Raw, "a"), (ExprStart, "<%-"), (Ident, "x"), (ExprEnd, "%>"),
+        (Raw, "{{"), (Comment, "<# c #>"), (Newline, "\n"),
+        (StStart, "%% if"), (Whitespace, " "), (Ident, "x"),
+        (Newline, "\n"),
+    ]);
+}
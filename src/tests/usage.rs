@@ -0,0 +1,86 @@
+use {Parser, Pos, VarUsage, FilterUsage};
+
+fn var(name: &str, attrs: &[&str], line: usize, column: usize) -> VarUsage {
+    VarUsage {
+        name: name.to_string(),
+        attrs: attrs.iter().map(|x| x.to_string()).collect(),
+        position: Pos { line, column },
+    }
+}
+
+#[test]
+fn simple_var() {
+    let usage = Parser::new().parse("{{ x }}").unwrap().usage();
+    assert_eq!(usage.variables, vec![var("x", &[], 1, 4)]);
+    assert_eq!(usage.filters, vec![]);
+}
+
+#[test]
+fn attributes() {
+    let usage = Parser::new()
+        .parse("{{ a.b.c }} {{ d['e'] }} {{ f[g].h }}").unwrap().usage();
+    assert_eq!(usage.variables, vec![
+        var("a", &["b", "c"], 1, 4),
+        var("d", &["e"], 1, 16),
+        var("f", &[], 1, 29),
+        var("g", &[], 1, 31),
+    ]);
+}
+
+#[test]
+fn loop_targets() {
+    let usage = Parser::new().parse("\
+        ## for entry in entries\n\
+        ## skip if entry.hidden or hide_all\n\
+        {{ entry.name }} {{ path }}\n\
+        ## endfor\n\
+        {{ entry }}\n").unwrap().usage();
+    assert_eq!(usage.variables, vec![
+        var("entries", &[], 1, 17),
+        var("hide_all", &[], 2, 28),
+        var("path", &[], 3, 21),
+        var("entry", &[], 5, 4),
+    ]);
+    assert_eq!(usage.variable_names(), vec!["entries", "entry",
+                                            "hide_all", "path"]);
+}
+
+#[test]
+fn pair_loop() {
+    let usage = Parser::new().parse("\
+        ## for key, value in map\n\
+        {{ key }}: {{ value.x }}\n\
+        ## endfor\n").unwrap().usage();
+    assert_eq!(usage.variables, vec![var("map", &[], 1, 22)]);
+}
+
+#[test]
+fn let_scope() {
+    let usage = Parser::new().parse("\
+        {{ y }}\n\
+        ## let y = x.a + 1\n\
+        {{ y }}\n\
+        ## if y\n\
+        ## let z = y\n\
+        {{ z }}\n\
+        ## endif\n\
+        {{ z }}\n").unwrap().usage();
+    assert_eq!(usage.variables, vec![
+        var("y", &[], 1, 4),
+        var("x", &["a"], 2, 12),
+        var("z", &[], 8, 4),
+    ]);
+}
+
+#[test]
+fn filters() {
+    let usage = Parser::new().parse("\
+        ## filter h: builtin.html_entities\n\
+        ## validate num: [0-9]+\n\
+        <b>{{ title | h }}</b> {{ count | num }}\n").unwrap().usage();
+    assert_eq!(usage.filters, vec![
+        FilterUsage { name: "h".into(), position: Pos { line: 3, column: 4 }},
+        FilterUsage { name: "num".into(),
+                      position: Pos { line: 3, column: 24 }},
+    ]);
+}
@@ -78,3 +78,88 @@ fn valid_non_default_override() {
               ## validate sum: [a-z+]+\n\
               {{ x | sum }}", "a+b");
 }
+
+fn render_err(template: &str, x: &str) -> String {
+    let tpl = Parser::new().parse(template).unwrap();
+    let mut vars: Context = Context::new();
+    vars.set("x", &x);
+    tpl.render(&vars).unwrap_err().to_string()
+}
+
+#[test]
+fn regex_error() {
+    assert_eq!(render_err("## validate default: [a-z]+\n{{ x }}", "a+b"),
+        "data error: 2:1: output \"a+b\" should match regex \"^[a-z]+$\"");
+}
+
+#[test]
+fn length() {
+    let tpl = "## validate default min_length: 2\n\
+               ## validate default max_length: 3\n\
+               {{ x }}";
+    assert_eq!(render_x(tpl, "ab"), "ab");
+    assert_eq!(render_x(tpl, "äöü"), "äöü");
+    assert_eq!(render_err(tpl, "a"),
+        "data error: 3:1: output \"a\" should be at least 2 characters long");
+    assert_eq!(render_err(tpl, "abcd"),
+        "data error: 3:1: output \"abcd\" \
+         should be at most 3 characters long");
+}
+
+#[test]
+fn categories() {
+    let tpl = "## validate name categories: L Nd Zs\n{{ x | name }}";
+    assert_eq!(render_x(tpl, "Jürgen 2"), "Jürgen 2");
+    assert_eq!(render_x(tpl, ""), "");
+    assert_eq!(render_err(tpl, "a\u{202e}b"),
+        "data error: 2:1: output \"a\\u{202e}b\" \
+         should only contain characters of categories L Nd Zs");
+}
+
+#[test]
+fn forbid() {
+    let tpl = "## validate default forbid: --\n\
+               ## validate default forbid: */\n\
+               {{ x }}";
+    assert_eq!(render_x(tpl, "a-b*c/"), "a-b*c/");
+    assert_eq!(render_err(tpl, "a--b"),
+        "data error: 3:1: output \"a--b\" should not contain \"--\"");
+    assert_eq!(render_err(tpl, "a*/"),
+        "data error: 3:1: output \"a*/\" should not contain \"*/\"");
+}
+
+#[test]
+fn escape_after_validation() {
+    let tpl = "## validate default: [a-z<>]*\n\
+               ## validate default max_length: 3\n\
+               ## validate default escape: builtin.html_entities\n\
+               {{ x }}";
+    assert_eq!(render_x(tpl, "<a>"), "&lt;a&gt;");
+    assert_eq!(render_err(tpl, "<ab>"),
+        "data error: 4:1: output \"<ab>\" \
+         should be at most 3 characters long");
+}
+
+#[test]
+fn regex_after_constraints() {
+    let tpl = "## validate default max_length: 3\n\
+               ## validate default: [a-z]*\n\
+               {{ x }}";
+    assert_eq!(render_x(tpl, "abc"), "abc");
+    assert!(render_err(tpl, "abcd").contains("at most 3"));
+    assert!(render_err(tpl, "ab1").contains("should match regex"));
+}
+
+#[test]
+fn bad_constraints() {
+    let parse = |t: &str| Parser::new().parse(t).unwrap_err().to_string();
+    assert_eq!(parse("## validate default max_length: x\n"),
+        "Validator constraint max_length \"x\" is invalid: \
+         invalid digit found in string");
+    assert_eq!(parse("## validate default maxlen: 1\n"),
+        "Validator constraint maxlen \"1\" is invalid: unknown constraint");
+    assert!(parse("## validate default categories: Nope\n")
+        .starts_with("Validator constraint categories \"Nope\" is invalid"));
+    assert_eq!(parse("## validate default escape: builtin.nope\n"),
+        "Filter \"builtin.nope\" is unknown");
+}
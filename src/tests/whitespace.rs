@@ -0,0 +1,149 @@
+use {Parser, Context};
+
+
+fn render(template: &str) -> String {
+    let tpl = Parser::new().parse(template).unwrap();
+    let x = "x";
+    let items = vec!["a", "b"];
+    let empty: Vec<&str> = Vec::new();
+    let mut vars: Context = Context::new();
+    vars.set("x", &x);
+    vars.set("items", &items);
+    vars.set("empty", &empty);
+    tpl.render(&vars).unwrap()
+}
+
+#[test]
+fn comment_strip() {
+    assert_eq!(render("a  {#- c -#}  b"), "ab");
+    assert_eq!(render("a  {#- c #}  b"), "a  b");
+    assert_eq!(render("a  {# c -#}  b"), "a  b");
+    assert_eq!(render("a  {#- c #}\n  b"), "a\n  b");
+}
+
+#[test]
+fn comment_space() {
+    assert_eq!(render("a \n {#+ c +#} \n b"), "a b");
+    assert_eq!(render("a  {#+ c -#}  b"), "a b");
+    assert_eq!(render("a  {#- c +#}  b"), "a b");
+    assert_eq!(render("{#+ c +#}  b"), "b");
+}
+
+#[test]
+fn comment_and_expression() {
+    assert_eq!(render("{{ x +}} {#- c #}  {{ x }}"), "x  x");
+    assert_eq!(render("{{ x }}  {#- c -#}  {{ x }}"), "xx");
+    assert_eq!(render("{{ x }}  {#+ c #}  {{- x }}"), "xx");
+}
+
+#[test]
+fn plain_comment() {
+    assert_eq!(render("a  {# - c + #}  b"), "a    b");
+}
+
+#[test]
+fn loop_strip() {
+    assert_eq!(render("<ul>\n\
+                       ##- for i in items\n\
+                       \x20 <li>{{ i }}</li>\n\
+                       ##- endfor\n\
+                       </ul>\n"),
+               "<ul><li>a</li><li>b</li></ul>\n");
+}
+
+#[test]
+fn loop_space() {
+    assert_eq!(render("items:\n\
+                       ##+ for i in items\n\
+                       \x20   {{ i }}\n\
+                       ##+ endfor\n\
+                       end\n"),
+               "items: a b end\n");
+}
+
+#[test]
+fn empty_loop() {
+    assert_eq!(render("items:\n\
+                       ##- for i in empty\n\
+                       \x20   {{ i }}\n\
+                       ##- endfor\n\
+                       \x20 end\n"),
+               "items:end\n");
+}
+
+#[test]
+fn skip_if() {
+    assert_eq!(render("##- for i in items\n\
+                       ##- skip if i == 'a'\n\
+                       \x20 {{ i }}\n\
+                       ## endfor\n"),
+               "b\n");
+}
+
+#[test]
+fn one_side() {
+    assert_eq!(render("<ul>\n\
+                       ## for i in items\n\
+                       \x20 <li>{{ i }}</li>\n\
+                       ##- endfor\n\
+                       </ul>\n"),
+               "<ul>\n  <li>a</li>  <li>b</li></ul>\n");
+}
+
+#[test]
+fn condition() {
+    let template = "[\n\
+        ##- if x == 'y'\n\
+        \x20 y\n\
+        ##- elif x == 'x'\n\
+        \x20 x\n\
+        ##- else\n\
+        \x20 z\n\
+        ##- endif\n\
+        ]\n";
+    assert_eq!(render(template), "[x]\n");
+    assert_eq!(render(&template.replace("'x'", "'z'")), "[z]\n");
+    assert_eq!(render(&template.replace("'y'", "'x'")), "[y]\n");
+}
+
+#[test]
+fn condition_without_else() {
+    assert_eq!(render("[\n##- if x == 'y'\n  y\n##- endif\n]"), "[]");
+    assert_eq!(render("[\n##+ if x\n  x\n##+ endif\n]"), "[ x ]");
+}
+
+#[test]
+fn let_statement() {
+    assert_eq!(render("a\n##- let y = x\n  {{ y }}"), "ax");
+    assert_eq!(render("a\n##+ let y = x\n  {{ y }}"), "a x");
+}
+
+#[test]
+fn oneline() {
+    assert_eq!(render("## syntax: oneline\n\
+                       a\n\
+                       ##- for i in items\n\
+                       \x20 {{ i }}\n\
+                       ## endfor\n\
+                       b {#- c #} c\n\
+                       d {#+ c +#} e\n"),
+               "aa b bc d e");
+}
+
+#[test]
+fn indent() {
+    assert_eq!(render("## syntax: indent\n\
+                       ##- for i in items\n\
+                       \x20   - {{ i }}\n\
+                       ## endfor\n"),
+               "- a\n- b\n");
+}
+
+#[test]
+fn format() {
+    assert_eq!(Parser::new().format(
+        "##-  if x\n  a {#- c +#} b\n##+  endif\n## for i in items\n\
+         ##-   skip  if i\n## endfor\n").unwrap(),
+        "##- if x\n  a {#- c +#} b\n##+ endif\n## for i in items\n\
+         \x20   ##- skip if i\n## endfor\n");
+}
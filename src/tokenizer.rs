@@ -1,9 +1,9 @@
 use std::fmt;
 use std::cmp::min;
 
-use regex::{Regex, RegexSet};
 use combine::{StreamOnce};
 use combine::primitives::{Error, Info};
+use parse_error::{ParseError, ParseErrorEnum};
 use {Pos};
 
 
@@ -11,6 +11,9 @@ use {Pos};
 pub enum Kind {
     Whitespace,
     Comment,
+    /// Comment with whitespace control markers `{#- comment +#}`, the value
+    /// is the text between the delimiters including the markers
+    ControlComment,
     Eof,
     Raw,
     // Top level tokens
@@ -19,7 +22,7 @@ pub enum Kind {
     ExprEnd,
     /// Joins two lines '##\n' without a newline
     LineJoiner,
-    /// Statement start '## something'
+    /// Statement start '## something' (or '##- something')
     StStart,
     /// A statement parsed by preparser
     EarlyStatement,
@@ -39,13 +42,23 @@ pub struct Token<'a> {
 }
 
 pub struct Tokenizer {
-    top_scan: Regex,
-    top_set: RegexSet,
-    top_list: Vec<(Regex, Kind)>,
-    expr_set: RegexSet,
-    expr_list: Vec<(Regex, Kind)>,
-    line_set: RegexSet,
-    line_list: Vec<(Regex, Kind)>,
+    _private: (),
+}
+
+/// Markers that delimit expressions, statements and comments in a template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delimiters {
+    /// `{{`
+    pub expr_start: String,
+    /// `}}`
+    pub expr_end: String,
+    /// `##`, also `###` is a line comment and `##` at the end of the line
+    /// is a line joiner
+    pub statement: String,
+    /// `{#`
+    pub comment_start: String,
+    /// `#}`
+    pub comment_end: String,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -57,8 +70,10 @@ enum State {
 
 #[derive(Clone)]
 pub struct TokenStream<'a> {
-    tok: &'a Tokenizer,
     buf: &'a str,
+    delimiters: &'a Delimiters,
+    /// Delimiters that are in effect after the `## delimiters` directive
+    directive: &'a Delimiters,
     indent: Option<usize>,
     parens: Vec<(char, Pos)>,
     position: Pos,
@@ -91,12 +106,23 @@ impl<'a> StreamOnce for TokenStream<'a> {
 
                 match tok.kind {
                     Comment => {
-                        if tok.value.starts_with('{') {
-                            let end = self.buf[self.off..].find("#}");
+                        let delim = self.delimiters;
+                        if tok.value.starts_with(&delim.comment_start[..]) {
+                            let end = self.buf[self.off..]
+                                .find(&delim.comment_end[..]);
                             if let Some(end) = end {
-                                let end = self.off+end+2;
+                                let inner = &self.buf[self.off..self.off+end];
+                                let end = self.off+end+delim.comment_end.len();
                                 let slice = &self.buf[self.off..end];
                                 self.update_pos(slice);
+                                if sign(inner) > 0 ||
+                                   inner.ends_with(&['+', '-'][..])
+                                {
+                                    return Ok(Token {
+                                        kind: ControlComment,
+                                        value: inner,
+                                    })
+                                }
                                 return Ok(Token {
                                     kind: Comment,
                                     value: &self.buf[start_off..end],
@@ -129,8 +155,15 @@ impl<'a> StreamOnce for TokenStream<'a> {
                                 Info::Borrowed("Statement must start at the \
                                     beginning of the line")));
                         }
-                        match tok.value.split_whitespace().nth(1) {
-                            Some("validate") | Some("syntax") | Some("filter")
+                        let value = &tok.value[whitespace(tok.value)..]
+                            [self.delimiters.statement.len()..];
+                        let name = &value[whitespace(value)..];
+                        if name == "delimiters" {
+                            self.delimiters = self.directive;
+                        }
+                        match name {
+                            "validate" | "syntax" | "filter" | "requires"
+                            | "delimiters" | "newline_at_eof"
                             => {
                                 let start_off = self.off;
                                 let end = self.buf[start_off..].find("\n");
@@ -228,41 +261,71 @@ impl<'a> StreamOnce for TokenStream<'a> {
 }
 
 impl<'a> TokenStream<'a> {
+    /// Byte offset of the next token in the source text
+    pub fn offset(&self) -> usize {
+        self.off
+    }
     fn match_top(&self) -> Token<'a> {
+        use self::Kind::*;
         let cur = &self.buf[self.off..];
-        let matching = self.tok.top_scan.find(cur);
-        match matching {
-            None => {
-                Token { kind: Kind::Raw, value: cur }
+        let token = |kind, len| Token { kind, value: &cur[..len] };
+        let delim = self.delimiters;
+        let prefix = &delim.statement[..];
+        if cur.starts_with(&delim.expr_start[..]) {
+            let rest = &cur[delim.expr_start.len()..];
+            return token(ExprStart, delim.expr_start.len() + sign(rest));
+        }
+        if cur.starts_with(&delim.comment_start[..]) {
+            return token(Comment, delim.comment_start.len());
+        }
+        if cur.starts_with('\n') {
+            return token(Newline, 1);
+        }
+        let space = whitespace(cur);
+        if cur[space..].starts_with(prefix) {
+            let rest = &cur[space+prefix.len()..];
+            if rest.starts_with('#') {
+                return token(Comment, space + prefix.len() + 1);
             }
-            Some(m) => {
-                if m.start() == 0 {
-                    let idx = self.tok.top_set.matches(cur)
-                        .into_iter().next().unwrap();
-                    Token {
-                        kind: self.tok.top_list[idx].1,
-                        value: &cur[..m.end()]
-                    }
-                } else {
-                    Token { kind: Kind::Raw, value: &cur[..m.start()] }
-                }
+            if rest.is_empty() {
+                return token(LineJoiner, space + prefix.len());
             }
+            if rest.starts_with('\n') {
+                return token(LineJoiner, space + prefix.len() + 1);
+            }
+        }
+        let blank = blank(cur);
+        if cur[blank..].starts_with(prefix) {
+            let start = blank + prefix.len();
+            let start = start + sign(&cur[start..]);
+            let name = start + whitespace(&cur[start..]);
+            return token(StStart, name + word(&cur[name..]));
+        }
+        if blank > 0 {
+            return token(Whitespace, blank);
         }
+        let bytes = cur.as_bytes();
+        let expr = delim.expr_start.as_bytes();
+        let comment = delim.comment_start.as_bytes();
+        let end = bytes.iter().enumerate().position(|(i, &c)| match c {
+            b'\n' | b' ' | b'\t' => true,
+            c if c == expr[0] && bytes[i..].starts_with(expr) => true,
+            c if c == comment[0] && bytes[i..].starts_with(comment) => true,
+            _ => false,
+        }).unwrap_or(cur.len());
+        token(Raw, end)
     }
     fn match_expr(&self) -> Result<Token<'a>, Error<Token<'a>, Token<'a>>> {
         let cur = &self.buf[self.off..];
-        match self.tok.expr_set.matches(cur).into_iter().next() {
+        let space = whitespace(cur);
+        if space > 0 {
+            return Ok(Token { kind: Kind::Whitespace, value: &cur[..space] });
+        }
+        match match_common(cur, self.delimiters) {
+            Some(tok) => Ok(tok),
             None => {
-                Err(Error::Unexpected(
-                    Info::Borrowed("end of file, expected `}}`")))
-            }
-            Some(idx) => {
-                let m = self.tok.expr_list[idx].0.find(cur).unwrap();
-                assert_eq!(m.start(), 0);
-                Ok(Token {
-                    kind: self.tok.expr_list[idx].1,
-                    value: &cur[..m.end()]
-                })
+                Err(Error::Unexpected(Info::Owned(format!(
+                    "end of file, expected `{}`", self.delimiters.expr_end))))
             }
         }
     }
@@ -270,7 +333,20 @@ impl<'a> TokenStream<'a> {
         -> Result<Token<'a>, Error<Token<'a>, Token<'a>>>
     {
         let cur = &self.buf[self.off..];
-        match self.tok.line_set.matches(cur).into_iter().next() {
+        if cur.starts_with('\n') {
+            let kind = if self.parens.is_empty() {
+                Kind::Newline
+            } else {
+                Kind::Whitespace
+            };
+            return Ok(Token { kind, value: &cur[..1] });
+        }
+        let blank = blank(cur);
+        if blank > 0 {
+            return Ok(Token { kind: Kind::Whitespace, value: &cur[..blank] });
+        }
+        match match_common(cur, self.delimiters) {
+            Some(tok) => Ok(tok),
             None => {
                 if cur.chars().all(|x| x.is_whitespace()) {
                     Ok(Token {
@@ -282,22 +358,6 @@ impl<'a> TokenStream<'a> {
                         cur[..min(8, cur.len())].into())))
                 }
             }
-            Some(idx) => {
-                let m = self.tok.line_list[idx].0.find(cur).unwrap();
-                assert_eq!(m.start(), 0);
-                let kind = self.tok.line_list[idx].1;
-                if kind == Kind::Newline && self.parens.len() > 0 {
-                    Ok(Token {
-                        kind: Kind::Whitespace,
-                        value: &cur[..m.end()]
-                    })
-                } else {
-                    Ok(Token {
-                        kind,
-                        value: &cur[..m.end()]
-                    })
-                }
-            }
         }
     }
     fn update_pos(&mut self, val: &str) {
@@ -320,78 +380,156 @@ impl<'a> TokenStream<'a> {
     }
 }
 
+/// Tokens that are the same in expressions and in statements
+fn match_common<'a>(cur: &'a str, delimiters: &Delimiters)
+    -> Option<Token<'a>>
+{
+    use self::Kind::*;
+    let token = |kind, len| Some(Token { kind, value: &cur[..len] });
+    let sign = sign(cur);
+    if cur[sign..].starts_with(&delimiters.expr_end[..]) {
+        return token(ExprEnd, sign + delimiters.expr_end.len());
+    }
+    if cur.starts_with('#') {
+        return token(Comment, 1);
+    }
+    let word = word(cur);
+    match &cur[..word] {
+        "and" | "or" | "not" => return token(Operator, word),
+        _ => {}
+    }
+    for op in &[">=", "<=", "==", "!=", "=", ".."] {
+        if cur.starts_with(op) {
+            return token(Operator, op.len());
+        }
+    }
+    match cur.as_bytes()[0] {
+        b'.' | b',' | b'|' | b':' | b'>' | b'<' | b'%' | b'*' | b'/' |
+        b'+' | b'-' => return token(Operator, 1),
+        b'{' | b'}' | b'(' | b')' | b'[' | b']' => return token(Paren, 1),
+        _ => {}
+    }
+    match &cur[..word] {
+        "for" | "in" | "endfor" | "skip" | "if" | "elif" | "else" |
+        "endif" | "let" | "syntax" | "validate"
+        => return token(Keyword, word),
+        _ => {}
+    }
+    let bytes = cur.as_bytes();
+    if is_ident_char(bytes[0]) && !is_digit(bytes[0]) {
+        let len = bytes.iter()
+            .position(|&c| !is_ident_char(c))
+            .unwrap_or(bytes.len());
+        if boundary(cur, len) {
+            return token(Ident, len);
+        }
+    }
+    if let Some(len) = number(cur) {
+        return token(Number, len);
+    }
+    match bytes[0] {
+        quote @ b'"' | quote @ b'\'' => {
+            // there are no escapes yet, string ends at the first quote
+            return cur[1..].find(quote as char)
+                .and_then(|end| token(String, end+2));
+        }
+        _ => {}
+    }
+    None
+}
+
+/// Length of the number at the start of `cur` (`0x1_000`, `1.5`, ...)
+fn number(cur: &str) -> Option<usize> {
+    let bytes = cur.as_bytes();
+    let digits = |start: usize| {
+        bytes[start..].iter()
+            .position(|&c| c != b'_' && !is_digit(c))
+            .map(|x| start + x)
+            .unwrap_or(bytes.len())
+    };
+    let prefixed = bytes.len() > 2 && bytes[0] == b'0' &&
+        matches!(bytes[1], b'o' | b'x' | b'b');
+    let starts: &[usize] = if prefixed { &[2, 0] } else { &[0] };
+    for &start in starts {
+        match bytes.get(start) {
+            Some(&c) if is_digit(c) => {}
+            _ => continue,
+        }
+        let int = digits(start);
+        let frac = match bytes.get(int+1) {
+            Some(&c) => c == b'_' || is_digit(c),
+            None => false,
+        };
+        if bytes.get(int) == Some(&b'.') && frac {
+            let end = digits(int+1);
+            if boundary(cur, end) {
+                return Some(end);
+            }
+        }
+        if boundary(cur, int) {
+            return Some(int);
+        }
+    }
+    None
+}
+
+fn is_word(c: char) -> bool {
+    c == '_' || c.is_alphanumeric()
+}
+
+fn is_digit(c: u8) -> bool {
+    c.wrapping_sub(b'0') < 10
+}
+
+/// Ascii letter, digit or underscore
+fn is_ident_char(c: u8) -> bool {
+    c == b'_' || (c as char).is_digit(36)
+}
+
+/// Checks that word ends at `off` (the character before is a word char)
+fn boundary(cur: &str, off: usize) -> bool {
+    match cur[off..].chars().next() {
+        Some(c) => !is_word(c),
+        None => true,
+    }
+}
+
+fn word(cur: &str) -> usize {
+    cur.find(|c| !is_word(c)).unwrap_or(cur.len())
+}
+
+/// Length of any whitespace including newlines
+fn whitespace(cur: &str) -> usize {
+    cur.find(|c: char| !c.is_whitespace()).unwrap_or(cur.len())
+}
+
+/// Length of spaces and tabs
+fn blank(cur: &str) -> usize {
+    cur.bytes().position(|c| c != b' ' && c != b'\t').unwrap_or(cur.len())
+}
+
+/// Length of optional whitespace control sign
+fn sign(cur: &str) -> usize {
+    if cur.starts_with('+') || cur.starts_with('-') { 1 } else { 0 }
+}
+
 impl Tokenizer {
     pub fn new() -> Tokenizer {
-        use self::Kind::*;
-        let top = &[
-            (r"\{\{[+-]?", ExprStart),
-            (r"\{#", Comment),
-            (r"\n", Newline),
-            (r"^\s*###", Comment),
-            (r"^\s*##(?:\n|$)", LineJoiner),
-            (r"^[ \t]*##\s*(\w*)", StStart),
-            (r"[ \t]+", Whitespace),
-        ];
-        let expr_common = &[
-            (r"^[+-]?\}\}", ExprEnd),
-            (r"^#", Comment),
-            (r"^(?:and\b|or\b|not\b|>=|<=|==|!=|=|\.\.|[.,|:><%*/+-])",
-             Operator),
-            (r"^[{}()\[\]]", Paren),
-            ("^(?:for|in|endfor\
-             |skip\
-             |if|elif|else|endif\
-             |let\
-             |syntax|validate\
-             )\\b", Keyword),
-            (r"^[a-zA-Z_][a-zA-Z0-9_]*\b", Ident),
-            (r"^(?:0[oxb])?[0-9][0-9_]*(\.[0-9_]+)?\b", Number),
-            (r#"^"(:?[^"]|\\")*"|^'(:?[^']|\\')*'"#, String),
-        ];
-        let expr = &[(r"^\s+", Whitespace)];
-        let line_expr = &[
-            (r"^\n", Newline),
-            (r"^[ \t]+", Whitespace),
-        ];
-        Tokenizer {
-            top_scan: Regex::new(
-                &format!("(?:{})",
-                    top.iter().map(|&(r, _)| format!("({})", r))
-                    .collect::<Vec<_>>()
-                    .join("|"))
-            ).unwrap(),
-            top_set: RegexSet::new(top.iter()
-                .map(|&(r, _)| format!("^{}", r))
-                .collect::<Vec<_>>()
-                .iter()
-                ).unwrap(),
-            top_list: top.iter()
-                .map(|&(r, k)| (Regex::new(r).unwrap(), k))
-                .collect(),
-            expr_set: RegexSet::new(
-                expr.iter()
-                .chain(expr_common.iter())
-                .map(|&(r, _)| r)).unwrap(),
-            expr_list: expr.iter()
-                .chain(expr_common.iter())
-                .map(|&(r, k)| (Regex::new(r).unwrap(), k))
-                .collect(),
-            line_set: RegexSet::new(
-                line_expr.iter()
-                .chain(expr_common.iter())
-                .map(|&(r, _)| r)
-                ).unwrap(),
-            line_list: line_expr.iter()
-                .chain(expr_common.iter())
-                .map(|&(r, k)| (Regex::new(r).unwrap(), k))
-                .collect(),
-        }
+        Tokenizer { _private: () }
     }
 
-    pub fn scan<'x: 'y, 'y>(&'x self, buf: &'y str) -> TokenStream<'y> {
+    /// Start tokenizing `buf`
+    ///
+    /// Tokenizing starts with `delimiters` and switches to the `directive`
+    /// ones when `## delimiters` directive is encountered.
+    pub fn scan<'a>(&self, buf: &'a str, delimiters: &'a Delimiters,
+        directive: &'a Delimiters)
+        -> TokenStream<'a>
+    {
         TokenStream {
-            tok: self,
             buf: buf,
+            delimiters,
+            directive,
             indent: Some(0),
             parens: Vec::new(),
             position: Pos { line: 1, column: 1 },
@@ -401,6 +539,87 @@ impl Tokenizer {
     }
 }
 
+impl Delimiters {
+    /// Parses value of the `## delimiters:` directive
+    ///
+    /// It's either `expr_start expr_end`, or these two followed by
+    /// a statement prefix and optionally by the comment markers. Markers that
+    /// are omitted are kept from `self`.
+    pub fn parse(&self, value: &str) -> Result<Delimiters, ParseError> {
+        let parts = value.split_whitespace().collect::<Vec<_>>();
+        let mut result = self.clone();
+        match parts.len() {
+            2 | 3 | 5 => {}
+            _ => {
+                return Err(ParseErrorEnum::InvalidDelimiters(value.into(),
+                    "expected 2, 3 or 5 markers separated by whitespace")
+                    .into());
+            }
+        }
+        result.expr_start = parts[0].to_string();
+        result.expr_end = parts[1].to_string();
+        if parts.len() > 2 {
+            result.statement = parts[2].to_string();
+        }
+        if parts.len() > 4 {
+            result.comment_start = parts[3].to_string();
+            result.comment_end = parts[4].to_string();
+        }
+        result.check()?;
+        Ok(result)
+    }
+    /// Checks that markers can be told apart from each other
+    pub fn check(&self) -> Result<(), ParseError> {
+        let error = |reason| -> Result<(), ParseError> {
+            Err(ParseErrorEnum::InvalidDelimiters(
+                format!("{} {} {} {} {}",
+                    self.expr_start, self.expr_end, self.statement,
+                    self.comment_start, self.comment_end),
+                reason).into())
+        };
+        let all = [&self.expr_start, &self.expr_end, &self.statement,
+                   &self.comment_start, &self.comment_end];
+        if all.iter().any(|m| m.is_empty()) {
+            return error("markers must not be empty");
+        }
+        if all.iter().any(|m| m.contains(char::is_whitespace)) {
+            return error("markers must not contain whitespace");
+        }
+        let signs = &['+', '-'][..];
+        if self.expr_start.ends_with(signs) ||
+           self.expr_end.starts_with(signs) ||
+           self.statement.ends_with(signs) ||
+           self.comment_start.ends_with(signs) ||
+           self.comment_end.starts_with(signs)
+        {
+            return error("whitespace control signs `+` and `-` \
+                can't be used at the inner side of markers");
+        }
+        let starts = [&self.expr_start, &self.statement, &self.comment_start];
+        for (i, a) in starts.iter().enumerate() {
+            for b in &starts[i+1..] {
+                if a.starts_with(&b[..]) || b.starts_with(&a[..]) {
+                    return error("expression, statement and comment \
+                        markers must start differently");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Delimiters {
+    fn default() -> Delimiters {
+        Delimiters {
+            expr_start: "{{".into(),
+            expr_end: "}}".into(),
+            statement: "##".into(),
+            comment_start: "{#".into(),
+            comment_end: "#}".into(),
+        }
+    }
+}
+
 impl<'a> fmt::Display for Token<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}[{:?}]", self.value, self.kind)
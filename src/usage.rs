@@ -0,0 +1,196 @@
+use grammar::{Body, Expr, ExprCode, AssignTarget, Template as Tpl};
+use {Pos};
+
+
+/// Variables and filters referenced by a template
+///
+/// Returned by `Template::usage()`. Every occurrence is listed separately
+/// in the order it's found in the template, so the same variable may be
+/// mentioned multiple times (at different positions).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    /// Free variables, i.e. ones which must be supplied in the `Context`
+    ///
+    /// Variables introduced by `## let` and `## for` are not listed here
+    pub variables: Vec<VarUsage>,
+    /// Validators and filters referenced as `{{ x | name }}`
+    pub filters: Vec<FilterUsage>,
+}
+
+/// A single reference to a context variable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarUsage {
+    /// Name of the variable in the context
+    pub name: String,
+    /// Chain of attributes accessed on the variable
+    ///
+    /// For `{{ entry.name }}` this is `["name"]`. Subscriptions by constant
+    /// string (`entry["name"]`) are treated the same as attributes.
+    pub attrs: Vec<String>,
+    /// Position of the expression in the template
+    pub position: Pos,
+}
+
+/// A single reference to a named validator or filter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterUsage {
+    /// Name of the validator or filter
+    pub name: String,
+    /// Position of the expression where the filter is used
+    pub position: Pos,
+}
+
+struct Scanner<'a> {
+    locals: Vec<&'a str>,
+    usage: Usage,
+}
+
+fn attr_path(expr: &Expr) -> Option<(&str, Vec<String>)> {
+    match expr.code {
+        ExprCode::Var(ref name) => Some((name, Vec::new())),
+        ExprCode::Attr(ref e, ref attr) => {
+            attr_path(e).map(|(name, mut attrs)| {
+                attrs.push(attr.clone());
+                (name, attrs)
+            })
+        }
+        ExprCode::Item(ref e, ref key) => match key.code {
+            ExprCode::Str(ref key) => {
+                attr_path(e).map(|(name, mut attrs)| {
+                    attrs.push(key.clone());
+                    (name, attrs)
+                })
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl<'a> Scanner<'a> {
+    fn visit_body(&mut self, body: &'a Body) {
+        use grammar::StatementCode::*;
+
+        let scope = self.locals.len();
+        for st in &body.statements {
+            match st.code {
+                OutputRaw(..) | Joiner | WhitespaceControl { .. } => {}
+                Output { ref expr, ref validator, .. } => {
+                    self.visit_expr(expr);
+                    if let Some(ref name) = *validator {
+                        self.usage.filters.push(FilterUsage {
+                            name: name.clone(),
+                            position: st.position.0,
+                        });
+                    }
+                }
+                Cond { ref conditional, ref otherwise, .. } => {
+                    for (cond, body) in conditional {
+                        self.visit_expr(cond);
+                        self.visit_body(body);
+                    }
+                    self.visit_body(otherwise);
+                }
+                Loop { ref target, ref iterator, ref filter, ref body, .. }
+                => {
+                    self.visit_expr(iterator);
+                    let inner = self.locals.len();
+                    self.bind(target);
+                    if let Some(ref filter) = *filter {
+                        self.visit_expr(filter);
+                    }
+                    self.visit_body(body);
+                    self.locals.truncate(inner);
+                }
+                Alias { ref target, ref value } => {
+                    self.visit_expr(value);
+                    self.bind(target);
+                }
+            }
+        }
+        self.locals.truncate(scope);
+    }
+    fn bind(&mut self, target: &'a AssignTarget) {
+        match *target {
+            AssignTarget::Var(ref name) => self.locals.push(name),
+            AssignTarget::Pair(ref a, ref b) => {
+                self.locals.push(a);
+                self.locals.push(b);
+            }
+        }
+    }
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        use grammar::ExprCode::*;
+
+        if let Some((name, attrs)) = attr_path(expr) {
+            if !self.locals.contains(&name) {
+                self.usage.variables.push(VarUsage {
+                    name: name.to_string(),
+                    attrs,
+                    position: expr.position.0,
+                });
+            }
+            return;
+        }
+        match expr.code {
+            Str(..) | Int(..) | Float(..) | Var(..) => {}
+            Attr(ref e, _) | Not(ref e) => self.visit_expr(e),
+            Item(ref a, ref b) | Filter(ref a, ref b) |
+            And(ref a, ref b) | Or(ref a, ref b) |
+            Add(ref a, ref b) | Sub(ref a, ref b) |
+            Mul(ref a, ref b) | Div(ref a, ref b) | Mod(ref a, ref b) => {
+                self.visit_expr(a);
+                self.visit_expr(b);
+            }
+            Comparison(ref first, ref rest) => {
+                self.visit_expr(first);
+                for (_, e) in rest {
+                    self.visit_expr(e);
+                }
+            }
+            List(ref items) => {
+                for e in items {
+                    self.visit_expr(e);
+                }
+            }
+            Dict(ref pairs) => {
+                for (k, v) in pairs {
+                    self.visit_expr(k);
+                    self.visit_expr(v);
+                }
+            }
+            Range(ref a, ref b) => {
+                if let Some(ref a) = *a {
+                    self.visit_expr(a);
+                }
+                if let Some(ref b) = *b {
+                    self.visit_expr(b);
+                }
+            }
+        }
+    }
+}
+
+pub fn scan(tpl: &Tpl) -> Usage {
+    let mut scanner = Scanner {
+        locals: Vec::new(),
+        usage: Usage {
+            variables: Vec::new(),
+            filters: Vec::new(),
+        },
+    };
+    scanner.visit_body(&tpl.body);
+    scanner.usage
+}
+
+impl Usage {
+    /// Returns names of the free variables, deduplicated and sorted
+    pub fn variable_names(&self) -> Vec<&str> {
+        let mut names = self.variables.iter()
+            .map(|v| &v.name[..])
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
@@ -1,20 +1,102 @@
 use regex::Regex;
+use std::fmt;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use escape;
 use parse_error::{ParseError, ParseErrorEnum};
+use render_error::DataError;
 
+/// One of the escapes available in `## filter` statement
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BuiltinEscape {
+    /// `builtin.html_entities`
     HtmlEntities,
+    /// `builtin.html_auto`, replaced by an escape suitable for the context
+    /// when template is parsed
+    HtmlAuto,
+    /// `builtin.quoted_shell_argument`
     QuotedShellArgument,
+    /// `builtin.shell_word`
+    ShellWord,
+    /// `builtin.shell_double_quoted`
+    ShellDoubleQuoted,
+    /// `builtin.ansi_c_shell_argument`
+    AnsiCShellArgument,
+    /// `builtin.url_path_segment`
+    UrlPathSegment,
+    /// `builtin.url_query_component`
+    UrlQueryComponent,
+    /// `builtin.url_form`
+    UrlForm,
+    /// `builtin.url`
+    Url,
+    /// `builtin.safe_url`
+    SafeUrl,
+    /// `builtin.css`
+    Css,
+    /// `builtin.yaml_string`
+    YamlString,
+    /// `builtin.toml_string`
+    TomlString,
+    /// `builtin.nginx_string`
+    NginxString,
+    /// `builtin.systemd_string`
+    SystemdString,
+    /// `builtin.csv_field`
+    CsvField,
+    /// `builtin.indent`
+    Indent,
+    /// `builtin.json_string`
+    JsonString,
+    /// `builtin.json`
+    Json,
 }
 
+/// A validator or an escape applied to the output expression
 #[derive(Debug, Clone)]
 pub enum Filter {
+    /// Output as is
     NoFilter,
+    /// Check that output matches regular expression
     Validate(Regex),
-    Escape(BuiltinEscape),
+    /// Escape output, escapes are applied left to right
+    Escape(Vec<BuiltinEscape>),
+    /// Check output against several constraints, then escape it
+    Constraints(Box<Constraints>),
+    /// Escape output by a function registered with `Options::escaper`
+    Custom(CustomEscape),
+}
+
+/// Signature of the function registered with `Options::escaper`
+pub type EscapeFn = Fn(&mut String, &str) + Send + Sync;
+
+/// Escape function defined in rust code
+#[derive(Clone)]
+pub struct CustomEscape {
+    /// Name the escaper is registered with
+    pub name: String,
+    /// Writes escaped second argument into the first one
+    pub func: Arc<EscapeFn>,
+}
+
+/// Constraints of a validator combined from several `## validate` lines
+#[derive(Debug, Clone)]
+pub struct Constraints {
+    /// Output must match regular expression
+    pub regex: Option<Regex>,
+    /// Minimum number of characters
+    pub min_length: Option<usize>,
+    /// Maximum number of characters
+    pub max_length: Option<usize>,
+    /// Allowed unicode categories (or scripts), like `L` or `Nd`
+    pub categories: Vec<String>,
+    /// Compiled `categories`, i.e. `^[\p{L}\p{Nd}]*$`
+    charset: Option<Regex>,
+    /// Substrings that must not appear in the output
+    pub forbid: Vec<String>,
+    /// Escapes applied to the output after validation
+    pub escapes: Vec<BuiltinEscape>,
 }
 
 impl PartialEq for Filter {
@@ -24,31 +106,241 @@ impl PartialEq for Filter {
             (&NoFilter, &NoFilter) => true,
             (&Validate(ref a), &Validate(ref b)) => a.as_str() == b.as_str(),
             (&Escape(ref a), &Escape(ref b)) => a == b,
+            (&Constraints(ref a), &Constraints(ref b)) => a == b,
+            (&Custom(ref a), &Custom(ref b)) => {
+                a.name == b.name && Arc::ptr_eq(&a.func, &b.func)
+            }
             (&NoFilter, _) => false,
             (&Validate(..), _) => false,
             (&Escape(..), _) => false,
+            (&Constraints(..), _) => false,
+            (&Custom(..), _) => false,
         }
     }
 }
 
+impl fmt::Debug for CustomEscape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CustomEscape")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl PartialEq for Constraints {
+    fn eq(&self, other: &Constraints) -> bool {
+        self.regex.as_ref().map(|x| x.as_str()) ==
+            other.regex.as_ref().map(|x| x.as_str()) &&
+        self.min_length == other.min_length &&
+        self.max_length == other.max_length &&
+        self.categories == other.categories &&
+        self.forbid == other.forbid &&
+        self.escapes == other.escapes
+    }
+}
+
+impl Constraints {
+    /// Constraints that accept any output
+    pub fn new() -> Constraints {
+        Constraints {
+            regex: None,
+            min_length: None,
+            max_length: None,
+            categories: Vec::new(),
+            charset: None,
+            forbid: Vec::new(),
+            escapes: Vec::new(),
+        }
+    }
+    /// Set allowed categories from a space-separated list
+    pub fn set_categories(&mut self, value: &str)
+        -> Result<(), ::regex::Error>
+    {
+        let categories = value.split_whitespace()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        self.charset = if categories.is_empty() {
+            None
+        } else {
+            let mut re = String::from("^[");
+            for cat in &categories {
+                re.push_str("\\p{");
+                re.push_str(cat);
+                re.push('}');
+            }
+            re.push_str("]*$");
+            Some(Regex::new(&re)?)
+        };
+        self.categories = categories;
+        Ok(())
+    }
+    /// Returns the description of the first failed constraint
+    pub fn check(&self, data: &str) -> Result<(), String> {
+        if let Some(ref re) = self.regex {
+            if !re.is_match(data) {
+                return Err(format!("should match regex {:?}", re.as_str()));
+            }
+        }
+        if self.min_length.is_some() || self.max_length.is_some() {
+            let len = data.chars().count();
+            if let Some(min) = self.min_length {
+                if len < min {
+                    return Err(format!(
+                        "should be at least {} characters long", min));
+                }
+            }
+            if let Some(max) = self.max_length {
+                if len > max {
+                    return Err(format!(
+                        "should be at most {} characters long", max));
+                }
+            }
+        }
+        if let Some(ref re) = self.charset {
+            if !re.is_match(data) {
+                return Err(format!(
+                    "should only contain characters of categories {}",
+                    self.categories.join(" ")));
+            }
+        }
+        for sub in &self.forbid {
+            if data.contains(&sub[..]) {
+                return Err(format!("should not contain {:?}", sub));
+            }
+        }
+        Ok(())
+    }
+}
+
 impl FromStr for BuiltinEscape {
     type Err = ParseError;
     fn from_str(val: &str) -> Result<Self, ParseError> {
         use self::BuiltinEscape::*;
         match val {
             "builtin.html_entities" => Ok(HtmlEntities),
+            "builtin.html_auto" => Ok(HtmlAuto),
             "builtin.quoted_shell_argument" => Ok(QuotedShellArgument),
+            "builtin.shell_word" => Ok(ShellWord),
+            "builtin.shell_double_quoted" => Ok(ShellDoubleQuoted),
+            "builtin.ansi_c_shell_argument" => Ok(AnsiCShellArgument),
+            "builtin.url_path_segment" => Ok(UrlPathSegment),
+            "builtin.url_query_component" => Ok(UrlQueryComponent),
+            "builtin.url_form" => Ok(UrlForm),
+            "builtin.url" => Ok(Url),
+            "builtin.safe_url" => Ok(SafeUrl),
+            "builtin.css" => Ok(Css),
+            "builtin.yaml_string" => Ok(YamlString),
+            "builtin.toml_string" => Ok(TomlString),
+            "builtin.nginx_string" => Ok(NginxString),
+            "builtin.systemd_string" => Ok(SystemdString),
+            "builtin.csv_field" => Ok(CsvField),
+            "builtin.indent" => Ok(Indent),
+            "builtin.json_string" => Ok(JsonString),
+            "builtin.json" => Ok(Json),
             _ => Err(ParseErrorEnum::BadFilter(val.to_string()).into()),
         }
     }
 }
 
 impl BuiltinEscape {
-    pub fn escape(&self, dest: &mut String, src: &str) {
+    /// Name of the escape as written in the template
+    pub fn name(&self) -> &'static str {
         use self::BuiltinEscape::*;
         match *self {
-            HtmlEntities => escape::html_entities(dest, src),
+            HtmlEntities => "builtin.html_entities",
+            HtmlAuto => "builtin.html_auto",
+            QuotedShellArgument => "builtin.quoted_shell_argument",
+            ShellWord => "builtin.shell_word",
+            ShellDoubleQuoted => "builtin.shell_double_quoted",
+            AnsiCShellArgument => "builtin.ansi_c_shell_argument",
+            UrlPathSegment => "builtin.url_path_segment",
+            UrlQueryComponent => "builtin.url_query_component",
+            UrlForm => "builtin.url_form",
+            Url => "builtin.url",
+            SafeUrl => "builtin.safe_url",
+            Css => "builtin.css",
+            YamlString => "builtin.yaml_string",
+            TomlString => "builtin.toml_string",
+            NginxString => "builtin.nginx_string",
+            SystemdString => "builtin.systemd_string",
+            CsvField => "builtin.csv_field",
+            Indent => "builtin.indent",
+            JsonString => "builtin.json_string",
+            Json => "builtin.json",
+        }
+    }
+    /// Write escaped `src` into `dest`
+    ///
+    /// Fails if the value can't be represented safely.
+    pub fn escape(&self, dest: &mut String, src: &str)
+        -> Result<(), DataError>
+    {
+        use self::BuiltinEscape::*;
+        match *self {
+            // only used when `html_auto` is in a chain of escapes
+            HtmlEntities | HtmlAuto => escape::html_entities(dest, src),
             QuotedShellArgument => escape::quoted_shell_argument(dest, src),
+            ShellWord => escape::shell_word(dest, src),
+            ShellDoubleQuoted => escape::shell_double_quoted(dest, src),
+            AnsiCShellArgument => escape::ansi_c_shell_argument(dest, src),
+            UrlPathSegment => escape::url_path_segment(dest, src),
+            UrlQueryComponent => escape::url_query_component(dest, src),
+            UrlForm => escape::url_form(dest, src),
+            Url => escape::url(dest, src),
+            SafeUrl => escape::safe_url(dest, src),
+            Css => escape::css(dest, src),
+            YamlString => escape::yaml_string(dest, src),
+            TomlString => escape::toml_string(dest, src),
+            NginxString => return escape::nginx_string(dest, src),
+            SystemdString => escape::systemd_string(dest, src),
+            CsvField => escape::csv_field(dest, src),
+            Indent => escape::indent(dest, src),
+            // `Json` serializes variable itself, when escaping a string
+            // it's the same as `JsonString`
+            JsonString | Json => escape::json_string(dest, src),
+        }
+        Ok(())
+    }
+}
+
+/// Parse a chain of escapes, like `builtin.url_form | builtin.html_entities`
+///
+/// `builtin.indent` is only allowed at the end of the chain, as it needs the
+/// output written so far to find out the column.
+pub fn parse_escapes(val: &str) -> Result<Vec<BuiltinEscape>, ParseError> {
+    let escapes = val.split('|').map(|x| x.trim().parse())
+        .collect::<Result<Vec<_>, _>>()?;
+    let init = escapes.split_last().map(|(_, init)| init).unwrap_or(&[]);
+    if init.contains(&BuiltinEscape::Indent) {
+        return Err(ParseErrorEnum::BadEscapeChain(val.trim().to_string(),
+            "builtin.indent must be the last in the chain").into());
+    }
+    Ok(escapes)
+}
+
+/// Name of the chain of escapes as written in the template
+pub fn escapes_name(escapes: &[BuiltinEscape]) -> String {
+    escapes.iter().map(|x| x.name()).collect::<Vec<_>>().join(" | ")
+}
+
+/// Write `src` escaped by every escape in chain into `dest`
+///
+/// Only the last escape writes directly into `dest`, this is why
+/// `parse_escapes` rejects `builtin.indent` anywhere else.
+pub fn escape_all(escapes: &[BuiltinEscape], dest: &mut String, src: &str)
+    -> Result<(), DataError>
+{
+    match escapes.split_last() {
+        None => dest.push_str(src),
+        Some((last, init)) => {
+            let mut buf = src.to_string();
+            for esc in init {
+                let mut next = String::with_capacity(buf.len());
+                esc.escape(&mut next, &buf)?;
+                buf = next;
+            }
+            last.escape(dest, &buf)?;
         }
     }
+    Ok(())
 }
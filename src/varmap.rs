@@ -20,6 +20,7 @@ pub enum Parent<'a, 'render: 'a> {
     Sub(&'a SubContext<'a, 'render>),
 }
 
+/// A scope of local variables derived from the context
 pub struct SubContext<'a, 'render: 'a> {
     parent: Parent<'a, 'render>,
     local: HashMap<ErasedRcRef<str>, VarRef<'render>>,
@@ -77,16 +78,20 @@ pub fn get<'x, 'render>(ctx: &SubContext<'x, 'render>, name: &str)
     }
     match ctx.parent {
         Parent::Sub(ref parent) => get(parent, name),
-        Parent::Root(ref root) => {
-            if let Some(value) = root.vars.get(name) {
-                Ok(OwningRef::new(root.nothing.clone())
-                    // This looks like safe because we limit the use of
-                    // the owning ref to the template render time anyway
-                    // and value has lifetime of 'render
-                    .map(|_| unsafe { transmute(*value) }))
-            } else {
-                Err(DataError::VariableNotFound(name.to_string()))
-            }
-        }
+        Parent::Root(ref root) => get_root(root, name),
+    }
+}
+
+pub fn get_root<'render>(root: &Context<'render>, name: &str)
+    -> Result<VarRef<'render>, DataError>
+{
+    if let Some(value) = root.vars.get(name) {
+        Ok(OwningRef::new(root.nothing.clone())
+            // This looks like safe because we limit the use of
+            // the owning ref to the template render time anyway
+            // and value has lifetime of 'render
+            .map(|_| unsafe { transmute(*value) }))
+    } else {
+        Err(DataError::VariableNotFound(name.to_string()))
     }
 }
@@ -6,6 +6,7 @@ use render_error::DataError;
 use owning_ref::{OwningRef, Erased};
 use {Var, Output, Number, Comparable};
 
+/// A reference to variable that is valid during template rendering
 pub type VarRef<'render> = OwningRef<Rc<Erased+'render>,
                                      Variable<'render>+'render>;
 
@@ -0,0 +1,337 @@
+//! Compile-time checked templates for trimmer
+//!
+//! Templates are parsed when the crate is compiled, so syntax errors are
+//! reported by the compiler instead of at startup of the program:
+//!
+//! ```rust
+//! #[macro_use] extern crate trimmer_macros;
+//! extern crate trimmer;
+//!
+//! # fn main() {
+//! let tpl: &trimmer::Template = template!("hello {{ name }}!");
+//! let name = "world";
+//! let mut ctx = trimmer::Context::new();
+//! ctx.set("name", &name);
+//! assert_eq!(tpl.render(&ctx).unwrap(), "hello world!");
+//! # }
+//! ```
+//!
+//! Both macros expand to a `&'static Template`. The template is parsed
+//! again on first use in each thread, which can't fail as it was already
+//! checked.
+//!
+//! Invalid template is a compile error:
+//!
+//! ```rust,compile_fail
+//! #[macro_use] extern crate trimmer_macros;
+//! extern crate trimmer;
+//!
+//! # fn main() {
+//! let tpl = template!("## if x\n");
+//! # }
+//! ```
+//!
+//! The error message contains line and column in the template. With the
+//! `nightly` feature enabled (requires nightly compiler), the error also
+//! points to the exact place inside of the string literal.
+#![cfg_attr(feature="nightly", feature(proc_macro_span))]
+extern crate proc_macro;
+extern crate trimmer;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::iter::Peekable;
+use std::path::Path;
+
+use proc_macro::{TokenStream, TokenTree, Span, Literal, Ident, Punct};
+use proc_macro::{Group, Delimiter, Spacing};
+use trimmer::{Parser, Pos};
+
+
+/// Parse the template in a string literal at compile time
+///
+/// Expands to a `&'static trimmer::Template`.
+#[proc_macro]
+pub fn template(input: TokenStream) -> TokenStream {
+    let lit = match string_literal(input) {
+        Ok(lit) => lit,
+        Err(e) => return e,
+    };
+    let literal = lit.to_string();
+    let (source, offsets) = match unescape(&literal) {
+        Some(pair) => pair,
+        None => return error(lit.span(), "expected a string literal"),
+    };
+    if let Err(e) = Parser::new().parse(&source) {
+        // Pointing inside of the literal requires nightly, otherwise error
+        // points to the literal and the message has line and column
+        let span = e.position()
+            .and_then(|pos| offset(&source, pos))
+            .and_then(|off| {
+                let start = offsets[off];
+                let end = offsets.get(off+1).cloned().unwrap_or(start+1);
+                subspan(&lit, start, end.max(start+1))
+            })
+            .unwrap_or_else(|| lit.span());
+        return error(span, &format!("invalid template: {}", e));
+    }
+    expand(&literal)
+}
+
+/// Parse the template from a file at compile time
+///
+/// Path is relative to the directory containing `Cargo.toml` of the crate
+/// (i.e. `CARGO_MANIFEST_DIR`). Expands to a `&'static trimmer::Template`.
+/// The file is also included using `include_str!` so crate is rebuilt when
+/// the template changes.
+#[proc_macro]
+pub fn include_template(input: TokenStream) -> TokenStream {
+    let lit = match string_literal(input) {
+        Ok(lit) => lit,
+        Err(e) => return e,
+    };
+    let span = lit.span();
+    let relative = match unescape(&lit.to_string()) {
+        Some((path, _)) => path,
+        None => return error(span, "expected a string literal"),
+    };
+    let dir = env::var("CARGO_MANIFEST_DIR").unwrap_or(".".to_string());
+    let path = Path::new(&dir).join(&relative);
+    let mut source = String::new();
+    if let Err(e) = File::open(&path)
+        .and_then(|mut f| f.read_to_string(&mut source))
+    {
+        return error(span,
+            &format!("can't read template {:?}: {}", path, e));
+    }
+    if let Err(e) = Parser::new().parse(&source) {
+        return error(span,
+            &format!("invalid template {}: {}", relative, e));
+    }
+    match path.to_str() {
+        Some(path) => expand(&format!("include_str!({:?})", path)),
+        None => error(span, "template path must be valid utf-8"),
+    }
+}
+
+fn expand(source: &str) -> TokenStream {
+    // There is no lazy static in std of older compilers, so template is
+    // parsed once per thread, like the generated code does, and is leaked
+    // to get a `'static` reference
+    format!("{{
+        thread_local! {{
+            static TEMPLATE: &'static ::trimmer::Template =
+                Box::leak(Box::new(::trimmer::Parser::new().parse({})
+                    .expect(\"template is checked at compile time\")));
+        }}
+        TEMPLATE.with(|tpl| *tpl)
+    }}", source).parse().expect("valid expansion")
+}
+
+/// Returns the single literal passed to the macro
+fn string_literal(input: TokenStream) -> Result<Literal, TokenStream> {
+    let mut tokens = input.into_iter();
+    match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Literal(lit)), None) => Ok(lit),
+        // literals passed through `macro_rules!` are wrapped into a group
+        (Some(TokenTree::Group(ref g)), None)
+            if g.delimiter() == Delimiter::None
+        => {
+            string_literal(g.stream())
+        }
+        (Some(tok), _) => {
+            Err(error(tok.span(), "expected a single string literal"))
+        }
+        (None, _) => {
+            Err(error(Span::call_site(), "expected a string literal"))
+        }
+    }
+}
+
+/// Makes `compile_error!("message")` pointing to the `span`
+fn error(span: Span, message: &str) -> TokenStream {
+    let mut msg = Literal::string(message);
+    msg.set_span(span);
+    let mut punct = Punct::new('!', Spacing::Alone);
+    punct.set_span(span);
+    let mut group = Group::new(Delimiter::Parenthesis,
+        TokenTree::from(msg).into());
+    group.set_span(span);
+    vec![
+        TokenTree::from(Ident::new("compile_error", span)),
+        TokenTree::from(punct),
+        TokenTree::from(group),
+    ].into_iter().collect()
+}
+
+#[cfg(feature="nightly")]
+fn subspan(lit: &Literal, start: usize, end: usize) -> Option<Span> {
+    lit.subspan(start..end)
+}
+
+#[cfg(not(feature="nightly"))]
+fn subspan(_lit: &Literal, _start: usize, _end: usize) -> Option<Span> {
+    None
+}
+
+/// Byte offset of the position in the template source
+fn offset(source: &str, pos: Pos) -> Option<usize> {
+    let mut line_start = 0;
+    for _ in 1..pos.line {
+        line_start += source[line_start..].find('\n')? + 1;
+    }
+    let line = &source[line_start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+    let column = line.char_indices().nth(pos.column.saturating_sub(1))
+        .map(|(off, _)| off)
+        .unwrap_or(line.len());
+    Some(line_start + column)
+}
+
+/// Returns value of the string literal given its source code
+///
+/// The second element maps each byte of the value (and the end of it) to
+/// the offset of the corresponding character in the literal. Returns `None`
+/// if that is not a string literal (e.g. a number or a byte string).
+fn unescape(literal: &str) -> Option<(String, Vec<usize>)> {
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let inner = &raw[hashes..raw.len()-hashes];
+        if inner.len() < 2 || !inner.starts_with('"') ||
+           !inner.ends_with('"')
+        {
+            return None;
+        }
+        let start = 1 + hashes + 1;
+        let value = inner[1..inner.len()-1].to_string();
+        let offsets = (start..start + value.len() + 1).collect();
+        return Some((value, offsets));
+    }
+    if literal.len() < 2 || !literal.starts_with('"') ||
+       !literal.ends_with('"')
+    {
+        return None;
+    }
+    let mut result = String::with_capacity(literal.len());
+    let mut offsets = Vec::with_capacity(literal.len());
+    let mut chars = literal[1..literal.len()-1].char_indices().peekable();
+    while let Some((off, c)) = chars.next() {
+        // offset of the character in the literal including the quote
+        let off = off + 1;
+        let len = result.len();
+        unescape_char(&mut result, &mut chars, c)?;
+        offsets.resize(offsets.len() + result.len() - len, off);
+    }
+    offsets.push(literal.len() - 1);
+    Some((result, offsets))
+}
+
+fn unescape_char<I>(result: &mut String, chars: &mut Peekable<I>, c: char)
+    -> Option<()>
+    where I: Iterator<Item=(usize, char)>,
+{
+    if c != '\\' {
+        result.push(c);
+        return Some(());
+    }
+    match chars.next()?.1 {
+        'n' => result.push('\n'),
+        'r' => result.push('\r'),
+        't' => result.push('\t'),
+        '0' => result.push('\0'),
+        '\\' => result.push('\\'),
+        '\'' => result.push('\''),
+        '"' => result.push('"'),
+        'x' => {
+            let hex = chars.by_ref().take(2)
+                .map(|(_, c)| c).collect::<String>();
+            let code = u8::from_str_radix(&hex, 16).ok()?;
+            result.push(code as char);
+        }
+        'u' => {
+            if chars.next()?.1 != '{' {
+                return None;
+            }
+            let hex = chars.by_ref()
+                .map(|(_, c)| c)
+                .take_while(|&c| c != '}')
+                .filter(|&c| c != '_')
+                .collect::<String>();
+            let code = u32::from_str_radix(&hex, 16).ok()?;
+            result.push(std::char::from_u32(code)?);
+        }
+        '\n' => {
+            // line continuation skips leading whitespace of next line
+            while let Some(&(_, c)) = chars.peek() {
+                if !c.is_whitespace() {
+                    break;
+                }
+                chars.next();
+            }
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod test {
+    use trimmer::Pos;
+    use super::{unescape, offset};
+
+    fn value(literal: &str) -> Option<String> {
+        unescape(literal).map(|(value, _)| value)
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!(value(r#""hello""#).unwrap(), "hello");
+        assert_eq!(value(r#""""#).unwrap(), "");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(value(r#""a\n\t\"\\\x41\u{1F600}\0""#).unwrap(),
+                   "a\n\t\"\\A\u{1F600}\0");
+        assert_eq!(value("\"a\\\n     b\"").unwrap(), "ab");
+    }
+
+    #[test]
+    fn raw() {
+        assert_eq!(value(r#"r"a\n""#).unwrap(), "a\\n");
+        assert_eq!(value(r###"r#"say "hi""#"###).unwrap(), "say \"hi\"");
+    }
+
+    #[test]
+    fn not_a_string() {
+        assert!(value("12").is_none());
+        assert!(value("b\"x\"").is_none());
+        assert!(value("'x'").is_none());
+    }
+
+    #[test]
+    fn offsets() {
+        let literal = "\"a\\nb\\\n    c\u{e9}d\"";
+        let (value, offsets) = unescape(literal).unwrap();
+        assert_eq!(value, "a\nbc\u{e9}d");
+        let chars = value.char_indices()
+            .map(|(i, _)| &literal[offsets[i]..])
+            .map(|x| x.chars().next().unwrap())
+            .collect::<String>();
+        assert_eq!(chars, "a\\bc\u{e9}d");
+        assert_eq!(offsets[value.len()], literal.len() - 1);
+        let (_, offsets) = unescape("r#\"ab\"#").unwrap();
+        assert_eq!(offsets, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn position() {
+        let source = "ab\n\u{e9}cd\n";
+        assert_eq!(offset(source, Pos { line: 1, column: 2 }), Some(1));
+        assert_eq!(offset(source, Pos { line: 2, column: 2 }), Some(5));
+        assert_eq!(offset(source, Pos { line: 2, column: 9 }), Some(7));
+        assert_eq!(offset(source, Pos { line: 3, column: 1 }), Some(8));
+        assert_eq!(offset(source, Pos { line: 4, column: 1 }), None);
+    }
+}
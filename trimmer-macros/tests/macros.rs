@@ -0,0 +1,43 @@
+#[macro_use] extern crate trimmer_macros;
+extern crate trimmer;
+
+use std::ptr;
+
+use trimmer::{Context, Template};
+
+
+macro_rules! wrapped {
+    ($source:expr) => { template!($source) }
+}
+
+#[test]
+fn literal() {
+    let tpl: &Template = template!("a{{ x }}b");
+    let x = "+";
+    let mut ctx = Context::new();
+    ctx.set("x", &x);
+    assert_eq!(tpl.render(&ctx).unwrap(), "a+b");
+}
+
+#[test]
+fn same_template() {
+    fn get() -> &'static Template {
+        template!(r#"{{ "x" }}"#)
+    }
+    assert!(ptr::eq(get(), get()));
+}
+
+#[test]
+fn through_macro_rules() {
+    let tpl = wrapped!("## syntax: oneline\n  a\n  b\n");
+    assert_eq!(tpl.render(&Context::new()).unwrap(), "a b");
+}
+
+#[test]
+fn include() {
+    let tpl = include_template!("tests/templates/list.txt");
+    let items = vec!["a", "b"];
+    let mut ctx = Context::new();
+    ctx.set("items", &items);
+    assert_eq!(tpl.render(&ctx).unwrap(), "items:\n- a\n- b\n");
+}